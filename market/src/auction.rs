@@ -1,18 +1,38 @@
 use std::collections::HashMap;
 
 use crate::bid::{Bid, Origins};
-use crate::fee::calculate_price_with_fees;
+use crate::custody::{custody_payout, custody_status_from_promise, return_custody_token};
+use crate::fee::{
+    calculate_price_with_fees, fees_with_protocol, market_fee_payouts, payout_balance, Fees,
+    PayoutMode,
+};
+use crate::inner::{account_index_insert, account_index_remove};
 use crate::market_core::AuctionArgs;
 use crate::sale::{
-    ext_contract, ext_self, Payout, GAS_FOR_FT_TRANSFER, GAS_FOR_NFT_TRANSFER, GAS_FOR_ROYALTIES,
-    NO_DEPOSIT,
+    ext_contract, ext_self, listing_status_from_promise, nft_transfer_with_payout, sale_key,
+    seller_payout, with_market_fees, Sale, GAS_FOR_NFT_TOKEN, GAS_FOR_RESOLVE_VERIFY,
+    GAS_FOR_ROYALTIES, GAS_FOR_VERIFIED_TRANSFER, NO_DEPOSIT,
 };
+use crate::settlement::payout_transfers;
 use crate::*;
-use near_sdk::{near_bindgen, promise_result_as_success};
+use near_contract_standards::non_fungible_token::hash_account_id;
+use near_sdk::serde_json::json;
+use near_sdk::{near_bindgen, promise_result_as_success, PromiseOrValue};
 // should check calculation
 pub const EXTENSION_DURATION: u64 = 15 * 60 * NANOS_PER_SEC; // 15 minutes
 pub const MAX_DURATION: u64 = 1000 * 60 * 60 * 24 * NANOS_PER_SEC; // 1000 days
 
+// What happens to the token when the auction is closed without a bid
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub enum AuctionFallback {
+    // A new auction with the same duration and a lower start price, it has no fallback
+    Relist { start_price: U128 },
+    // A sale at the fixed price in the token of the auction
+    Sale { price: U128 },
+}
+
 #[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
 #[serde(crate = "near_sdk::serde")]
 pub struct Auction {
@@ -31,6 +51,13 @@ pub struct Auction {
     pub end: u64,
 
     pub origins: Origins,
+
+    pub allowed_buyers: Option<Vec<AccountId>>,
+
+    // The token was sent with `nft_transfer_call` and is held by the market
+    pub custody: bool,
+
+    pub fallback: Option<AuctionFallback>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -48,6 +75,19 @@ pub struct AuctionJson {
 
     pub start: U64,
     pub end: U64,
+
+    pub allowed_buyers: Option<Vec<AccountId>>,
+    pub is_private: bool,
+    pub custody: bool,
+    pub fallback: Option<AuctionFallback>,
+}
+
+impl Auction {
+    pub fn is_buyer_allowed(&self, buyer_id: &AccountId) -> bool {
+        self.allowed_buyers
+            .as_ref()
+            .map_or(true, |allowed| allowed.contains(buyer_id))
+    }
 }
 
 #[near_bindgen]
@@ -61,6 +101,7 @@ impl Market {
         owner_id: AccountId,
         approval_id: u64,
         nft_contract_id: AccountId,
+        custody: bool,
     ) -> (u128, AuctionJson) {
         require!(
             args.duration.0 >= EXTENSION_DURATION && args.duration.0 <= MAX_DURATION,
@@ -76,7 +117,13 @@ impl Market {
             .unwrap_or_else(env::block_timestamp);
         require!(start >= env::block_timestamp(), "incorrect start time");
         let end = start + args.duration.0;
-        let auction_id = self.market.next_auction_id;
+        self.assert_valid_origins(args.origins.as_ref());
+        if let Some(AuctionFallback::Relist { start_price }) = args.fallback.as_ref() {
+            require!(
+                start_price.0 < args.start_price.0,
+                "The relist start price should be lower than the start price"
+            );
+        }
         let origins = args.origins.unwrap_or_default();
         let auction = Auction {
             owner_id,
@@ -92,9 +139,11 @@ impl Market {
             start,
             end,
             origins,
+            allowed_buyers: args.allowed_buyers,
+            custody,
+            fallback: args.fallback,
         };
-        self.market.auctions.insert(&auction_id, &auction);
-        self.market.next_auction_id += 1;
+        let auction_id = self.internal_insert_auction(&auction);
 
         let auction_json = self.json_from_auction(auction);
 
@@ -116,6 +165,7 @@ impl Market {
             self.market.ft_token_ids.contains(&ft_token_id),
             "token not supported"
         );
+        self.assert_valid_origins(origins.as_ref());
         require!(
             self.check_auction_in_progress(auction_id),
             "Auction is not in progress"
@@ -129,7 +179,11 @@ impl Market {
             auction.owner_id != env::predecessor_account_id(),
             "Cannot bid on your own auction"
         );
-            let deposit = env::attached_deposit();
+        require!(
+            auction.is_buyer_allowed(&env::predecessor_account_id()),
+            "The auction is private, the buyer is not allowed"
+        );
+        let deposit = env::attached_deposit();
         let min_deposit =
             calculate_price_with_fees(self.get_minimal_next_bid(auction_id), origins.as_ref());
 
@@ -140,6 +194,11 @@ impl Market {
         );
         //Return previous bid
         if let Some(previous_bid) = auction.bid {
+            account_index_remove(
+                &mut self.market.auction_bids_by_owner_id,
+                &previous_bid.owner_id,
+                &auction_id.0,
+            );
             self.refund_bid(ft_token_id, previous_bid.owner_id, previous_bid.price);
         }
         // If the price is bigger than the buy_out_price, the auction end is set to the current time
@@ -160,6 +219,14 @@ impl Market {
         };
         // Extend the auction if the bid is added EXTENSION_DURATION (15 min) before the auction end
         // and the token is not bought out
+        account_index_insert(
+            &mut self.market.auction_bids_by_owner_id,
+            &bid.owner_id,
+            &auction_id.0,
+            StorageKey::AuctionBidsByOwnerIdInner {
+                account_id_hash: hash_account_id(&bid.owner_id),
+            },
+        );
         auction.bid = Some(bid);
         if auction.end - env::block_timestamp() < EXTENSION_DURATION && !bought_out {
             auction.end = env::block_timestamp() + EXTENSION_DURATION;
@@ -185,115 +252,289 @@ impl Market {
             auction.bid.is_none(),
             "Can't cancel the auction after the first bid is made"
         );
-        self.market.auctions.remove(&auction_id.into());
+        self.internal_remove_auction(auction_id.into());
+        if auction.custody {
+            return_custody_token(auction.nft_contract_id, auction.token_id, auction.owner_id);
+        }
     }
 
-    // Finishes the auction if it has reached its end
+    // Checks that the owner of the token hasn't changed and the market is still approved
+    // Can be called by anyone, the stale auction is removed and its bid is refunded
+    pub fn verify_auction(&mut self, auction_id: U128) -> Promise {
+        let auction = self
+            .market
+            .auctions
+            .get(&auction_id.into())
+            .unwrap_or_else(|| env::panic_str("Auction is not active"));
+        require!(!auction.custody, "The token is held by the market");
+        ext_contract::nft_token(
+            auction.token_id,
+            auction.nft_contract_id,
+            NO_DEPOSIT,
+            GAS_FOR_NFT_TOKEN,
+        )
+        .then(ext_self::resolve_verify_auction(
+            auction_id,
+            auction.owner_id,
+            auction.approval_id,
+            env::current_account_id(),
+            NO_DEPOSIT,
+            GAS_FOR_RESOLVE_VERIFY,
+        ))
+    }
+
+    // self callback
+    // Returns whether the auction is still valid
+    #[private]
+    pub fn resolve_verify_auction(
+        &mut self,
+        auction_id: U128,
+        owner_id: AccountId,
+        approval_id: u64,
+    ) -> bool {
+        if listing_status_from_promise(&owner_id, approval_id) != Some(false) {
+            return true;
+        }
+        let auction = match self.market.auctions.get(&auction_id.into()) {
+            Some(auction) if auction.owner_id == owner_id && auction.approval_id == approval_id => {
+                auction
+            }
+            // the auction could have been finished while the NFT contract was called
+            _ => return false,
+        };
+        self.internal_remove_auction(auction_id.into());
+        if let Some(bid) = auction.bid {
+            self.refund_bid(auction.ft_token_id, bid.owner_id, bid.price);
+        }
+        env::log_str(
+            &json!({
+                "type": "remove_stale_auction",
+                "params": {
+                    "auction_id": auction_id,
+                    "owner_id": auction.owner_id,
+                    "nft_contract_id": auction.nft_contract_id,
+                    "token_id": auction.token_id,
+                }
+            })
+            .to_string(),
+        );
+        false
+    }
+
+    // Closes the auction which has ended without a bid and applies its fallback
     // Can be called by anyone
-    pub fn finish_auction(&mut self, auction_id: U128) -> Promise {
+    pub fn close_auction(&mut self, auction_id: U128) {
         let auction = self
             .market
             .auctions
-            .remove(&auction_id.into())
+            .get(&auction_id.into())
+            .unwrap_or_else(|| env::panic_str("Auction is not active"));
+        require!(
+            env::block_timestamp() > auction.end,
+            "Auction can be closed only after the end time"
+        );
+        require!(
+            auction.bid.is_none(),
+            "The auction has a bid, use finish_auction"
+        );
+        self.internal_close_auction(auction_id.into());
+    }
+
+    // Finishes the auction if it has reached its end
+    // Can be called by anyone
+    pub fn finish_auction(&mut self, auction_id: U128) -> Promise {
+        let auction = self
+            .internal_remove_auction(auction_id.into())
             .unwrap_or_else(|| env::panic_str("Auction is not active"));
         require!(
             env::block_timestamp() > auction.end,
             "Auction can be finalized only after the end time"
         );
+        let final_bid = auction.bid.clone().unwrap_or_else(|| {
+            env::panic_str("Can finalize only if there is a bid, use close_auction")
+        });
+        let fees = fees_with_protocol(final_bid.origins, &auction.origins);
+        // make sure that the listing is still valid before transferring the token
+        ext_contract::nft_token(
+            auction.token_id.clone(),
+            auction.nft_contract_id.clone(),
+            NO_DEPOSIT,
+            GAS_FOR_NFT_TOKEN,
+        )
+        .then(ext_self::resolve_verified_finish_auction(
+            auction,
+            fees,
+            env::current_account_id(),
+            NO_DEPOSIT,
+            GAS_FOR_VERIFIED_TRANSFER,
+        ))
+    }
+
+    // self callback
+    // If the listing is still valid - transfer the token with payouts
+    // If not - refund the final bid
+    #[private]
+    pub fn resolve_verified_finish_auction(
+        &mut self,
+        auction: Auction,
+        fees: Fees,
+    ) -> PromiseOrValue<U128> {
         let final_bid = auction
             .bid
+            .clone()
             .unwrap_or_else(|| env::panic_str("Can finalize only if there is a bid"));
-        let mut buyer = final_bid.origins;
-        buyer.insert(env::current_account_id(), PROTOCOL_FEE as u32);
-        let mut seller_fee = HashMap::with_capacity(auction.origins.len() + 1);
-        seller_fee.extend(auction.origins.clone()); // TODO: dodge this clone
-        seller_fee.insert(env::current_account_id(), PROTOCOL_FEE as u32);
-        let fees = fee::Fees {
-            buyer,
-            seller: seller_fee,
+        let listing_status = if auction.custody {
+            custody_status_from_promise()
+        } else {
+            listing_status_from_promise(&auction.owner_id, auction.approval_id)
         };
-        ext_contract::nft_transfer_payout(
+        if listing_status != Some(true) {
+            self.refund_bid(
+                auction.ft_token_id.clone(),
+                final_bid.owner_id.clone(),
+                final_bid.price,
+            );
+            if auction.custody {
+                return_custody_token(
+                    auction.nft_contract_id.clone(),
+                    auction.token_id.clone(),
+                    auction.owner_id.clone(),
+                );
+            }
+            env::log_str(
+                &json!({
+                    "type": "remove_stale_auction",
+                    "params": {
+                        "owner_id": auction.owner_id,
+                        "nft_contract_id": auction.nft_contract_id,
+                        "token_id": auction.token_id,
+                        "buyer_id": final_bid.owner_id,
+                        "price": final_bid.price,
+                    }
+                })
+                .to_string(),
+            );
+            return PromiseOrValue::Value(final_bid.price);
+        }
+        let payout_mode = self.get_payout_mode(auction.nft_contract_id.clone());
+        nft_transfer_with_payout(
+            payout_mode,
+            auction.nft_contract_id.clone(),
             final_bid.owner_id.clone(),
             auction.token_id.clone(),
             auction.approval_id,
-            Some(near_sdk::serde_json::to_string(&fees).expect("Failed to sereailize")),
-            final_bid.price,
-            10,
-            auction.nft_contract_id.clone(),
-            1,
-            GAS_FOR_NFT_TRANSFER,
+            final_bid.price.0,
+            &fees,
+            self.market.max_payout_len,
         )
         .then(ext_self::resolve_finish_auction(
-            auction.ft_token_id,
-            final_bid.owner_id.clone(),
-            final_bid.price,
+            auction,
+            fees,
+            payout_mode,
             env::current_account_id(),
             NO_DEPOSIT,
             GAS_FOR_ROYALTIES,
         ))
+        .into()
     }
 
     // self callback
-    // If transfer of token succeded - count fees and transfer payouts
-    // If failed - refund price to buyer
+    // If transfer of token succeded - count fees and transfer payouts,
+    // the seller gets the price without fees if the payout is not valid
+    // If failed - refund price to buyer and return the custody token
     #[private]
     pub fn resolve_finish_auction(
         &mut self,
-        ft_token_id: AccountId,
-        buyer_id: AccountId,
-        price: U128,
+        auction: Auction,
+        fees: Fees,
+        payout_mode: PayoutMode,
     ) -> U128 {
-        let payout_option = promise_result_as_success().and_then(|value| {
-            near_sdk::serde_json::from_slice::<Payout>(&value)
-                .ok()
-                .and_then(|payout| {
-                    if payout.payout.len() > 10 || payout.payout.is_empty() {
-                        env::log_str("Cannot have more than 10 payouts and sale.bids refunds");
-                        None
-                    } else {
-                        let mut remainder = price.0;
-                        for &value in payout.payout.values() {
-                            remainder = remainder.checked_sub(value.0)?;
-                        }
-                        if remainder <= 1 {
-                            Some(payout)
+        let final_bid = auction
+            .bid
+            .clone()
+            .unwrap_or_else(|| env::panic_str("Can finalize only if there is a bid"));
+        let ft_token_id = auction.ft_token_id.clone();
+        let buyer_id = final_bid.owner_id;
+        let price = final_bid.price;
+        let market_fees = market_fee_payouts(payout_mode, price.0, &fees);
+        let max_payout_len = self.market.max_payout_len as usize;
+        let result = promise_result_as_success();
+        // the token belongs to the buyer even if the payout is not valid
+        let transferred = result.is_some();
+        let payout_option = result.and_then(|value| {
+            self.internal_payout_from_result(
+                payout_mode,
+                &value,
+                &auction.nft_contract_id,
+                &auction.owner_id,
+                payout_balance(payout_mode, price.0, &fees),
+            )
+            .and_then(|payout| {
+                // the transfers which don't fit this call are left for `settle`,
+                // the length is limited by the gas of the callback
+                if payout.payout.len() > max_payout_len || payout.payout.is_empty() {
+                    env::log_str(&format!("Cannot have more than {} payouts", max_payout_len));
+                    None
+                } else {
+                    let mut remainder = payout_balance(payout_mode, price.0, &fees);
+                    for &value in payout.payout.values() {
+                        remainder = remainder.checked_sub(value.0)?;
+                    }
+                    if remainder <= 1 {
+                        let payout = if auction.custody {
+                            custody_payout(payout_mode, payout, &auction.owner_id, price.0, &fees)
                         } else {
-                            None
-                        }
+                            payout
+                        };
+                        Some(with_market_fees(payout, market_fees))
+                    } else {
+                        None
                     }
-                })
+                }
+            })
         });
         // is payout option valid?
         let payout = if let Some(payout_option) = payout_option {
             payout_option
+        } else if transferred {
+            env::log_str("The payout is not valid, the seller gets the price without fees");
+            seller_payout(&auction.owner_id, price.0, &fees)
         } else {
             if ft_token_id == "near".parse().unwrap() {
-                Promise::new(buyer_id).transfer(u128::from(price));
+                self.internal_transfer(ft_token_id.clone(), buyer_id, price);
+            }
+            // the auction is already removed, the market shouldn't keep the token
+            if auction.custody {
+                return_custody_token(auction.nft_contract_id, auction.token_id, auction.owner_id);
             }
             // leave function and return all FTs in ft_resolve_transfer
             return price;
         };
+        self.internal_record_trade(
+            &auction.nft_contract_id,
+            &auction.token_id,
+            &ft_token_id,
+            price.0,
+        );
+        self.internal_record_origin_earnings(&ft_token_id, price.0, &fees);
+        self.internal_add_trade(
+            auction.nft_contract_id,
+            auction.token_id,
+            auction.owner_id,
+            buyer_id,
+            ft_token_id.clone(),
+            price.0,
+            &fees,
+            &payout,
+        );
 
-        // NEAR payouts
+        // The transfers which don't fit this call are left for `settle`
+        self.internal_settle(payout_transfers(&ft_token_id, payout));
         if ft_token_id == "near".parse().unwrap() {
-            for (receiver_id, amount) in payout.payout {
-                Promise::new(receiver_id).transfer(amount.0);
-            }
             // refund all FTs (won't be any)
             price
         } else {
-            // FT payouts
-            for (receiver_id, amount) in payout.payout {
-                ext_contract::ft_transfer(
-                    receiver_id,
-                    amount,
-                    None,
-                    ft_token_id.clone(),
-                    1,
-                    GAS_FOR_FT_TRANSFER,
-                );
-            }
-            // keep all FTs (already transferred for payouts)
+            // keep all FTs (transferred for payouts)
             U128(0)
         }
     }
@@ -324,6 +565,147 @@ impl Market {
             buy_out_price: auction.buy_out_price.map(|p| p.into()),
             start: auction.start.into(),
             end: auction.end.into(),
+            is_private: auction.allowed_buyers.is_some(),
+            allowed_buyers: auction.allowed_buyers,
+            custody: auction.custody,
+            fallback: auction.fallback,
+        }
+    }
+}
+
+impl Market {
+    // Stores the new auction and indexes it by the owner, returns its id
+    pub(crate) fn internal_insert_auction(&mut self, auction: &Auction) -> u128 {
+        let auction_id = self.market.next_auction_id;
+        self.market.auctions.insert(&auction_id, auction);
+        self.market.next_auction_id += 1;
+        account_index_insert(
+            &mut self.market.auctions_by_owner_id,
+            &auction.owner_id,
+            &auction_id,
+            StorageKey::AuctionsByOwnerIdInner {
+                account_id_hash: hash_account_id(&auction.owner_id),
+            },
+        );
+        auction_id
+    }
+
+    // Removes the auction which has ended without a bid
+    // The auction is relisted or turned into a sale if it has a fallback,
+    // otherwise the token held by the market is returned to the owner
+    // Returns `false` if there is no such auction
+    pub(crate) fn internal_close_auction(&mut self, auction_id: u128) -> bool {
+        let auction = match self.internal_remove_auction(auction_id) {
+            Some(auction) => auction,
+            None => return false,
+        };
+        let owner_id = auction.owner_id.clone();
+        let nft_contract_id = auction.nft_contract_id.clone();
+        let token_id = auction.token_id.clone();
+        let custody = auction.custody;
+        let fallback = auction.fallback.clone();
+        let relisted = match fallback.clone() {
+            Some(AuctionFallback::Relist { start_price }) => {
+                let now = env::block_timestamp();
+                let relisted_auction = Auction {
+                    bid: None,
+                    created_at: now,
+                    start_price: start_price.0,
+                    start: now,
+                    end: now + (auction.end - auction.start).max(EXTENSION_DURATION),
+                    fallback: None,
+                    ..auction
+                };
+                let relisted_id = self.internal_insert_auction(&relisted_auction);
+                json!({ "auction_id": U128(relisted_id) })
+            }
+            // the sale takes the storage of the owner, without it the auction is just closed
+            // The sale is listed with the checked arguments of the auction, so closing
+            // doesn't fail if the market settings have changed since
+            Some(AuctionFallback::Sale { price })
+                if self.internal_can_list_fallback_sale(&auction) =>
+            {
+                let now = env::block_timestamp();
+                let sale_json = self.internal_insert_sale(Sale {
+                    owner_id: owner_id.clone(),
+                    approval_id: auction.approval_id,
+                    nft_contract_id: nft_contract_id.clone(),
+                    token_id: token_id.clone(),
+                    sale_conditions: HashMap::from([(auction.ft_token_id, price)]),
+                    created_at: now,
+                    token_type: None,
+                    start: Some(now),
+                    end: None,
+                    origins: auction.origins,
+                    allowed_buyers: auction.allowed_buyers,
+                    reserved_buyer: None,
+                    locked_at: None,
+                    custody,
+                });
+                json!({ "sale_json": sale_json })
+            }
+            _ => {
+                if custody {
+                    return_custody_token(
+                        nft_contract_id.clone(),
+                        token_id.clone(),
+                        owner_id.clone(),
+                    );
+                }
+                json!(null)
+            }
+        };
+        env::log_str(
+            &json!({
+                "type": "close_auction",
+                "params": {
+                    "auction_id": U128(auction_id),
+                    "owner_id": owner_id,
+                    "nft_contract_id": nft_contract_id,
+                    "token_id": token_id,
+                    "fallback": fallback,
+                    "relisted": relisted,
+                }
+            })
+            .to_string(),
+        );
+        true
+    }
+
+    // The owner has paid the storage for one more sale, the token of the auction
+    // is still supported and it isn't on a sale locked by a purchase
+    fn internal_can_list_fallback_sale(&self, auction: &Auction) -> bool {
+        let owner_paid_storage = self
+            .market
+            .storage_deposits
+            .get(&auction.owner_id)
+            .unwrap_or(0);
+        let has_storage = owner_paid_storage
+            >= (self.internal_supply_with_listings(&auction.owner_id) + 1) as u128
+                * STORAGE_PER_SALE;
+        let locked = self
+            .market
+            .sales
+            .get(&sale_key(&auction.nft_contract_id, &auction.token_id))
+            .map_or(false, |sale| sale.is_locked());
+        has_storage && self.market.ft_token_ids.contains(&auction.ft_token_id) && !locked
+    }
+
+    // Removes the auction and its entries in `auctions_by_owner_id` and `auction_bids_by_owner_id`
+    pub(crate) fn internal_remove_auction(&mut self, auction_id: u128) -> Option<Auction> {
+        let auction = self.market.auctions.remove(&auction_id)?;
+        account_index_remove(
+            &mut self.market.auctions_by_owner_id,
+            &auction.owner_id,
+            &auction_id,
+        );
+        if let Some(bid) = auction.bid.as_ref() {
+            account_index_remove(
+                &mut self.market.auction_bids_by_owner_id,
+                &bid.owner_id,
+                &auction_id,
+            );
         }
+        Some(auction)
     }
 }
@@ -1,5 +1,6 @@
 use crate::auction::AuctionJson;
 use crate::common::*;
+use crate::pagination::{iter_after, take_page, Page};
 use crate::*;
 
 #[near_bindgen]
@@ -69,16 +70,24 @@ impl Market {
         })
     }
 
-    pub fn get_auctions(&self, from_index: Option<U128>, limit: Option<u64>) -> Vec<AuctionJson> {
-        let auctions = &self.market.auctions;
-        let start_index: u128 = from_index.map(From::from).unwrap_or_default();
-        let limit = limit.map(|v| v as usize).unwrap_or(usize::MAX);
-        auctions
-            .values()
-            .skip(start_index as usize)
-            .take(limit)
-            .map(|auction| self.json_from_auction(auction))
-            .collect()
+    // The cursor is the id of the last auction on the page
+    pub fn get_auctions(
+        &self,
+        cursor: Option<U128>,
+        limit: Option<u64>,
+    ) -> Page<AuctionJson, U128> {
+        let (auctions, next_cursor) = take_page(
+            iter_after(&self.market.auctions, cursor.map(u128::from)),
+            limit,
+            |(auction_id, _)| U128(*auction_id),
+        );
+        Page {
+            items: auctions
+                .into_iter()
+                .map(|(_, auction)| self.json_from_auction(auction))
+                .collect(),
+            next_cursor,
+        }
     }
 
     //pub fn get_bid_total_amount() -> U128;
@@ -3,9 +3,8 @@ use std::collections::HashMap;
 use near_sdk::assert_one_yocto;
 
 use crate::fee::{calculate_actual_amount, calculate_origins};
-use crate::sale::{
-    ext_contract, ContractAndTokenId, FungibleTokenId, Sale, DELIMETER, GAS_FOR_FT_TRANSFER,
-};
+use crate::sale::{sale_key, FungibleTokenId, SaleKey};
+use crate::settlement::PendingTransfer;
 use crate::*;
 
 #[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
@@ -45,11 +44,10 @@ impl Market {
     #[private]
     pub(crate) fn add_bid(
         &mut self,
-        contract_and_token_id: ContractAndTokenId,
+        sale_key: SaleKey,
         amount: Balance,
         ft_token_id: AccountId,
         buyer_id: AccountId,
-        sale: &mut Sale,
         start: U64,
         end: Option<U64>,
         origins: Option<Origins>,
@@ -58,13 +56,12 @@ impl Market {
             self.market.ft_token_ids.contains(&ft_token_id),
             format!("Token {} not supported by this market", ft_token_id)
         );
+        self.assert_valid_origins(origins.as_ref());
         let total_origins = if let Some(ref origins) = origins {
             calculate_origins(origins)
         } else {
             0
         };
-
-        require!(total_origins < 4_700, "Max origins exceeded"); // TODO: FINDOUT MAX ORIGINS
         let actual_amount = calculate_actual_amount(amount, total_origins);
 
         // store a bid and refund any current bid lower
@@ -76,10 +73,9 @@ impl Market {
             origins: origins.unwrap_or_default(),
         };
 
-        let bids_for_token_id = sale
-            .bids
-            .entry(ft_token_id.clone())
-            .or_insert_with(Vec::new);
+        // only the bids for this ft_token_id are read and written
+        let bids_key = (sale_key, ft_token_id.clone());
+        let mut bids_for_token_id = self.market.bids.get(&bids_key).unwrap_or_default();
         if let Some(current_bid) = bids_for_token_id.last() {
             let current_origins = calculate_origins(&current_bid.origins);
             let current_amount = calculate_actual_amount(current_bid.price.0, current_origins);
@@ -100,7 +96,7 @@ impl Market {
             bids_for_token_id.remove(0);
         }
 
-        self.market.sales.insert(&contract_and_token_id, sale);
+        self.internal_set_bids(&bids_key, bids_for_token_id);
     }
 
     #[payable]
@@ -113,7 +109,8 @@ impl Market {
     ) {
         assert_one_yocto();
         let owner_id = env::predecessor_account_id();
-        self.internal_remove_bid(nft_contract_id, &ft_token_id, token_id, &owner_id, price);
+        self.internal_remove_bid(nft_contract_id, &ft_token_id, token_id, &owner_id, price)
+            .expect("No bid");
         self.refund_bid(ft_token_id, owner_id, price);
     }
 
@@ -146,58 +143,42 @@ impl Market {
         token_id: TokenId,
         ft_token_id: AccountId,
     ) {
-        let contract_and_token_id = format!("{}{}{}", &nft_contract_id, DELIMETER, token_id);
-        let mut sale = self
-            .market
-            .sales
-            .get(&contract_and_token_id)
-            .expect("No sale");
-        let bid_vec = sale.bids.get_mut(&ft_token_id).expect("No token");
-        let mut sale = self
-            .market
-            .sales
-            .get(&contract_and_token_id)
-            .expect("No sale");
-        bid_vec.retain(|bid_from_vec| {
-            let mut not_finished = true;
-            if let Some(end) = bid_from_vec.end {
-                //is_finished &= env::block_timestamp() >= end.0;
-                if env::block_timestamp() >= end.0 {
-                    self.refund_bid(
-                        ft_token_id.clone(),
-                        bid_from_vec.owner_id.clone(),
-                        bid_from_vec.price,
-                    );
-                    not_finished = false;
-                };
-            }
-            not_finished
-        });
-        if bid_vec.is_empty() {
-            // If there is no bids left, should remove ft_token_id from the HashMap
-            sale.bids.remove(&ft_token_id);
-        } else {
-            // If there are some bids left, add a vector of valid bids
-            sale.bids.insert(ft_token_id.clone(), bid_vec.to_vec());
-        };
-        self.market.sales.insert(&contract_and_token_id, &sale);
+        let sale_key = sale_key(&nft_contract_id, &token_id);
+        require!(self.market.sales.get(&sale_key).is_some(), "No sale");
+        let bids_key = (sale_key, ft_token_id.clone());
+        let bid_vec = self.market.bids.get(&bids_key).expect("No token");
+        let now = env::block_timestamp();
+        let (finished, not_finished): (Vec<Bid>, Vec<Bid>) = bid_vec
+            .into_iter()
+            .partition(|bid| bid.end.map_or(false, |end| now >= end.0));
+        for bid in finished {
+            self.refund_bid(ft_token_id.clone(), bid.owner_id, bid.price);
+        }
+        // If there is no bids left, the bids for ft_token_id are removed
+        self.internal_set_bids(&bids_key, not_finished);
     }
 }
 
 impl Market {
+    // Refunds are batched, see `internal_settle`
     pub(crate) fn refund_all_bids(&mut self, bids_map: &Bids) {
-        for (ft, bids) in bids_map {
-            for bid in bids {
-                self.refund_bid((*ft).clone(), bid.owner_id.clone(), bid.price);
-            }
-        }
+        self.internal_settle(bid_refunds(bids_map));
     }
 
     pub(crate) fn refund_bid(&mut self, bid_ft: FungibleTokenId, owner_id: AccountId, price: U128) {
-        if bid_ft.as_str() == "near" {
-            Promise::new(owner_id).transfer(u128::from(price));
-        } else {
-            ext_contract::ft_transfer(owner_id, price, None, bid_ft, 1, GAS_FOR_FT_TRANSFER);
-        }
+        self.internal_transfer(bid_ft, owner_id, price);
     }
 }
+
+pub(crate) fn bid_refunds(bids_map: &Bids) -> Vec<PendingTransfer> {
+    bids_map
+        .iter()
+        .flat_map(|(ft, bids)| {
+            bids.iter().map(move |bid| PendingTransfer {
+                ft_token_id: ft.clone(),
+                receiver_id: bid.owner_id.clone(),
+                amount: bid.price,
+            })
+        })
+        .collect()
+}
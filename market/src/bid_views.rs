@@ -0,0 +1,211 @@
+use std::collections::HashMap;
+
+use near_sdk::json_types::Base58CryptoHash;
+
+use crate::pagination::{iter_after, take_page, Page};
+use crate::sale::{BidsKey, FungibleTokenId};
+use crate::*;
+
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub enum BidStatus {
+    // The last bid of the sale for its fungible token, or the current bid of the auction
+    Leading,
+    // A higher bid has been made on the sale, the bid is kept until it is removed or refunded
+    Outbid,
+    // The end of the bid has passed, it can be refunded with `cancel_bid`
+    Expired,
+    // The bid can't be accepted before its start
+    PendingStart,
+    // The auction has ended with this bid, it is waiting for `finish_auction`
+    Won,
+}
+
+// A bid of the account on a sale
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub struct AccountBid {
+    pub nft_contract_id: AccountId,
+    pub token_id: TokenId,
+    pub ft_token_id: FungibleTokenId,
+    pub bid: Bid,
+    pub status: BidStatus,
+}
+
+// The current bid of the account on an auction
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub struct AccountAuctionBid {
+    pub auction_id: U128,
+    pub nft_contract_id: AccountId,
+    pub token_id: TokenId,
+    pub ft_token_id: FungibleTokenId,
+    pub bid: Bid,
+    pub end: U64,
+    pub status: BidStatus,
+}
+
+// The sale and the fungible token of the last bids on the page
+#[derive(Serialize, Deserialize, Clone)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub struct AccountBidsCursor {
+    pub sale_key: Base58CryptoHash,
+    pub ft_token_id: FungibleTokenId,
+}
+
+impl From<BidsKey> for AccountBidsCursor {
+    fn from((sale_key, ft_token_id): BidsKey) -> Self {
+        Self {
+            sale_key: sale_key.into(),
+            ft_token_id,
+        }
+    }
+}
+
+impl From<AccountBidsCursor> for BidsKey {
+    fn from(cursor: AccountBidsCursor) -> Self {
+        (cursor.sale_key.into(), cursor.ft_token_id)
+    }
+}
+
+#[near_bindgen]
+impl Market {
+    // The cursor goes over the sales with the bids of the account,
+    // all the bids of the account on one sale are on the same page
+    pub fn get_bids_by_account(
+        &self,
+        account_id: AccountId,
+        cursor: Option<AccountBidsCursor>,
+        limit: Option<u64>,
+    ) -> Page<AccountBid, AccountBidsCursor> {
+        let by_owner_id = match self.market.bids_by_owner_id.get(&account_id) {
+            Some(by_owner_id) => by_owner_id,
+            None => {
+                return Page {
+                    items: vec![],
+                    next_cursor: None,
+                }
+            }
+        };
+        let (bids_keys, next_cursor) = take_page(
+            iter_after(&by_owner_id, cursor.map(BidsKey::from)),
+            limit,
+            |(bids_key, _)| AccountBidsCursor::from(bids_key.clone()),
+        );
+        Page {
+            items: bids_keys
+                .into_iter()
+                .flat_map(|(bids_key, _)| self.internal_account_bids(&account_id, &bids_key))
+                .collect(),
+            next_cursor,
+        }
+    }
+
+    pub fn get_auctions_bid_by_account(
+        &self,
+        account_id: AccountId,
+        cursor: Option<U128>,
+        limit: Option<u64>,
+    ) -> Page<AccountAuctionBid, U128> {
+        let by_owner_id = match self.market.auction_bids_by_owner_id.get(&account_id) {
+            Some(by_owner_id) => by_owner_id,
+            None => {
+                return Page {
+                    items: vec![],
+                    next_cursor: None,
+                }
+            }
+        };
+        let (auction_ids, next_cursor) = take_page(
+            iter_after(&by_owner_id, cursor.map(u128::from)),
+            limit,
+            |(auction_id, _)| U128(*auction_id),
+        );
+        let now = env::block_timestamp();
+        Page {
+            items: auction_ids
+                .into_iter()
+                .filter_map(|(auction_id, _)| {
+                    let auction = self.market.auctions.get(&auction_id)?;
+                    let bid = auction.bid?;
+                    Some(AccountAuctionBid {
+                        auction_id: U128(auction_id),
+                        nft_contract_id: auction.nft_contract_id,
+                        token_id: auction.token_id,
+                        ft_token_id: auction.ft_token_id,
+                        bid,
+                        end: U64(auction.end),
+                        status: if auction.end < now {
+                            BidStatus::Won
+                        } else {
+                            BidStatus::Leading
+                        },
+                    })
+                })
+                .collect(),
+            next_cursor,
+        }
+    }
+
+    // The total of the bids of the account held by the market, for every fungible token
+    pub fn get_escrowed_by_account(&self, account_id: AccountId) -> HashMap<FungibleTokenId, U128> {
+        let mut escrowed: HashMap<FungibleTokenId, U128> = HashMap::new();
+        if let Some(by_owner_id) = self.market.bids_by_owner_id.get(&account_id) {
+            for (bids_key, _) in by_owner_id.iter() {
+                let bids = self.market.bids.get(&bids_key).unwrap_or_default();
+                for bid in bids.iter().filter(|bid| bid.owner_id == account_id) {
+                    escrowed.entry(bids_key.1.clone()).or_insert(U128(0)).0 += bid.price.0;
+                }
+            }
+        }
+        if let Some(by_owner_id) = self.market.auction_bids_by_owner_id.get(&account_id) {
+            for (auction_id, _) in by_owner_id.iter() {
+                // a stale id is skipped, as in `get_auctions_bid_by_account`
+                if let Some(auction) = self.market.auctions.get(&auction_id) {
+                    if let Some(bid) = auction.bid {
+                        escrowed.entry(auction.ft_token_id).or_insert(U128(0)).0 += bid.price.0;
+                    }
+                }
+            }
+        }
+        escrowed
+    }
+}
+
+impl Market {
+    fn internal_account_bids(&self, account_id: &AccountId, bids_key: &BidsKey) -> Vec<AccountBid> {
+        let sale = match self.market.sales.get(&bids_key.0) {
+            Some(sale) => sale,
+            None => return vec![],
+        };
+        let bids = self.market.bids.get(bids_key).unwrap_or_default();
+        let last = bids.len().saturating_sub(1);
+        let now = env::block_timestamp();
+        bids.into_iter()
+            .enumerate()
+            .filter(|(_, bid)| &bid.owner_id == account_id)
+            .map(|(index, bid)| {
+                let status = if bid.end.map_or(false, |end| now >= end.0) {
+                    BidStatus::Expired
+                } else if bid.start.0 >= now {
+                    BidStatus::PendingStart
+                } else if index == last {
+                    BidStatus::Leading
+                } else {
+                    BidStatus::Outbid
+                };
+                AccountBid {
+                    nft_contract_id: sale.nft_contract_id.clone(),
+                    token_id: sale.token_id.clone(),
+                    ft_token_id: bids_key.1.clone(),
+                    bid,
+                    status,
+                }
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,171 @@
+use std::collections::HashMap;
+
+use crate::bid::bid_refunds;
+use crate::custody::return_custody_token;
+use crate::sale::{FungibleTokenId, Sale};
+use crate::settlement::PendingTransfer;
+use crate::*;
+
+// Keeps one call within the gas limit, every custody token is returned with its own transfer
+pub const BULK_REMOVAL_MAX: u64 = 10;
+
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub struct BulkRemoval {
+    pub removed: U64,
+    // The total of the refunded bids for every fungible token
+    pub refunded: HashMap<FungibleTokenId, U128>,
+    // Left after the call, including the ones which can't be removed now
+    pub remaining: U64,
+}
+
+#[near_bindgen]
+impl Market {
+    // Removes up to `limit` sales of the caller and refunds their bids
+    // The sales locked by a purchase are skipped
+    #[payable]
+    pub fn remove_all_sales(&mut self, limit: Option<u64>) -> BulkRemoval {
+        assert_one_yocto();
+        let owner_id = env::predecessor_account_id();
+        let sales: Vec<Sale> = self
+            .market
+            .by_owner_id
+            .get(&owner_id)
+            .map(|by_owner_id| {
+                by_owner_id
+                    .iter()
+                    .filter_map(|(sale_key, _)| self.market.sales.get(&sale_key))
+                    .filter(|sale| !sale.is_locked())
+                    .take(bulk_limit(limit))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let mut refunds = vec![];
+        for sale in sales.iter() {
+            let (_, bids) =
+                self.internal_remove_sale(sale.nft_contract_id.clone(), sale.token_id.clone());
+            refunds.extend(bid_refunds(&bids));
+            if sale.custody {
+                return_custody_token(
+                    sale.nft_contract_id.clone(),
+                    sale.token_id.clone(),
+                    sale.owner_id.clone(),
+                );
+            }
+        }
+        let remaining = self.get_supply_by_owner_id(owner_id);
+        self.internal_bulk_removal(sales.len(), refunds, remaining)
+    }
+
+    // Cancels up to `limit` auctions of the caller, the auctions with a bid are skipped
+    #[payable]
+    pub fn cancel_all_auctions(&mut self, limit: Option<u64>) -> BulkRemoval {
+        assert_one_yocto();
+        let owner_id = env::predecessor_account_id();
+        let auction_ids: Vec<u128> = self
+            .market
+            .auctions_by_owner_id
+            .get(&owner_id)
+            .map(|by_owner_id| {
+                by_owner_id
+                    .iter()
+                    .map(|(auction_id, _)| auction_id)
+                    .filter(|auction_id| {
+                        self.market
+                            .auctions
+                            .get(auction_id)
+                            .map_or(false, |auction| auction.bid.is_none())
+                    })
+                    .take(bulk_limit(limit))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        for auction_id in auction_ids.iter() {
+            if let Some(auction) = self.internal_remove_auction(*auction_id) {
+                if auction.custody {
+                    return_custody_token(
+                        auction.nft_contract_id,
+                        auction.token_id,
+                        auction.owner_id,
+                    );
+                }
+            }
+        }
+        let remaining = self
+            .market
+            .auctions_by_owner_id
+            .get(&owner_id)
+            .map_or(0, |by_owner_id| by_owner_id.len());
+        self.internal_bulk_removal(auction_ids.len(), vec![], U64(remaining))
+    }
+
+    // Removes and refunds the bids of the caller on up to `limit` sales
+    #[payable]
+    pub fn remove_all_bids(&mut self, limit: Option<u64>) -> BulkRemoval {
+        assert_one_yocto();
+        let owner_id = env::predecessor_account_id();
+        let bids_keys: Vec<BidsKey> = self
+            .market
+            .bids_by_owner_id
+            .get(&owner_id)
+            .map(|by_owner_id| {
+                by_owner_id
+                    .iter()
+                    .map(|(bids_key, _)| bids_key)
+                    .take(bulk_limit(limit))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let mut refunds = vec![];
+        for bids_key in bids_keys.iter() {
+            let bids = self.market.bids.get(bids_key).unwrap_or_default();
+            let (removed, kept): (Vec<Bid>, Vec<Bid>) =
+                bids.into_iter().partition(|bid| bid.owner_id == owner_id);
+            self.internal_set_bids(bids_key, kept);
+            refunds.extend(removed.into_iter().map(|bid| PendingTransfer {
+                ft_token_id: bids_key.1.clone(),
+                receiver_id: bid.owner_id,
+                amount: bid.price,
+            }));
+        }
+        let removed = refunds.len();
+        let remaining = self
+            .market
+            .bids_by_owner_id
+            .get(&owner_id)
+            .map_or(0, |by_owner_id| by_owner_id.len());
+        self.internal_bulk_removal(removed, refunds, U64(remaining))
+    }
+}
+
+impl Market {
+    // Refunds the bids in one settlement and sums them up for the result
+    fn internal_bulk_removal(
+        &mut self,
+        removed: usize,
+        refunds: Vec<PendingTransfer>,
+        remaining: U64,
+    ) -> BulkRemoval {
+        let mut refunded: HashMap<FungibleTokenId, U128> = HashMap::new();
+        for refund in refunds.iter() {
+            refunded
+                .entry(refund.ft_token_id.clone())
+                .or_insert(U128(0))
+                .0 += refund.amount.0;
+        }
+        self.internal_settle(refunds);
+        BulkRemoval {
+            removed: U64(removed as u64),
+            refunded,
+            remaining,
+        }
+    }
+}
+
+fn bulk_limit(limit: Option<u64>) -> usize {
+    limit.unwrap_or(BULK_REMOVAL_MAX).min(BULK_REMOVAL_MAX) as usize
+}
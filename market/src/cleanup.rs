@@ -0,0 +1,167 @@
+use std::cmp::min;
+
+use crate::auction::Auction;
+use crate::custody::return_custody_token;
+use crate::pagination::{iter_after, take_page, Page};
+use crate::sale::Sale;
+use crate::search::SearchCursor;
+use crate::*;
+
+// A tenth of the storage deposit for one sale
+pub const KEEPER_REWARD_DEFAULT: Balance = STORAGE_PER_SALE / 10;
+
+// A sale or an auction removed by `cleanup_expired`
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub struct CleanedListing {
+    pub nft_contract_id: AccountId,
+    pub token_id: TokenId,
+    // Set for the auctions, which are closed as with `close_auction`
+    pub auction_id: Option<U128>,
+    // Taken from the storage deposit of the sale owner
+    pub reward: U128,
+}
+
+// The last listing checked by `cleanup_expired`, the sales are checked before the auctions
+#[derive(Serialize, Deserialize, Clone)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub enum CleanupCursor {
+    Sale(SearchCursor),
+    Auction(U128),
+}
+
+#[near_bindgen]
+impl Market {
+    // Removes the sales and the auctions which have ended, the auctions with a bid are left
+    // for `finish_auction`. Up to `limit` listings are checked, the oldest sales first
+    // and then the auctions.
+    // Can be called by anyone, the caller is rewarded from the storage deposit of the removed sales
+    pub fn cleanup_expired(
+        &mut self,
+        cursor: Option<CleanupCursor>,
+        limit: Option<u64>,
+    ) -> Page<CleanedListing, CleanupCursor> {
+        let now = env::block_timestamp();
+        let (sales_after, auctions_after) = match cursor {
+            Some(CleanupCursor::Sale(cursor)) => (Some(SortKey::from(cursor)), None),
+            Some(CleanupCursor::Auction(auction_id)) => (None, Some(auction_id.0)),
+            None => (None, None),
+        };
+        // the sales are done once the cursor is on the auctions
+        let sales = if auctions_after.is_none() {
+            Some(iter_after(&self.market.by_created_at, sales_after))
+        } else {
+            None
+        };
+        let listings = sales
+            .into_iter()
+            .flatten()
+            .map(|(key, _)| CleanupCursor::Sale(key.into()))
+            .chain(
+                iter_after(&self.market.auctions, auctions_after)
+                    .map(|(auction_id, _)| CleanupCursor::Auction(U128(auction_id))),
+            );
+        let (listings, next_cursor) = take_page(listings, limit, CleanupCursor::clone);
+
+        let mut expired_sales: Vec<Sale> = vec![];
+        let mut expired_auctions: Vec<(u128, Auction)> = vec![];
+        for listing in listings {
+            match listing {
+                CleanupCursor::Sale(key) => {
+                    let sale = match self.market.sales.get(&SaleKey::from(key.sale_key)) {
+                        Some(sale) => sale,
+                        None => continue,
+                    };
+                    if !sale.is_locked() && sale.end.map_or(false, |end| end < now) {
+                        expired_sales.push(sale);
+                    }
+                }
+                CleanupCursor::Auction(auction_id) => {
+                    let auction = match self.market.auctions.get(&auction_id.0) {
+                        Some(auction) => auction,
+                        None => continue,
+                    };
+                    if auction.end < now && auction.bid.is_none() {
+                        expired_auctions.push((auction_id.0, auction));
+                    }
+                }
+            }
+        }
+
+        let mut items = vec![];
+        let mut reward = 0;
+        for sale in expired_sales {
+            let (_, bids) =
+                self.internal_remove_sale(sale.nft_contract_id.clone(), sale.token_id.clone());
+            self.refund_all_bids(&bids);
+            if sale.custody {
+                return_custody_token(
+                    sale.nft_contract_id.clone(),
+                    sale.token_id.clone(),
+                    sale.owner_id.clone(),
+                );
+            }
+            let sale_reward = self.internal_take_keeper_reward(&sale.owner_id);
+            reward += sale_reward;
+            items.push(CleanedListing {
+                nft_contract_id: sale.nft_contract_id,
+                token_id: sale.token_id,
+                auction_id: None,
+                reward: U128(sale_reward),
+            });
+        }
+        // the fallback of the auction is applied as in `close_auction`,
+        // an auction which can't be closed is skipped
+        for (auction_id, auction) in expired_auctions {
+            if !self.internal_close_auction(auction_id) {
+                continue;
+            }
+            items.push(CleanedListing {
+                nft_contract_id: auction.nft_contract_id,
+                token_id: auction.token_id,
+                auction_id: Some(U128(auction_id)),
+                reward: U128(0),
+            });
+        }
+        if reward > 0 {
+            Promise::new(env::predecessor_account_id()).transfer(reward);
+        }
+
+        Page { items, next_cursor }
+    }
+
+    pub fn set_keeper_reward(&mut self, reward: U128) {
+        require!(
+            env::predecessor_account_id() == self.market.owner_id,
+            "Only the market owner can set the keeper reward"
+        );
+        require!(
+            reward.0 <= STORAGE_PER_SALE,
+            "The keeper reward can't exceed the storage deposit for one sale"
+        );
+        self.market.keeper_reward = reward.0;
+    }
+
+    pub fn get_keeper_reward(&self) -> U128 {
+        U128(self.market.keeper_reward)
+    }
+}
+
+impl Market {
+    // Takes the keeper reward from the storage deposit of `owner_id`,
+    // the deposit which covers the remaining sales, series sales, raffles and swaps
+    // is never touched
+    pub(crate) fn internal_take_keeper_reward(&mut self, owner_id: &AccountId) -> Balance {
+        let balance = self.market.storage_deposits.get(owner_id).unwrap_or(0);
+        let occupied = u128::from(self.internal_supply_with_listings(owner_id)) * STORAGE_PER_SALE;
+        let reward = min(self.market.keeper_reward, balance.saturating_sub(occupied));
+        if reward > 0 {
+            self.market
+                .storage_deposits
+                .insert(owner_id, &(balance - reward));
+        }
+        reward
+    }
+}
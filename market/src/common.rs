@@ -1,6 +1,6 @@
 pub use near_sdk::{
     borsh::{self, BorshDeserialize, BorshSerialize},
-    collections::{LazyOption, LookupSet, LookupMap, UnorderedMap, UnorderedSet},
+    collections::{LazyOption, LookupSet, LookupMap, TreeMap, UnorderedMap, UnorderedSet},
     env::{self, STORAGE_PRICE_PER_BYTE},
     json_types::{U128, U64},
     near_bindgen, require,
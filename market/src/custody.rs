@@ -0,0 +1,92 @@
+use near_sdk::{promise_result_as_success, Gas, PromiseOrValue};
+
+use crate::fee::{fee_payouts, Fees, PayoutMode};
+use crate::market_core::ArgsKind;
+use crate::sale::{ext_contract, Payout};
+use crate::*;
+
+pub const GAS_FOR_CUSTODY_TRANSFER: Gas = Gas(15_000_000_000_000);
+
+#[near_bindgen]
+impl Market {
+    // Called by `nft_transfer_call` of the NFT contract to create a sale or an auction from `msg`
+    // The market holds the token until it is sold or the listing is removed,
+    // so the listing can't go stale
+    pub fn nft_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        previous_owner_id: AccountId,
+        token_id: TokenId,
+        msg: String,
+    ) -> PromiseOrValue<bool> {
+        let nft_contract_id = env::predecessor_account_id();
+        let signer_id = env::signer_account_id();
+        require!(
+            nft_contract_id != signer_id,
+            "nft_on_transfer should only be called via cross-contract call"
+        );
+        require!(
+            previous_owner_id == signer_id && sender_id == signer_id,
+            "The token should be sent by its owner"
+        );
+        let args: ArgsKind = near_sdk::serde_json::from_str(&msg).expect("Not valid args");
+        // any panic here returns the token to the owner
+        self.internal_list(args, token_id, previous_owner_id, 0, nft_contract_id, true);
+        PromiseOrValue::Value(false)
+    }
+}
+
+// Checks the result of `nft_token`: the token should still be held by the market
+// Returns `None` if the NFT contract call failed
+pub(crate) fn custody_status_from_promise() -> Option<bool> {
+    let value = promise_result_as_success()?;
+    let token = near_sdk::serde_json::from_slice::<Option<Token>>(&value).ok()?;
+    Some(token.map_or(false, |token| token.owner_id == env::current_account_id()))
+}
+
+// The listing is removed, the market sends the token back to the owner
+pub(crate) fn return_custody_token(
+    nft_contract_id: AccountId,
+    token_id: TokenId,
+    owner_id: AccountId,
+) {
+    ext_contract::nft_transfer(
+        owner_id,
+        token_id,
+        None,
+        None,
+        nft_contract_id,
+        1,
+        GAS_FOR_CUSTODY_TRANSFER,
+    );
+}
+
+// The NFT contract pays the share of the token owner to the market, it goes to the seller
+// In the `Memo` mode that share also includes the protocol fee, which stays on the market
+pub(crate) fn custody_payout(
+    payout_mode: PayoutMode,
+    mut payout: Payout,
+    seller_id: &AccountId,
+    price: u128,
+    fees: &Fees,
+) -> Payout {
+    let market_id = env::current_account_id();
+    let amount = if let Some(amount) = payout.payout.remove(&market_id) {
+        amount.0
+    } else {
+        return payout;
+    };
+    let protocol_fee = match payout_mode {
+        PayoutMode::Memo => fee_payouts(price, fees)
+            .get(&market_id)
+            .copied()
+            .unwrap_or(0)
+            .min(amount),
+        PayoutMode::Standard | PayoutMode::Transfer => 0,
+    };
+    if protocol_fee > 0 {
+        payout.payout.insert(market_id, U128(protocol_fee));
+    }
+    payout.payout.entry(seller_id.clone()).or_insert(U128(0)).0 += amount - protocol_fee;
+    payout
+}
@@ -0,0 +1,221 @@
+use std::collections::HashSet;
+
+use crate::sale::{sale_key, SaleKey, TokenSeriesId};
+use crate::series_sale::SeriesSale;
+use crate::*;
+
+// The purchases of the wallet during the phase of the series sale, by the phase id
+pub type PhaseWalletKey = (SaleKey, u32, AccountId);
+
+// A stage of the series sale: presale for the allowlist, public sale and so on
+// Only the first phase which has started and not ended yet is active
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub struct DropPhase {
+    // The purchases are counted by the id, so reordering the phases keeps the limits
+    pub id: u32,
+    pub start: Option<U64>,
+    pub end: Option<U64>,
+    // The price in NEAR during the phase, the price of the series sale if not set
+    pub price: Option<U128>,
+    // Only these accounts can buy during the phase, everyone if not set
+    pub allowlist: Option<HashSet<AccountId>>,
+    // The copies one wallet can buy during the phase, no limit if not set
+    pub wallet_limit: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub struct ActivePhase {
+    pub index: u32,
+    pub phase: DropPhase,
+}
+
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub struct WalletAllocation {
+    // The id of the active phase
+    // `None` if the series sale has no phases or none of them is active
+    pub phase: Option<u32>,
+    pub allowed: bool,
+    // The copies bought by the wallet during the phase
+    pub bought: U64,
+    // The copies the wallet can still buy, limited by the copies left
+    pub remaining: U64,
+}
+
+impl DropPhase {
+    pub fn is_active(&self) -> bool {
+        let now = env::block_timestamp();
+        self.start.map_or(true, |start| start.0 <= now) && self.end.map_or(true, |end| now < end.0)
+    }
+
+    pub fn is_allowed(&self, account_id: &AccountId) -> bool {
+        self.allowlist
+            .as_ref()
+            .map_or(true, |allowlist| allowlist.contains(account_id))
+    }
+}
+
+impl SeriesSale {
+    // `None` if there are phases, but none of them is active
+    // Without phases the series sale is always public
+    pub fn active_phase(&self) -> Option<Option<(u32, &DropPhase)>> {
+        if self.phases.is_empty() {
+            return Some(None);
+        }
+        self.phases
+            .iter()
+            .enumerate()
+            .find(|(_, phase)| phase.is_active())
+            .map(|(index, phase)| Some((index as u32, phase)))
+    }
+}
+
+#[near_bindgen]
+impl Market {
+    // Replaces the phases of the series sale, an empty list makes it public
+    // The purchases of the wallets are kept by the phase id, so the limits of a phase
+    // can't be reset by reordering the phases
+    #[payable]
+    pub fn set_series_phases(
+        &mut self,
+        nft_contract_id: AccountId,
+        series_id: TokenSeriesId,
+        phases: Vec<DropPhase>,
+    ) {
+        assert_one_yocto();
+        let key = sale_key(&nft_contract_id, &series_id);
+        let mut series_sale = self.market.series_sales.get(&key).expect("No series sale");
+        require!(
+            env::predecessor_account_id() == series_sale.owner_id,
+            "Only the series owner can set the phases"
+        );
+        let mut ids = HashSet::new();
+        for phase in &phases {
+            require!(ids.insert(phase.id), "The phase ids should be unique");
+            if let (Some(start), Some(end)) = (phase.start, phase.end) {
+                require!(start.0 < end.0, "The phase should start before it ends");
+            }
+            require!(
+                phase.wallet_limit != Some(0),
+                "The wallet limit should be positive"
+            );
+        }
+        series_sale.phases = phases;
+        self.market.series_sales.insert(&key, &series_sale);
+    }
+
+    pub fn get_series_phase(
+        &self,
+        nft_contract_id: AccountId,
+        series_id: TokenSeriesId,
+    ) -> Option<ActivePhase> {
+        let series_sale = self
+            .market
+            .series_sales
+            .get(&sale_key(&nft_contract_id, &series_id))?;
+        let (index, phase) = series_sale.active_phase().flatten()?;
+        Some(ActivePhase {
+            index,
+            phase: phase.clone(),
+        })
+    }
+
+    pub fn get_series_allocation(
+        &self,
+        nft_contract_id: AccountId,
+        series_id: TokenSeriesId,
+        account_id: AccountId,
+    ) -> WalletAllocation {
+        let key = sale_key(&nft_contract_id, &series_id);
+        let series_sale = self.market.series_sales.get(&key).expect("No series sale");
+        let (phase, allowed, bought, limit) = match series_sale.active_phase() {
+            None => (None, false, 0, Some(0)),
+            Some(None) => (None, true, 0, None),
+            Some(Some((_, phase))) => (
+                Some(phase.id),
+                phase.is_allowed(&account_id),
+                self.internal_phase_purchases(&key, phase.id, &account_id),
+                phase.wallet_limit,
+            ),
+        };
+        let remaining = if allowed {
+            limit.map_or(series_sale.copies, |limit| {
+                limit.saturating_sub(bought).min(series_sale.copies)
+            })
+        } else {
+            0
+        };
+        WalletAllocation {
+            phase,
+            allowed,
+            bought: U64(bought),
+            remaining: U64(remaining),
+        }
+    }
+}
+
+impl Market {
+    // Checks the active phase for the buyer and counts the purchase,
+    // returns the phase id and its price
+    pub(crate) fn internal_use_phase(
+        &mut self,
+        key: &SaleKey,
+        series_sale: &SeriesSale,
+        buyer_id: &AccountId,
+    ) -> (Option<u32>, Option<U128>) {
+        let phase = match series_sale.active_phase() {
+            None => env::panic_str("No active phase"),
+            Some(None) => return (None, None),
+            Some(Some((_, phase))) => phase,
+        };
+        require!(
+            phase.is_allowed(buyer_id),
+            "The buyer is not on the allowlist of the phase"
+        );
+        let bought = self.internal_phase_purchases(key, phase.id, buyer_id);
+        if let Some(limit) = phase.wallet_limit {
+            require!(bought < limit, "The wallet limit of the phase is reached");
+        }
+        self.market
+            .phase_purchases
+            .insert(&(*key, phase.id, buyer_id.clone()), &(bought + 1));
+        (Some(phase.id), phase.price)
+    }
+
+    // The purchase failed, it doesn't count for the limit
+    pub(crate) fn internal_revert_phase(
+        &mut self,
+        key: &SaleKey,
+        phase: Option<u32>,
+        buyer_id: &AccountId,
+    ) {
+        if let Some(phase_id) = phase {
+            let wallet_key = (*key, phase_id, buyer_id.clone());
+            let bought = self.market.phase_purchases.get(&wallet_key).unwrap_or(0);
+            if bought <= 1 {
+                self.market.phase_purchases.remove(&wallet_key);
+            } else {
+                self.market
+                    .phase_purchases
+                    .insert(&wallet_key, &(bought - 1));
+            }
+        }
+    }
+
+    fn internal_phase_purchases(
+        &self,
+        key: &SaleKey,
+        phase_id: u32,
+        account_id: &AccountId,
+    ) -> u64 {
+        self.market
+            .phase_purchases
+            .get(&(*key, phase_id, account_id.clone()))
+            .unwrap_or(0)
+    }
+}
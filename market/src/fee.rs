@@ -5,13 +5,100 @@ use std::collections::HashMap;
 pub const PAYOUT_TOTAL_VALUE: u128 = 10_000;
 pub const PROTOCOL_FEE: u128 = 300; // 10_000 is 100%, so 300 is 3%
 
-#[derive(Serialize)]
+#[derive(Serialize, Deserialize)]
 #[serde(crate = "near_sdk::serde")]
 pub struct Fees {
     pub buyer: HashMap<AccountId, u32>,
     pub seller: HashMap<AccountId, u32>,
 }
 
+// Both buyer and seller pay the protocol fee in addition to their origins
+pub fn fees_with_protocol(buyer_origins: Origins, seller_origins: &Origins) -> Fees {
+    let mut buyer = buyer_origins;
+    buyer.insert(env::current_account_id(), PROTOCOL_FEE as u32);
+    let mut seller = HashMap::with_capacity(seller_origins.len() + 1);
+    seller.extend(seller_origins.clone());
+    seller.insert(env::current_account_id(), PROTOCOL_FEE as u32);
+    Fees { buyer, seller }
+}
+
+// How the protocol and origin fees are paid when the token is sold
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug))]
+#[serde(crate = "near_sdk::serde")]
+pub enum PayoutMode {
+    // The fees are passed in `memo` of `nft_transfer_payout` and included in its payout,
+    // only the NFT contract of this repo supports it
+    Memo,
+    // Standard NEP-199: the market pays the fees itself and passes the rest as `balance`
+    Standard,
+    // For NFT contracts without payouts: the token is transferred with `nft_transfer`,
+    // the market pays the fees, the royalty from the market-side registry and the seller
+    Transfer,
+}
+
+impl Default for PayoutMode {
+    fn default() -> Self {
+        PayoutMode::Memo
+    }
+}
+
+// The fees of every account, split the same way as in `nft_transfer_payout`:
+// the buyer fees are included in `price`, the seller fees are taken from the rest
+pub fn fee_payouts(price: u128, fees: &Fees) -> HashMap<AccountId, u128> {
+    let buyer_value = calculate_origins(&fees.buyer) as u128;
+    let initial_price = price - price * buyer_value / (PAYOUT_TOTAL_VALUE + buyer_value);
+    let mut payouts = HashMap::new();
+    for (account_id, value) in fees.buyer.iter().chain(fees.seller.iter()) {
+        *payouts.entry(account_id.clone()).or_insert(0) +=
+            initial_price * *value as u128 / PAYOUT_TOTAL_VALUE;
+    }
+    payouts
+}
+
+// The part of `price` paid to the protocol and the origins of both sides
+pub fn calculate_fees_amount(price: u128, fees: &Fees) -> u128 {
+    fee_payouts(price, fees).values().sum()
+}
+
+// `balance` of `nft_transfer_payout`
+pub fn payout_balance(payout_mode: PayoutMode, price: u128, fees: &Fees) -> u128 {
+    match payout_mode {
+        PayoutMode::Memo => price,
+        PayoutMode::Standard | PayoutMode::Transfer => {
+            price - calculate_fees_amount(price, fees)
+        }
+    }
+}
+
+// `memo` of `nft_transfer_payout`
+pub fn payout_memo(payout_mode: PayoutMode, fees: &Fees) -> Option<String> {
+    match payout_mode {
+        PayoutMode::Memo => {
+            Some(near_sdk::serde_json::to_string(fees).expect("Failed to sereailize"))
+        }
+        PayoutMode::Standard | PayoutMode::Transfer => None,
+    }
+}
+
+// The fees which are not included in the payout of the NFT contract and paid by the market,
+// the protocol fee just stays on the market account
+pub fn market_fee_payouts(
+    payout_mode: PayoutMode,
+    price: u128,
+    fees: &Fees,
+) -> HashMap<AccountId, u128> {
+    match payout_mode {
+        PayoutMode::Memo => HashMap::new(),
+        PayoutMode::Standard | PayoutMode::Transfer => {
+            let mut payouts = fee_payouts(price, fees);
+            payouts.remove(&env::current_account_id());
+            payouts.retain(|_, amount| *amount > 0);
+            payouts
+        }
+    }
+}
+
 pub fn calculate_origins(origins: &Origins) -> u32 {
     let mut total: u32 = 0;
     for val in origins.values() {
@@ -40,6 +127,21 @@ impl Market {
     pub fn price_with_fees(&self, price: U128, origins: Option<Origins>) -> U128 {
         calculate_price_with_fees(price, origins.as_ref()).into()
     }
+
+    pub fn set_payout_mode(&mut self, nft_contract_id: AccountId, payout_mode: PayoutMode) {
+        require!(
+            env::predecessor_account_id() == self.market.owner_id,
+            "Only the market owner can set the payout mode"
+        );
+        self.market.payout_modes.insert(&nft_contract_id, &payout_mode);
+    }
+
+    pub fn get_payout_mode(&self, nft_contract_id: AccountId) -> PayoutMode {
+        self.market
+            .payout_modes
+            .get(&nft_contract_id)
+            .unwrap_or_default()
+    }
 }
 
 // pub fn with_fees(price: u128) -> u128 {
@@ -1,25 +1,33 @@
+use crate::sale::contract_and_token_id_to_key;
 use crate::*;
 
 #[near_bindgen]
 impl Market {
 
     pub fn hack_finish_sale(&mut self, nft_contract_token: ContractAndTokenId) {
-        let mut sale = self.market.sales.get(&nft_contract_token).expect("no sale");
+        let sale_key = contract_and_token_id_to_key(&nft_contract_token);
+        let mut sale = self.market.sales.get(&sale_key).expect("no sale");
         sale.end = Some(env::block_timestamp());
-        self.market.sales.insert(&nft_contract_token, &sale);
+        self.market.sales.insert(&sale_key, &sale);
+    }
+
+    pub fn hack_lock_sale(&mut self, nft_contract_token: ContractAndTokenId, locked_at: U64) {
+        let sale_key = contract_and_token_id_to_key(&nft_contract_token);
+        let mut sale = self.market.sales.get(&sale_key).expect("no sale");
+        sale.locked_at = Some(locked_at.0);
+        self.market.sales.insert(&sale_key, &sale);
     }
 
     pub fn hack_finish_bid(&mut self, nft_contract_token: ContractAndTokenId) {
-        let mut sale = self.market.sales.get(&nft_contract_token).expect("no sale");
-        let bid = sale
-            .bids
-            .get_mut(&("near".parse().unwrap()))
-            .expect("no bids")
-            .last_mut();
-        if let Some(bid) = bid {
+        let bids_key = (
+            contract_and_token_id_to_key(&nft_contract_token),
+            "near".parse().unwrap(),
+        );
+        let mut bids = self.market.bids.get(&bids_key).expect("no bids");
+        if let Some(bid) = bids.last_mut() {
             bid.end = Some(U64(env::block_timestamp()))
         }
-        self.market.sales.insert(&nft_contract_token, &sale);
+        self.market.bids.insert(&bids_key, &bids);
     }
 
     pub fn hack_finish_auction(&mut self, auction_id: U128) {
@@ -0,0 +1,265 @@
+#![allow(clippy::too_many_arguments)]
+use crate::fee::{calculate_fees_amount, Fees};
+use crate::pagination::{take_page, Page};
+use crate::sale::{sale_key, FungibleTokenId, Payout};
+use crate::*;
+use near_contract_standards::non_fungible_token::hash_account_id;
+use near_sdk::serde_json::json;
+
+// The number of the latest trades kept, the oldest trade is replaced by a new one
+pub const TRADE_HISTORY_LENGTH: u64 = 10_000;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub struct Trade {
+    pub id: U64,
+    pub nft_contract_id: AccountId,
+    pub token_id: TokenId,
+    pub seller_id: AccountId,
+    pub buyer_id: AccountId,
+    pub ft_token_id: FungibleTokenId,
+    // Paid by the buyer, including the buyer fees
+    pub price: U128,
+    // Protocol and origin fees of both sides
+    pub fees: U128,
+    pub royalties: U128,
+    pub timestamp: U64,
+}
+
+// The trade ids of one token or account from the oldest to the latest.
+// A trade is only replaced after all the older ones, so ids are dropped from the front,
+// and every trade takes the same gas and storage however long the history of the key is.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct TradeIds {
+    // The position of the oldest id
+    first: u64,
+    len: u64,
+    ids: LookupMap<u64, u64>,
+}
+
+impl TradeIds {
+    fn new(prefix: StorageKey) -> Self {
+        Self {
+            first: 0,
+            len: 0,
+            ids: LookupMap::new(prefix),
+        }
+    }
+
+    fn push(&mut self, id: u64) {
+        self.ids.insert(&(self.first + self.len), &id);
+        self.len += 1;
+    }
+
+    fn remove_oldest(&mut self, id: u64) {
+        if self.len > 0 && self.ids.get(&self.first) == Some(id) {
+            self.ids.remove(&self.first);
+            self.first += 1;
+            self.len -= 1;
+        }
+    }
+
+    // `index` 0 is the oldest id
+    fn get(&self, index: u64) -> u64 {
+        self.ids.get(&(self.first + index)).expect("No trade id")
+    }
+
+    // The number of the ids lower than `id`, the ids grow from the oldest to the latest
+    fn count_below(&self, id: u64) -> u64 {
+        let (mut low, mut high) = (0, self.len);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            if self.get(middle) < id {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+        low
+    }
+}
+
+#[near_bindgen]
+impl Market {
+    // All views return the latest trades first, the cursor is the id of the last trade on the page
+    pub fn get_trades(&self, cursor: Option<U64>, limit: Option<u64>) -> Page<Trade> {
+        let next_trade_id = self.market.next_trade_id;
+        let oldest = next_trade_id.saturating_sub(TRADE_HISTORY_LENGTH);
+        let before = cursor.map_or(next_trade_id, |cursor| cursor.0.min(next_trade_id));
+        let (items, next_cursor) = take_page(
+            (oldest..before).rev().map(|id| self.internal_get_trade(id)),
+            limit,
+            |trade| trade.id,
+        );
+        Page { items, next_cursor }
+    }
+
+    pub fn get_trades_by_token(
+        &self,
+        nft_contract_id: AccountId,
+        token_id: TokenId,
+        cursor: Option<U64>,
+        limit: Option<u64>,
+    ) -> Page<Trade> {
+        let ids = self
+            .market
+            .trades_by_token
+            .get(&sale_key(&nft_contract_id, &token_id));
+        self.trades_page(ids.as_ref(), cursor, limit)
+    }
+
+    pub fn get_trades_by_buyer(
+        &self,
+        account_id: AccountId,
+        cursor: Option<U64>,
+        limit: Option<u64>,
+    ) -> Page<Trade> {
+        let ids = self.market.trades_by_buyer.get(&account_id);
+        self.trades_page(ids.as_ref(), cursor, limit)
+    }
+
+    pub fn get_trades_by_seller(
+        &self,
+        account_id: AccountId,
+        cursor: Option<U64>,
+        limit: Option<u64>,
+    ) -> Page<Trade> {
+        let ids = self.market.trades_by_seller.get(&account_id);
+        self.trades_page(ids.as_ref(), cursor, limit)
+    }
+}
+
+impl Market {
+    fn trades_page(
+        &self,
+        ids: Option<&TradeIds>,
+        cursor: Option<U64>,
+        limit: Option<u64>,
+    ) -> Page<Trade> {
+        let ids = match ids {
+            Some(ids) => ids,
+            None => {
+                return Page {
+                    items: vec![],
+                    next_cursor: None,
+                }
+            }
+        };
+        let below = cursor.map_or(ids.len, |cursor| ids.count_below(cursor.0));
+        let (items, next_cursor) = take_page(
+            (0..below)
+                .rev()
+                .map(|index| self.internal_get_trade(ids.get(index))),
+            limit,
+            |trade| trade.id,
+        );
+        Page { items, next_cursor }
+    }
+
+    fn internal_get_trade(&self, id: u64) -> Trade {
+        self.market
+            .trades
+            .get(&(id % TRADE_HISTORY_LENGTH))
+            .expect("No trade")
+    }
+
+    pub(crate) fn internal_add_trade(
+        &mut self,
+        nft_contract_id: AccountId,
+        token_id: TokenId,
+        seller_id: AccountId,
+        buyer_id: AccountId,
+        ft_token_id: FungibleTokenId,
+        price: u128,
+        fees: &Fees,
+        payout: &Payout,
+    ) {
+        let id = self.market.next_trade_id;
+        self.market.next_trade_id += 1;
+        let slot = id % TRADE_HISTORY_LENGTH;
+        if let Some(oldest) = self.market.trades.get(&slot) {
+            self.internal_remove_trade_from_indices(&oldest);
+        }
+
+        let fees = calculate_fees_amount(price, fees);
+        let seller_payout = payout.payout.get(&seller_id).map_or(0, |amount| amount.0);
+        let trade = Trade {
+            id: U64(id),
+            nft_contract_id,
+            token_id,
+            seller_id,
+            buyer_id,
+            ft_token_id,
+            price: U128(price),
+            fees: U128(fees),
+            royalties: U128(price.saturating_sub(fees).saturating_sub(seller_payout)),
+            timestamp: U64(env::block_timestamp()),
+        };
+        self.market.trades.insert(&slot, &trade);
+
+        let token_key = sale_key(&trade.nft_contract_id, &trade.token_id);
+        push_trade_id(
+            &mut self.market.trades_by_token,
+            &token_key,
+            id,
+            StorageKey::TradesByTokenInner {
+                sale_key: token_key,
+            },
+        );
+        push_trade_id(
+            &mut self.market.trades_by_buyer,
+            &trade.buyer_id,
+            id,
+            StorageKey::TradesByBuyerInner {
+                account_id_hash: hash_account_id(&trade.buyer_id),
+            },
+        );
+        push_trade_id(
+            &mut self.market.trades_by_seller,
+            &trade.seller_id,
+            id,
+            StorageKey::TradesBySellerInner {
+                account_id_hash: hash_account_id(&trade.seller_id),
+            },
+        );
+
+        env::log_str(
+            &json!({
+                "type": "trade",
+                "params": trade,
+            })
+            .to_string(),
+        );
+    }
+
+    // The replaced trade is the oldest one in every index
+    fn internal_remove_trade_from_indices(&mut self, trade: &Trade) {
+        let token_key = sale_key(&trade.nft_contract_id, &trade.token_id);
+        remove_trade_id(&mut self.market.trades_by_token, &token_key, trade.id.0);
+        remove_trade_id(&mut self.market.trades_by_buyer, &trade.buyer_id, trade.id.0);
+        remove_trade_id(&mut self.market.trades_by_seller, &trade.seller_id, trade.id.0);
+    }
+}
+
+fn push_trade_id<K: BorshSerialize>(
+    index: &mut LookupMap<K, TradeIds>,
+    key: &K,
+    id: u64,
+    inner_key: StorageKey,
+) {
+    let mut ids = index.get(key).unwrap_or_else(|| TradeIds::new(inner_key));
+    ids.push(id);
+    index.insert(key, &ids);
+}
+
+fn remove_trade_id<K: BorshSerialize>(index: &mut LookupMap<K, TradeIds>, key: &K, id: u64) {
+    if let Some(mut ids) = index.get(key) {
+        ids.remove_oldest(id);
+        if ids.len == 0 {
+            index.remove(key);
+        } else {
+            index.insert(key, &ids);
+        }
+    }
+}
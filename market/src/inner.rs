@@ -1,27 +1,28 @@
-use crate::bid::Bid;
+use near_contract_standards::non_fungible_token::hash_account_id;
+
+use crate::bid::{Bid, Bids};
 use crate::common::*;
-use crate::sale::{Sale, DELIMETER};
-use crate::Market;
+use crate::sale::{sale_key, BidsKey, FungibleTokenId, Sale, SaleKey, TokenType};
+use crate::settlement::PendingTransfer;
+use crate::{Market, StorageKey, STORAGE_PER_SALE};
 
 impl Market {
+    // Removes the sale together with all its bids
     pub(crate) fn internal_remove_sale(
         &mut self,
         nft_contract_id: AccountId,
         token_id: TokenId,
-    ) -> Sale {
-        let contract_and_token_id = format!("{}{}{}", &nft_contract_id, DELIMETER, token_id);
-        let sale = self
-            .market
-            .sales
-            .remove(&contract_and_token_id)
-            .expect("No sale");
+    ) -> (Sale, Bids) {
+        let sale_key = sale_key(&nft_contract_id, &token_id);
+        let sale = self.market.sales.remove(&sale_key).expect("No sale");
+        self.internal_unindex_sale(&sale_key, &sale);
 
         let mut by_owner_id = self
             .market
             .by_owner_id
             .get(&sale.owner_id)
             .expect("No sale by_owner_id");
-        by_owner_id.remove(&contract_and_token_id);
+        by_owner_id.remove(&sale_key);
         if by_owner_id.is_empty() {
             self.market.by_owner_id.remove(&sale.owner_id);
         } else {
@@ -33,7 +34,7 @@ impl Market {
             .by_nft_contract_id
             .get(&nft_contract_id)
             .expect("No sale by nft_contract_id");
-        by_nft_contract_id.remove(&token_id);
+        by_nft_contract_id.remove(&sale_key);
         if by_nft_contract_id.is_empty() {
             self.market.by_nft_contract_id.remove(&nft_contract_id);
         } else {
@@ -42,64 +43,283 @@ impl Market {
                 .insert(&nft_contract_id, &by_nft_contract_id);
         }
 
-        // here AccountId is used as "token type", idk why so (adsick)
-        if let Some(token_type) = sale.token_type.to_owned() {
+        self.internal_unindex_token_type(&sale_key, &sale.token_type);
+
+        let bids = self.internal_remove_bids(&sale_key);
+        (sale, bids)
+    }
+
+    // here AccountId is used as "token type", idk why so (adsick)
+    pub(crate) fn internal_index_token_type(
+        &mut self,
+        sale_key: &SaleKey,
+        token_id: &str,
+        token_type: &TokenType,
+    ) {
+        if let Some(token_type) = token_type {
+            assert!(
+                token_id.contains(token_type.as_str()),
+                "TokenType should be substr of TokenId"
+            );
+            let mut by_nft_token_type = self
+                .market
+                .by_nft_token_type
+                .get(token_type)
+                .unwrap_or_else(|| {
+                    TreeMap::new(
+                        StorageKey::ByNFTTokenTypeInner {
+                            token_type_hash: hash_account_id(&AccountId::new_unchecked(
+                                token_type.clone(),
+                            )),
+                        }
+                        .try_to_vec()
+                        .unwrap(),
+                    )
+                });
+            by_nft_token_type.insert(sale_key, &());
+            self.market
+                .by_nft_token_type
+                .insert(token_type, &by_nft_token_type);
+        }
+    }
+
+    pub(crate) fn internal_unindex_token_type(
+        &mut self,
+        sale_key: &SaleKey,
+        token_type: &TokenType,
+    ) {
+        if let Some(token_type) = token_type {
             let mut by_nft_token_type = self
                 .market
                 .by_nft_token_type
-                .get(&token_type)
+                .get(token_type)
                 .expect("No sale by nft_token_type");
-            by_nft_token_type.remove(&contract_and_token_id);
+            by_nft_token_type.remove(sale_key);
             if by_nft_token_type.is_empty() {
-                self.market.by_nft_token_type.remove(&token_type);
+                self.market.by_nft_token_type.remove(token_type);
             } else {
                 self.market
                     .by_nft_token_type
-                    .insert(&token_type, &by_nft_token_type);
+                    .insert(token_type, &by_nft_token_type);
             }
         }
+    }
 
+    // The number of sales and other listings (series sales, raffles and swaps)
+    // of the account, each of them takes `STORAGE_PER_SALE`
+    pub(crate) fn internal_supply_with_listings(&self, account_id: &AccountId) -> u64 {
+        self.get_supply_by_owner_id(account_id.clone()).0
+            + self
+                .market
+                .listings_by_owner_id
+                .get(account_id)
+                .unwrap_or(0)
+    }
+
+    // A series sale, a raffle or a swap takes the storage of one sale
+    pub(crate) fn internal_add_listing_storage(&mut self, owner_id: &AccountId) {
+        let owner_paid_storage = self.market.storage_deposits.get(owner_id).unwrap_or(0);
+        let owner_storage_required =
+            (self.internal_supply_with_listings(owner_id) + 1) as u128 * STORAGE_PER_SALE;
+        assert!(
+            owner_paid_storage >= owner_storage_required,
+            "Insufficient storage paid: {}, for {} sales at {} rate of per sale",
+            owner_paid_storage,
+            owner_storage_required / STORAGE_PER_SALE,
+            STORAGE_PER_SALE
+        );
+        let listings_count = self.market.listings_by_owner_id.get(owner_id).unwrap_or(0);
+        self.market
+            .listings_by_owner_id
+            .insert(owner_id, &(listings_count + 1));
+    }
+
+    pub(crate) fn internal_release_listing_storage(&mut self, owner_id: &AccountId) {
+        let listings_count = self.market.listings_by_owner_id.get(owner_id).unwrap_or(0);
+        if listings_count <= 1 {
+            self.market.listings_by_owner_id.remove(owner_id);
+        } else {
+            self.market
+                .listings_by_owner_id
+                .insert(owner_id, &(listings_count - 1));
+        }
+    }
+
+    // The sale stays listed with its bids until the purchase is resolved
+    pub(crate) fn internal_lock_sale(
+        &mut self,
+        nft_contract_id: &AccountId,
+        token_id: &str,
+    ) -> Sale {
+        let sale_key = sale_key(nft_contract_id, token_id);
+        let mut sale = self.market.sales.get(&sale_key).expect("No sale");
+        require!(!sale.is_locked(), "The sale is locked by a purchase");
+        sale.locked_at = Some(env::block_timestamp());
+        self.market.sales.insert(&sale_key, &sale);
         sale
     }
 
+    // The stored sale still has the lock of the purchase of `sale`,
+    // it could be removed, replaced or bought again after the lock has expired
+    fn internal_holds_lock(&self, sale: &Sale) -> bool {
+        self.market
+            .sales
+            .get(&sale_key(&sale.nft_contract_id, &sale.token_id))
+            .map_or(false, |stored| stored.locked_at == sale.locked_at)
+    }
+
+    pub(crate) fn internal_unlock_sale(&mut self, sale: &Sale) {
+        if self.internal_holds_lock(sale) {
+            let sale_key = sale_key(&sale.nft_contract_id, &sale.token_id);
+            let mut sale = self.market.sales.get(&sale_key).expect("No sale");
+            sale.locked_at = None;
+            self.market.sales.insert(&sale_key, &sale);
+        }
+    }
+
+    // Removes the sale locked by the purchase, no bids if it is not there anymore
+    pub(crate) fn internal_remove_locked_sale(&mut self, sale: &Sale) -> Bids {
+        if self.internal_holds_lock(sale) {
+            let (_, bids) =
+                self.internal_remove_sale(sale.nft_contract_id.clone(), sale.token_id.clone());
+            bids
+        } else {
+            Bids::new()
+        }
+    }
+
+    // Bids of the sale in all supported fungible tokens
+    pub(crate) fn internal_get_bids(&self, sale_key: &SaleKey) -> Bids {
+        self.market
+            .ft_token_ids
+            .iter()
+            .filter_map(|ft_token_id| {
+                self.market
+                    .bids
+                    .get(&(*sale_key, ft_token_id.clone()))
+                    .map(|bids| (ft_token_id, bids))
+            })
+            .collect()
+    }
+
+    pub(crate) fn internal_remove_bids(&mut self, sale_key: &SaleKey) -> Bids {
+        let ft_token_ids = self.market.ft_token_ids.to_vec();
+        ft_token_ids
+            .into_iter()
+            .filter_map(|ft_token_id| {
+                let bids = self.internal_set_bids(&(*sale_key, ft_token_id.clone()), vec![]);
+                if bids.is_empty() {
+                    None
+                } else {
+                    Some((ft_token_id, bids))
+                }
+            })
+            .collect()
+    }
+
     pub(crate) fn internal_remove_bid(
         &mut self,
         nft_contract_id: AccountId,
         ft_token_id: &AccountId,
         token_id: TokenId,
         owner_id: &AccountId,
-        price: U128
+        price: U128,
     ) -> Option<Bid> {
-        let contract_and_token_id = format!("{}{}{}", &nft_contract_id, DELIMETER, token_id);
-        let sale = self
-            .market
-            .sales
-            .get(&contract_and_token_id)
-            .expect("No sale");
-        let bid_vec = sale.bids.get(ft_token_id).expect("No token");
+        let sale_key = sale_key(&nft_contract_id, &token_id);
+        require!(self.market.sales.get(&sale_key).is_some(), "No sale");
+        let bids_key = (sale_key, ft_token_id.clone());
+        let mut bid_vec = self.market.bids.get(&bids_key).expect("No token");
+        // shouldn't allow bids with equal price
+        let index = bid_vec
+            .iter()
+            .position(|bid_from_vec| {
+                &bid_from_vec.owner_id == owner_id && bid_from_vec.price == price
+            })?;
+        let bid = bid_vec.remove(index);
+        self.internal_set_bids(&bids_key, bid_vec);
+        Some(bid)
+    }
 
-        let mut sale = self
-            .market
-            .sales
-            .get(&contract_and_token_id)
-            .expect("No sale");
-        for (index, bid_from_vec) in bid_vec.iter().enumerate() {
-            if &(bid_from_vec.owner_id) == owner_id && bid_from_vec.price == price {
-                if bid_vec.len() == 1 {
-                    //If the vector contained only one bid, should remove ft_token_id from the HashMap
-                    sale.bids.remove(ft_token_id);
-                } else {
-                    //If there are several bids for this ft_token_id, should remove one bid
-                    sale.bids
-                        .get_mut(ft_token_id)
-                        .expect("No token")
-                        .remove(index);
-                };
-                self.market.sales.insert(&contract_and_token_id, &sale);
-                //break; // shouldn't allow bids with equal price 
-                return Some((*bid_from_vec).clone());
-            };
+    // Keeps the bids of the sale which satisfy `keep`, returns the refunds of the rest
+    pub(crate) fn internal_retain_bids(
+        &mut self,
+        sale_key: &SaleKey,
+        keep: impl Fn(&FungibleTokenId, &Bid) -> bool,
+    ) -> Vec<PendingTransfer> {
+        let mut refunds = vec![];
+        for (ft_token_id, bids) in self.internal_get_bids(sale_key) {
+            let (kept, removed): (Vec<Bid>, Vec<Bid>) =
+                bids.into_iter().partition(|bid| keep(&ft_token_id, bid));
+            if removed.is_empty() {
+                continue;
+            }
+            refunds.extend(removed.into_iter().map(|bid| PendingTransfer {
+                ft_token_id: ft_token_id.clone(),
+                receiver_id: bid.owner_id,
+                amount: bid.price,
+            }));
+            self.internal_set_bids(&(*sale_key, ft_token_id), kept);
+        }
+        refunds
+    }
+
+    // Writes the bids of the sale for one fungible token, an empty list removes them
+    // Keeps `bids_by_owner_id` in sync, returns the previous bids
+    pub(crate) fn internal_set_bids(&mut self, bids_key: &BidsKey, bids: Vec<Bid>) -> Vec<Bid> {
+        let previous = if bids.is_empty() {
+            self.market.bids.remove(bids_key)
+        } else {
+            self.market.bids.insert(bids_key, &bids)
+        }
+        .unwrap_or_default();
+        for bid in previous.iter() {
+            if !bids.iter().any(|kept| kept.owner_id == bid.owner_id) {
+                account_index_remove(&mut self.market.bids_by_owner_id, &bid.owner_id, bids_key);
+            }
+        }
+        for bid in bids.iter() {
+            if !previous.iter().any(|old| old.owner_id == bid.owner_id) {
+                account_index_insert(
+                    &mut self.market.bids_by_owner_id,
+                    &bid.owner_id,
+                    bids_key,
+                    StorageKey::BidsByOwnerIdInner {
+                        account_id_hash: hash_account_id(&bid.owner_id),
+                    },
+                );
+            }
+        }
+        previous
+    }
+}
+
+// Adds the item to the set of the account in an index like `bids_by_owner_id`,
+// `inner_key` is the prefix of a new set
+pub(crate) fn account_index_insert<T: Ord + Clone + BorshSerialize + BorshDeserialize>(
+    index: &mut LookupMap<AccountId, TreeMap<T, ()>>,
+    account_id: &AccountId,
+    item: &T,
+    inner_key: StorageKey,
+) {
+    let mut items = index
+        .get(account_id)
+        .unwrap_or_else(|| TreeMap::new(inner_key.try_to_vec().unwrap()));
+    items.insert(item, &());
+    index.insert(account_id, &items);
+}
+
+// Removes the item, an empty set is removed from the index
+pub(crate) fn account_index_remove<T: Ord + Clone + BorshSerialize + BorshDeserialize>(
+    index: &mut LookupMap<AccountId, TreeMap<T, ()>>,
+    account_id: &AccountId,
+    item: &T,
+) {
+    if let Some(mut items) = index.get(account_id) {
+        items.remove(item);
+        if items.is_empty() {
+            index.remove(account_id);
+        } else {
+            index.insert(account_id, &items);
         }
-        None
     }
 }
@@ -0,0 +1,117 @@
+use std::collections::HashMap;
+
+use near_sdk::serde_json::json;
+use near_sdk::{promise_result_as_success, Gas};
+
+use crate::sale::{ext_contract, ext_self, GAS_FOR_FT_TRANSFER, NO_DEPOSIT};
+use crate::*;
+
+pub const GAS_FOR_RESOLVE_TRANSFER: Gas = Gas(5_000_000_000_000);
+
+// Funds of one account in every currency
+pub type Claimable = HashMap<FungibleTokenId, Balance>;
+
+#[near_bindgen]
+impl Market {
+    // Withdraws the funds which the market failed to transfer to the account
+    #[payable]
+    pub fn withdraw(&mut self, ft_token_id: FungibleTokenId, amount: U128) {
+        assert_one_yocto();
+        let account_id = env::predecessor_account_id();
+        let mut claimable = self.market.claimable.get(&account_id).unwrap_or_default();
+        let balance = claimable.get(&ft_token_id).copied().unwrap_or(0);
+        require!(amount.0 > 0, "The amount should be positive");
+        require!(amount.0 <= balance, "Not enough claimable balance");
+        if balance == amount.0 {
+            claimable.remove(&ft_token_id);
+        } else {
+            claimable.insert(ft_token_id.clone(), balance - amount.0);
+        }
+        if claimable.is_empty() {
+            self.market.claimable.remove(&account_id);
+        } else {
+            self.market.claimable.insert(&account_id, &claimable);
+        }
+        self.internal_transfer(ft_token_id, account_id, amount);
+    }
+
+    pub fn get_claimable(&self, account_id: AccountId) -> HashMap<FungibleTokenId, U128> {
+        self.market
+            .claimable
+            .get(&account_id)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(ft_token_id, amount)| (ft_token_id, U128(amount)))
+            .collect()
+    }
+
+    // self callback
+    // If the transfer failed, the funds can be withdrawn later
+    #[private]
+    pub fn resolve_transfer(
+        &mut self,
+        ft_token_id: FungibleTokenId,
+        receiver_id: AccountId,
+        amount: U128,
+    ) -> bool {
+        if promise_result_as_success().is_some() {
+            return true;
+        }
+        self.internal_credit(&receiver_id, &ft_token_id, amount.0);
+        env::log_str(
+            &json!({
+                "type": "transfer_failed",
+                "params": {
+                    "receiver_id": receiver_id,
+                    "ft_token_id": ft_token_id,
+                    "amount": amount,
+                }
+            })
+            .to_string(),
+        );
+        false
+    }
+}
+
+impl Market {
+    // Transfers NEAR or the fungible token from the market,
+    // the amount is credited to the ledger if the transfer fails
+    pub(crate) fn internal_transfer(
+        &mut self,
+        ft_token_id: FungibleTokenId,
+        receiver_id: AccountId,
+        amount: U128,
+    ) {
+        let transfer = if ft_token_id.as_str() == "near" {
+            Promise::new(receiver_id.clone()).transfer(amount.0)
+        } else {
+            ext_contract::ft_transfer(
+                receiver_id.clone(),
+                amount,
+                None,
+                ft_token_id.clone(),
+                1,
+                GAS_FOR_FT_TRANSFER,
+            )
+        };
+        transfer.then(ext_self::resolve_transfer(
+            ft_token_id,
+            receiver_id,
+            amount,
+            env::current_account_id(),
+            NO_DEPOSIT,
+            GAS_FOR_RESOLVE_TRANSFER,
+        ));
+    }
+
+    pub(crate) fn internal_credit(
+        &mut self,
+        account_id: &AccountId,
+        ft_token_id: &FungibleTokenId,
+        amount: Balance,
+    ) {
+        let mut claimable = self.market.claimable.get(account_id).unwrap_or_default();
+        *claimable.entry(ft_token_id.clone()).or_insert(0) += amount;
+        self.market.claimable.insert(account_id, &claimable);
+    }
+}
@@ -1,12 +1,29 @@
 mod auction;
 mod auction_views;
 mod bid;
+mod bid_views;
+mod bulk;
+mod cleanup;
 mod common;
+mod custody;
+mod drop_phase;
 mod fee;
+mod history;
 mod inner;
+mod ledger;
 mod market_core;
+mod origin;
+mod pagination;
+mod quote;
+mod raffle;
+mod royalty;
 mod sale;
 mod sale_views;
+mod search;
+mod series_sale;
+mod settlement;
+mod stats;
+mod swap;
 mod token;
 
 mod hack; // TODO: remove
@@ -14,12 +31,42 @@ mod hack; // TODO: remove
 use common::*;
 
 use crate::sale::{Sale, SaleConditions, TokenType,
-    ContractAndTokenId, FungibleTokenId};
+    ContractAndTokenId, FungibleTokenId, SaleKey, BidsKey};
 use crate::auction::Auction;
-pub use crate::sale::{SaleJson, BID_HISTORY_LENGTH_DEFAULT};
+use crate::ledger::Claimable;
+use crate::series_sale::SeriesSale;
+use crate::drop_phase::PhaseWalletKey;
+use crate::origin::OriginFee;
+use crate::raffle::Raffle;
+use crate::swap::Swap;
+pub use crate::bid::Bid;
+use crate::search::{PriceIndexKey, SortKey};
+use crate::stats::{Stats, StatsKey};
+use crate::history::TradeIds;
+pub use crate::sale::{
+    PurchaseArgs, ReservedBuyer, SaleJson, BID_HISTORY_LENGTH_DEFAULT, SALE_LOCK_TIMEOUT,
+};
 pub use crate::market_core::{ArgsKind, SaleArgs, AuctionArgs};
-pub use crate::auction::{AuctionJson, EXTENSION_DURATION};
-pub use crate::fee::{Fees, PAYOUT_TOTAL_VALUE, PROTOCOL_FEE};
+pub use crate::auction::{AuctionFallback, AuctionJson, EXTENSION_DURATION};
+pub use crate::fee::{Fees, PayoutMode, PAYOUT_TOTAL_VALUE, PROTOCOL_FEE};
+pub use crate::bid_views::{AccountAuctionBid, AccountBid, AccountBidsCursor, BidStatus};
+pub use crate::bulk::{BulkRemoval, BULK_REMOVAL_MAX};
+pub use crate::cleanup::{CleanedListing, CleanupCursor, KEEPER_REWARD_DEFAULT};
+pub use crate::pagination::{Page, PAGE_SIZE_DEFAULT, PAGE_SIZE_MAX};
+pub use crate::search::{SaleFilter, SaleSort, SearchCursor, SEARCH_SCAN_MAX};
+pub use crate::stats::{CollectionStats, TradeStats};
+pub use crate::swap::{SwapArgs, SwapToken, SWAP_TOKENS_MAX};
+pub use crate::history::{Trade, TRADE_HISTORY_LENGTH};
+pub use crate::origin::{OriginJson, ORIGIN_FEE_MAX};
+pub use crate::quote::PurchaseQuote;
+pub use crate::raffle::{RaffleJson, RaffleStatus, RAFFLE_ENTRIES_MAX, RAFFLE_MINT_BATCH};
+pub use crate::royalty::{Royalty, MAXIMUM_ROYALTY};
+pub use crate::series_sale::{SeriesSaleJson, STORAGE_FOR_MINT};
+pub use crate::drop_phase::{ActivePhase, DropPhase, WalletAllocation};
+pub use crate::settlement::{
+    PendingTransfer, Settlement, MAX_PAYOUT_LEN_DEFAULT, MAX_PAYOUT_LEN_LIMIT,
+    SETTLEMENT_BATCH_SIZE,
+};
 
 const STORAGE_PER_SALE: u128 = 1000 * STORAGE_PRICE_PER_BYTE;
 
@@ -38,21 +85,93 @@ pub enum StorageKey {
     OriginFees,
     Auctions,
     AuctionId,
+    Bids,
+    ByPrice,
+    ByPriceInner { index_hash: CryptoHash },
+    ByCreatedAt,
+    Stats,
+    Trades,
+    TradesByToken,
+    TradesByBuyer,
+    TradesBySeller,
+    PayoutModes,
+    CollectionRoyalties,
+    CollectionCreators,
+    Settlements,
+    Claimable,
+    TokenRoyalties,
+    SeriesSales,
+    ListingsByOwnerId,
+    PhasePurchases,
+    Raffles,
+    RaffleEntriesInner { raffle_id: u64 },
+    Swaps,
+    SwapsByToken,
+    BidsByOwnerId,
+    BidsByOwnerIdInner { account_id_hash: CryptoHash },
+    AuctionsByOwnerId,
+    AuctionsByOwnerIdInner { account_id_hash: CryptoHash },
+    AuctionBidsByOwnerId,
+    AuctionBidsByOwnerIdInner { account_id_hash: CryptoHash },
+    TradesByTokenInner { sale_key: SaleKey },
+    TradesByBuyerInner { account_id_hash: CryptoHash },
+    TradesBySellerInner { account_id_hash: CryptoHash },
 }
 
 #[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
 pub struct MarketSales {
     pub owner_id: AccountId,
-    pub sales: UnorderedMap<ContractAndTokenId, Sale>,
-    pub by_owner_id: LookupMap<AccountId, UnorderedSet<ContractAndTokenId>>,
-    pub by_nft_contract_id: LookupMap<AccountId, UnorderedSet<TokenId>>,
-    pub by_nft_token_type: LookupMap<String, UnorderedSet<ContractAndTokenId>>,
+    pub sales: UnorderedMap<SaleKey, Sale>,
+    pub bids: LookupMap<BidsKey, Vec<Bid>>,
+    pub bids_by_owner_id: LookupMap<AccountId, TreeMap<BidsKey, ()>>,
+    pub by_owner_id: LookupMap<AccountId, TreeMap<SaleKey, ()>>,
+    pub by_nft_contract_id: LookupMap<AccountId, TreeMap<SaleKey, ()>>,
+    pub by_nft_token_type: LookupMap<String, TreeMap<SaleKey, ()>>,
+    pub by_price: LookupMap<PriceIndexKey, TreeMap<SortKey, ()>>,
+    pub by_created_at: TreeMap<SortKey, ()>,
+    pub stats: LookupMap<StatsKey, Stats>,
+
+    pub trades: LookupMap<u64, Trade>,
+    pub next_trade_id: u64,
+    pub trades_by_token: LookupMap<SaleKey, TradeIds>,
+    pub trades_by_buyer: LookupMap<AccountId, TradeIds>,
+    pub trades_by_seller: LookupMap<AccountId, TradeIds>,
+
+    pub payout_modes: LookupMap<AccountId, PayoutMode>,
+    pub collection_royalties: LookupMap<AccountId, Royalty>,
+    pub collection_creators: LookupMap<AccountId, AccountId>,
+    pub token_royalties: LookupMap<SaleKey, Royalty>,
+
+    pub settlements: TreeMap<u64, Settlement>,
+    pub next_settlement_id: u64,
+    pub max_payout_len: u32,
+    pub claimable: LookupMap<AccountId, Claimable>,
+
+    pub origin_fees: TreeMap<AccountId, OriginFee>,
+    pub max_origin_fee: u32,
+
     pub ft_token_ids: UnorderedSet<FungibleTokenId>,
     pub storage_deposits: LookupMap<AccountId, Balance>,
     pub bid_history_length: u8,
 
-    pub auctions: UnorderedMap<u128, Auction>,
+    pub auctions: TreeMap<u128, Auction>,
     pub next_auction_id: u128,
+    pub auctions_by_owner_id: LookupMap<AccountId, TreeMap<u128, ()>>,
+    pub auction_bids_by_owner_id: LookupMap<AccountId, TreeMap<u128, ()>>,
+
+    pub series_sales: TreeMap<SaleKey, SeriesSale>,
+    // series sales, raffles and swaps of the account, they are paid as sales
+    pub listings_by_owner_id: LookupMap<AccountId, u64>,
+    pub phase_purchases: LookupMap<PhaseWalletKey, u64>,
+
+    pub raffles: TreeMap<u64, Raffle>,
+    pub next_raffle_id: u64,
+
+    pub swaps: TreeMap<u64, Swap>,
+    pub next_swap_id: u64,
+    pub swaps_by_token: LookupMap<SaleKey, Vec<u64>>,
+
+    pub keeper_reward: Balance,
 }
 
 #[near_bindgen]
@@ -73,14 +192,45 @@ impl Market {
         let market = MarketSales {
             owner_id,
             sales: UnorderedMap::new(StorageKey::Sales),
+            bids: LookupMap::new(StorageKey::Bids),
+            bids_by_owner_id: LookupMap::new(StorageKey::BidsByOwnerId),
             by_owner_id: LookupMap::new(StorageKey::ByOwnerId),
             by_nft_contract_id: LookupMap::new(StorageKey::ByNFTContractId),
             by_nft_token_type: LookupMap::new(StorageKey::ByNFTTokenType),
+            by_price: LookupMap::new(StorageKey::ByPrice),
+            by_created_at: TreeMap::new(StorageKey::ByCreatedAt),
+            stats: LookupMap::new(StorageKey::Stats),
+            trades: LookupMap::new(StorageKey::Trades),
+            next_trade_id: 0,
+            trades_by_token: LookupMap::new(StorageKey::TradesByToken),
+            trades_by_buyer: LookupMap::new(StorageKey::TradesByBuyer),
+            trades_by_seller: LookupMap::new(StorageKey::TradesBySeller),
+            payout_modes: LookupMap::new(StorageKey::PayoutModes),
+            collection_royalties: LookupMap::new(StorageKey::CollectionRoyalties),
+            collection_creators: LookupMap::new(StorageKey::CollectionCreators),
+            token_royalties: LookupMap::new(StorageKey::TokenRoyalties),
+            settlements: TreeMap::new(StorageKey::Settlements),
+            next_settlement_id: 0,
+            max_payout_len: MAX_PAYOUT_LEN_DEFAULT,
+            claimable: LookupMap::new(StorageKey::Claimable),
+            origin_fees: TreeMap::new(StorageKey::OriginFees),
+            max_origin_fee: ORIGIN_FEE_MAX,
             ft_token_ids: tokens,
             storage_deposits: LookupMap::new(StorageKey::StorageDeposits),
             bid_history_length: BID_HISTORY_LENGTH_DEFAULT,
-            auctions: UnorderedMap::new(StorageKey::Auctions),
+            auctions: TreeMap::new(StorageKey::Auctions),
             next_auction_id: 0,
+            auctions_by_owner_id: LookupMap::new(StorageKey::AuctionsByOwnerId),
+            auction_bids_by_owner_id: LookupMap::new(StorageKey::AuctionBidsByOwnerId),
+            series_sales: TreeMap::new(StorageKey::SeriesSales),
+            listings_by_owner_id: LookupMap::new(StorageKey::ListingsByOwnerId),
+            phase_purchases: LookupMap::new(StorageKey::PhasePurchases),
+            raffles: TreeMap::new(StorageKey::Raffles),
+            next_raffle_id: 0,
+            swaps: TreeMap::new(StorageKey::Swaps),
+            next_swap_id: 0,
+            swaps_by_token: LookupMap::new(StorageKey::SwapsByToken),
+            keeper_reward: KEEPER_REWARD_DEFAULT,
         };
         Self {
             non_fungible_token_account_ids,
@@ -93,8 +243,7 @@ impl Market {
         assert_one_yocto();
         let owner_id = env::predecessor_account_id();
         let mut amount = self.market.storage_deposits.remove(&owner_id).unwrap_or(0);
-        let sales = self.market.by_owner_id.get(&owner_id);
-        let len = sales.map(|s| s.len()).unwrap_or_default();
+        let len = self.internal_supply_with_listings(&owner_id);
         let diff = u128::from(len) * STORAGE_PER_SALE;
         amount -= diff;
         if amount > 0 {
@@ -0,0 +1,177 @@
+use std::collections::HashMap;
+
+use crate::bid::Origins;
+use crate::fee::{calculate_origins, fee_payouts, Fees};
+use crate::pagination::{iter_after, take_page, Page};
+use crate::*;
+
+// The highest total origin fee of one side, 10_000 is 100%
+pub const ORIGIN_FEE_MAX: u32 = 4_700;
+
+// An approved origin (referral) account
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct OriginFee {
+    pub max_fee: u32,
+    pub earnings: HashMap<FungibleTokenId, Balance>,
+    pub trades: u64,
+}
+
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub struct OriginJson {
+    pub origin_id: AccountId,
+    pub max_fee: u32,
+    pub earnings: HashMap<FungibleTokenId, U128>,
+    // The number of completed purchases in which the origin was paid
+    pub trades: U64,
+}
+
+#[near_bindgen]
+impl Market {
+    // Approves the origin or changes its maximum fee, the earnings are kept
+    pub fn register_origin(&mut self, origin_id: AccountId, max_fee: u32) {
+        require!(
+            env::predecessor_account_id() == self.market.owner_id,
+            "Only the market owner can manage origins"
+        );
+        require!(
+            max_fee > 0 && max_fee <= self.market.max_origin_fee,
+            format!(
+                "The origin fee should be from 1 to {}",
+                self.market.max_origin_fee
+            )
+        );
+        let mut origin = self
+            .market
+            .origin_fees
+            .get(&origin_id)
+            .unwrap_or(OriginFee {
+                max_fee,
+                earnings: HashMap::new(),
+                trades: 0,
+            });
+        origin.max_fee = max_fee;
+        self.market.origin_fees.insert(&origin_id, &origin);
+    }
+
+    // The listings and bids which already have the origin still pay it
+    pub fn unregister_origin(&mut self, origin_id: AccountId) {
+        require!(
+            env::predecessor_account_id() == self.market.owner_id,
+            "Only the market owner can manage origins"
+        );
+        self.market
+            .origin_fees
+            .remove(&origin_id)
+            .unwrap_or_else(|| env::panic_str("The origin is not registered"));
+    }
+
+    // The cap of the total origin fee of one side
+    pub fn set_max_origin_fee(&mut self, max_origin_fee: u32) {
+        require!(
+            env::predecessor_account_id() == self.market.owner_id,
+            "Only the market owner can set the origin fee cap"
+        );
+        require!(
+            max_origin_fee <= ORIGIN_FEE_MAX,
+            format!("The origin fee cap can't exceed {}", ORIGIN_FEE_MAX)
+        );
+        self.market.max_origin_fee = max_origin_fee;
+    }
+
+    pub fn get_max_origin_fee(&self) -> u32 {
+        self.market.max_origin_fee
+    }
+
+    pub fn get_origin(&self, origin_id: AccountId) -> Option<OriginJson> {
+        self.market
+            .origin_fees
+            .get(&origin_id)
+            .map(|origin| origin_json(origin_id, origin))
+    }
+
+    pub fn get_origins(
+        &self,
+        cursor: Option<AccountId>,
+        limit: Option<u64>,
+    ) -> Page<OriginJson, AccountId> {
+        let (origins, next_cursor) = take_page(
+            iter_after(&self.market.origin_fees, cursor),
+            limit,
+            |(origin_id, _)| origin_id.clone(),
+        );
+        Page {
+            items: origins
+                .into_iter()
+                .map(|(origin_id, origin)| origin_json(origin_id, origin))
+                .collect(),
+            next_cursor,
+        }
+    }
+}
+
+impl Market {
+    // Every origin should be registered and take no more than its maximum fee,
+    // the total fee of the origins is capped
+    pub(crate) fn assert_valid_origins(&self, origins: Option<&Origins>) {
+        let origins = if let Some(origins) = origins {
+            origins
+        } else {
+            return;
+        };
+        for (origin_id, fee) in origins {
+            let origin = self
+                .market
+                .origin_fees
+                .get(origin_id)
+                .unwrap_or_else(|| {
+                    env::panic_str(&format!("{} is not a registered origin", origin_id))
+                });
+            require!(
+                *fee <= origin.max_fee,
+                format!(
+                    "The fee of the origin {} exceeds its maximum {}",
+                    origin_id, origin.max_fee
+                )
+            );
+        }
+        require!(
+            calculate_origins(origins) <= self.market.max_origin_fee,
+            "Max origins exceeded"
+        );
+    }
+
+    // Adds the fees of the completed purchase to the earnings of the registered origins
+    pub(crate) fn internal_record_origin_earnings(
+        &mut self,
+        ft_token_id: &FungibleTokenId,
+        price: u128,
+        fees: &Fees,
+    ) {
+        let market_id = env::current_account_id();
+        for (origin_id, amount) in fee_payouts(price, fees) {
+            if origin_id == market_id {
+                continue;
+            }
+            if let Some(mut origin) = self.market.origin_fees.get(&origin_id) {
+                *origin.earnings.entry(ft_token_id.clone()).or_insert(0) += amount;
+                origin.trades += 1;
+                self.market.origin_fees.insert(&origin_id, &origin);
+            }
+        }
+    }
+}
+
+fn origin_json(origin_id: AccountId, origin: OriginFee) -> OriginJson {
+    OriginJson {
+        origin_id,
+        max_fee: origin.max_fee,
+        earnings: origin
+            .earnings
+            .into_iter()
+            .map(|(ft_token_id, amount)| (ft_token_id, U128(amount)))
+            .collect(),
+        trades: U64(origin.trades),
+    }
+}
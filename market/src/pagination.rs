@@ -0,0 +1,56 @@
+use crate::*;
+use std::cmp::min;
+
+// The number of items returned when `limit` is not specified
+pub const PAGE_SIZE_DEFAULT: u64 = 20;
+// Keeps every page within the view gas limit
+pub const PAGE_SIZE_MAX: u64 = 50;
+
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub struct Page<T, C = U64> {
+    pub items: Vec<T>,
+    // Pass it as `cursor` to get the next page, `None` on the last page
+    pub next_cursor: Option<C>,
+}
+
+// An empty limit would return the same cursor forever, so it is rejected
+pub(crate) fn page_limit(limit: Option<u64>) -> usize {
+    let limit = min(limit.unwrap_or(PAGE_SIZE_DEFAULT), PAGE_SIZE_MAX);
+    require!(limit > 0, "The limit should be greater than 0");
+    limit as usize
+}
+
+// Takes a page of the items which follow the cursor, the next cursor is the key of the last item.
+// The cursor is a key and not a position in the list, so the items added or removed
+// between two calls don't make the next page skip or repeat an item
+pub(crate) fn take_page<T, C>(
+    items: impl Iterator<Item = T>,
+    limit: Option<u64>,
+    cursor_of: impl Fn(&T) -> C,
+) -> (Vec<T>, Option<C>) {
+    let mut items = items.peekable();
+    let page: Vec<T> = items.by_ref().take(page_limit(limit)).collect();
+    let next_cursor = if items.peek().is_some() {
+        page.last().map(cursor_of)
+    } else {
+        None
+    };
+    (page, next_cursor)
+}
+
+// The entries of the map in the order of the keys, starting after the cursor
+pub(crate) fn iter_after<'a, K, V>(
+    map: &'a TreeMap<K, V>,
+    cursor: Option<K>,
+) -> Box<dyn Iterator<Item = (K, V)> + 'a>
+where
+    K: Ord + Clone + BorshSerialize + BorshDeserialize + 'a,
+    V: BorshSerialize + BorshDeserialize + 'a,
+{
+    match cursor {
+        Some(cursor) => Box::new(map.iter_from(cursor)),
+        None => Box::new(map.iter()),
+    }
+}
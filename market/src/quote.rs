@@ -0,0 +1,223 @@
+use std::collections::HashMap;
+
+use near_sdk::{Gas, PromiseResult};
+
+use crate::bid::Origins;
+use crate::fee::{
+    calculate_origins, calculate_price_with_fees, fee_payouts, fees_with_protocol, payout_balance,
+    Fees, PayoutMode, PAYOUT_TOTAL_VALUE,
+};
+use crate::royalty::Royalty;
+use crate::sale::{ext_contract, ext_self, sale_key, Payout, GAS_FOR_NFT_TOKEN, NO_DEPOSIT};
+use crate::*;
+
+pub const GAS_FOR_NFT_PAYOUT: Gas = Gas(10_000_000_000_000);
+pub const GAS_FOR_RESOLVE_CACHE_ROYALTY: Gas = Gas(10_000_000_000_000);
+
+// Where the price paid by the buyer goes
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub struct PurchaseQuote {
+    pub ft_token_id: FungibleTokenId,
+    pub payout_mode: PayoutMode,
+    // The price with all the buyer fees, the buyer pays it
+    pub total: U128,
+    // The protocol fee of both sides
+    pub protocol_fee: U128,
+    // The origin fees of both sides
+    pub origins: HashMap<AccountId, U128>,
+    pub royalty: HashMap<AccountId, U128>,
+    // `false` if the royalty of the token hasn't been cached with `cache_royalty`
+    pub royalty_cached: bool,
+    pub seller: U128,
+}
+
+#[near_bindgen]
+impl Market {
+    // The breakdown of the purchase of the sale at its current price,
+    // `buyer_id` is needed to quote the private price of the reserved buyer
+    pub fn get_purchase_quote(
+        &self,
+        nft_contract_id: AccountId,
+        token_id: String,
+        ft_token_id: FungibleTokenId,
+        origins: Option<Origins>,
+        buyer_id: Option<AccountId>,
+    ) -> PurchaseQuote {
+        let sale = self
+            .market
+            .sales
+            .get(&sale_key(&nft_contract_id, &token_id))
+            .expect("No sale");
+        let price = buyer_id
+            .map_or_else(
+                || sale.sale_conditions.get(&ft_token_id).copied(),
+                |buyer_id| sale.price_for(&buyer_id, &ft_token_id),
+            )
+            .unwrap_or_else(|| env::panic_str("Not supported ft"));
+        let total = calculate_price_with_fees(price, origins.as_ref());
+        let fees = fees_with_protocol(origins.unwrap_or_default(), &sale.origins);
+        self.internal_quote(
+            &nft_contract_id,
+            &token_id,
+            &sale.owner_id,
+            ft_token_id,
+            total,
+            &fees,
+        )
+    }
+
+    // The breakdown of the minimal next bid of the auction,
+    // it's paid if the bid wins the auction
+    pub fn get_auction_quote(&self, auction_id: U128, origins: Option<Origins>) -> PurchaseQuote {
+        let auction = self
+            .market
+            .auctions
+            .get(&auction_id.into())
+            .unwrap_or_else(|| env::panic_str("Auction does not exist"));
+        let total =
+            calculate_price_with_fees(self.get_minimal_next_bid(auction_id), origins.as_ref());
+        let fees = fees_with_protocol(origins.unwrap_or_default(), &auction.origins);
+        self.internal_quote(
+            &auction.nft_contract_id,
+            &auction.token_id,
+            &auction.owner_id,
+            auction.ft_token_id,
+            total,
+            &fees,
+        )
+    }
+
+    // Caches the royalty of the token for the quotes, can be called by anyone
+    // The royalty is the payout of `nft_payout` without the share of the token owner
+    pub fn cache_royalty(&mut self, nft_contract_id: AccountId, token_id: String) -> Promise {
+        ext_contract::nft_token(
+            token_id.clone(),
+            nft_contract_id.clone(),
+            NO_DEPOSIT,
+            GAS_FOR_NFT_TOKEN,
+        )
+        .and(ext_contract::nft_payout(
+            token_id.clone(),
+            U128(PAYOUT_TOTAL_VALUE),
+            self.market.max_payout_len,
+            nft_contract_id.clone(),
+            NO_DEPOSIT,
+            GAS_FOR_NFT_PAYOUT,
+        ))
+        .then(ext_self::resolve_cache_royalty(
+            nft_contract_id,
+            token_id,
+            env::current_account_id(),
+            NO_DEPOSIT,
+            GAS_FOR_RESOLVE_CACHE_ROYALTY,
+        ))
+    }
+
+    // self callback
+    #[private]
+    pub fn resolve_cache_royalty(
+        &mut self,
+        nft_contract_id: AccountId,
+        token_id: String,
+    ) -> Royalty {
+        let token = promise_result_value(0)
+            .and_then(|value| near_sdk::serde_json::from_slice::<Option<Token>>(&value).ok())
+            .flatten()
+            .unwrap_or_else(|| env::panic_str("No token"));
+        let payout = promise_result_value(1)
+            .and_then(|value| near_sdk::serde_json::from_slice::<Payout>(&value).ok())
+            .unwrap_or_else(|| env::panic_str("The NFT contract doesn't support nft_payout"));
+        let royalty: Royalty = payout
+            .payout
+            .into_iter()
+            .filter(|(account_id, amount)| account_id != &token.owner_id && amount.0 > 0)
+            .map(|(account_id, amount)| (account_id, amount.0 as u32))
+            .collect();
+        // an empty royalty is cached too, the token just has no royalty
+        self.market
+            .token_royalties
+            .insert(&sale_key(&nft_contract_id, &token_id), &royalty);
+        royalty
+    }
+
+    pub fn get_cached_royalty(
+        &self,
+        nft_contract_id: AccountId,
+        token_id: String,
+    ) -> Option<Royalty> {
+        self.market
+            .token_royalties
+            .get(&sale_key(&nft_contract_id, &token_id))
+    }
+}
+
+impl Market {
+    // Splits `total` the same way as `resolve_purchase` does:
+    // in the `Memo` mode the NFT contract counts the royalty from the price without the buyer fees,
+    // otherwise it is counted from the price without all the fees
+    pub(crate) fn internal_quote(
+        &self,
+        nft_contract_id: &AccountId,
+        token_id: &str,
+        owner_id: &AccountId,
+        ft_token_id: FungibleTokenId,
+        total: u128,
+        fees: &Fees,
+    ) -> PurchaseQuote {
+        let payout_mode = self.get_payout_mode(nft_contract_id.clone());
+        let mut origins = fee_payouts(total, fees);
+        let protocol_fee = origins.remove(&env::current_account_id()).unwrap_or(0);
+        origins.retain(|_, amount| *amount > 0);
+        let royalty_base = match payout_mode {
+            PayoutMode::Memo => {
+                let buyer_value = calculate_origins(&fees.buyer) as u128;
+                total - total * buyer_value / (PAYOUT_TOTAL_VALUE + buyer_value)
+            }
+            PayoutMode::Standard | PayoutMode::Transfer => payout_balance(payout_mode, total, fees),
+        };
+        let (royalty, royalty_cached) = match payout_mode {
+            PayoutMode::Transfer => (self.get_collection_royalty(nft_contract_id.clone()), true),
+            PayoutMode::Memo | PayoutMode::Standard => {
+                let cached = self.get_cached_royalty(nft_contract_id.clone(), token_id.to_string());
+                let royalty_cached = cached.is_some();
+                (cached.unwrap_or_default(), royalty_cached)
+            }
+        };
+        let royalty: HashMap<AccountId, u128> = royalty
+            .into_iter()
+            .filter(|(account_id, _)| account_id != owner_id)
+            .map(|(account_id, value)| {
+                let amount = royalty_base * value as u128 / PAYOUT_TOTAL_VALUE;
+                (account_id, amount)
+            })
+            .collect();
+        let paid: u128 =
+            protocol_fee + origins.values().sum::<u128>() + royalty.values().sum::<u128>();
+        PurchaseQuote {
+            ft_token_id,
+            payout_mode,
+            total: U128(total),
+            protocol_fee: U128(protocol_fee),
+            origins: to_json_amounts(origins),
+            royalty: to_json_amounts(royalty),
+            royalty_cached,
+            seller: U128(total.saturating_sub(paid)),
+        }
+    }
+}
+
+fn to_json_amounts(amounts: HashMap<AccountId, u128>) -> HashMap<AccountId, U128> {
+    amounts
+        .into_iter()
+        .map(|(account_id, amount)| (account_id, U128(amount)))
+        .collect()
+}
+
+fn promise_result_value(result_idx: u64) -> Option<Vec<u8>> {
+    match env::promise_result(result_idx) {
+        PromiseResult::Successful(value) => Some(value),
+        _ => None,
+    }
+}
@@ -0,0 +1,369 @@
+use near_sdk::collections::UnorderedSet;
+use near_sdk::serde_json::json;
+
+use crate::bid::Origins;
+use crate::fee::{calculate_price_with_fees, fees_with_protocol};
+use crate::pagination::{iter_after, take_page, Page};
+use crate::sale::{ext_contract, ext_self, sale_key, TokenSeriesId, NO_DEPOSIT};
+use crate::series_sale::{GAS_FOR_MINT, GAS_FOR_RESOLVE_MINT};
+use crate::settlement::PendingTransfer;
+use crate::*;
+
+// `draw` reads every entry to refund the losers, so the number of entries is capped
+pub const RAFFLE_ENTRIES_MAX: u64 = 300;
+// The number of winners minted in one call of `draw`
+pub const RAFFLE_MINT_BATCH: usize = 2;
+
+// Copies of the series sale which are given to random entrants
+// Everyone escrows the price during the entry window, the losers are refunded
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Raffle {
+    pub owner_id: AccountId,
+    pub nft_contract_id: AccountId,
+    pub series_id: TokenSeriesId,
+    // The price in NEAR without fees
+    pub price: Balance,
+    pub copies: u64,
+    pub end: u64,
+    pub entries: UnorderedSet<AccountId>,
+    pub drawn: bool,
+    pub winners: Vec<AccountId>,
+    // The number of winners whose tokens have been minted (or refunded)
+    pub minted: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub enum RaffleStatus {
+    // Accepting entries
+    Open,
+    // The entry window has ended, waiting for `draw`
+    Closed,
+    // The winners are drawn, the tokens are being minted
+    Minting,
+    Finished,
+}
+
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub struct RaffleJson {
+    pub raffle_id: U64,
+    pub owner_id: AccountId,
+    pub nft_contract_id: AccountId,
+    pub series_id: TokenSeriesId,
+    pub price: U128,
+    pub copies: U64,
+    pub end: U64,
+    pub entries: U64,
+    pub status: RaffleStatus,
+    pub minted: U64,
+}
+
+impl Raffle {
+    pub fn status(&self) -> RaffleStatus {
+        if self.drawn {
+            if self.minted < self.winners.len() as u64 {
+                RaffleStatus::Minting
+            } else {
+                RaffleStatus::Finished
+            }
+        } else if env::block_timestamp() < self.end {
+            RaffleStatus::Open
+        } else {
+            RaffleStatus::Closed
+        }
+    }
+
+    // The escrow of one entry: the price with the protocol fee and the storage of the token
+    pub fn entry_deposit(&self) -> Balance {
+        calculate_price_with_fees(U128(self.price), None) + STORAGE_FOR_MINT
+    }
+}
+
+#[near_bindgen]
+impl Market {
+    // Moves the copies from the series sale to the raffle
+    #[payable]
+    pub fn start_raffle(
+        &mut self,
+        nft_contract_id: AccountId,
+        series_id: TokenSeriesId,
+        price: U128,
+        copies: u64,
+        end: U64,
+    ) -> U64 {
+        assert_one_yocto();
+        let owner_id = env::predecessor_account_id();
+        let key = sale_key(&nft_contract_id, &series_id);
+        let mut series_sale = self.market.series_sales.get(&key).expect("No series sale");
+        require!(
+            series_sale.owner_id == owner_id,
+            "Only the series owner can start a raffle"
+        );
+        require!(price.0 > 0, "The price should be positive");
+        require!(
+            copies > 0 && copies <= series_sale.copies,
+            format!(
+                "The number of copies should be from 1 to {}",
+                series_sale.copies
+            )
+        );
+        require!(
+            end.0 > env::block_timestamp(),
+            "The raffle should end in the future"
+        );
+        self.internal_add_listing_storage(&owner_id);
+        series_sale.copies -= copies;
+        series_sale.raffled += copies;
+        self.market.series_sales.insert(&key, &series_sale);
+
+        let raffle_id = self.market.next_raffle_id;
+        self.market.next_raffle_id += 1;
+        let raffle = Raffle {
+            owner_id,
+            nft_contract_id,
+            series_id,
+            price: price.0,
+            copies,
+            end: end.0,
+            entries: UnorderedSet::new(StorageKey::RaffleEntriesInner { raffle_id }),
+            drawn: false,
+            winners: Vec::new(),
+            minted: 0,
+        };
+        self.market.raffles.insert(&raffle_id, &raffle);
+        env::log_str(
+            &json!({
+                "type": "start_raffle",
+                "params": raffle_json(raffle_id, &raffle),
+            })
+            .to_string(),
+        );
+        U64(raffle_id)
+    }
+
+    // One entry per account, the deposit above the escrow is refunded
+    #[payable]
+    pub fn enter_raffle(&mut self, raffle_id: U64) {
+        let buyer_id = env::predecessor_account_id();
+        let mut raffle = self.market.raffles.get(&raffle_id.0).expect("No raffle");
+        require!(
+            matches!(raffle.status(), RaffleStatus::Open),
+            "The raffle is not open"
+        );
+        require!(raffle.owner_id != buyer_id, "Cannot enter your own raffle");
+        require!(
+            raffle.entries.len() < RAFFLE_ENTRIES_MAX,
+            "The raffle is full"
+        );
+        let required = raffle.entry_deposit();
+        let deposit = env::attached_deposit();
+        require!(
+            deposit >= required,
+            format!("Attached deposit must be at least {}", required)
+        );
+        require!(raffle.entries.insert(&buyer_id), "Already entered");
+        if deposit > required {
+            Promise::new(buyer_id).transfer(deposit - required);
+        }
+        self.market.raffles.insert(&raffle_id.0, &raffle);
+    }
+
+    // Can be called by anyone after the entry window
+    // The first call draws the winners and refunds everyone else,
+    // every call mints the tokens of the next `RAFFLE_MINT_BATCH` winners
+    // Returns the number of the winners left to mint
+    pub fn draw(&mut self, raffle_id: U64) -> U64 {
+        let mut raffle = self.market.raffles.get(&raffle_id.0).expect("No raffle");
+        match raffle.status() {
+            RaffleStatus::Open => env::panic_str("The raffle is not over yet"),
+            RaffleStatus::Finished => env::panic_str("The raffle is finished"),
+            RaffleStatus::Closed => self.internal_draw_winners(raffle_id.0, &mut raffle),
+            RaffleStatus::Minting => {}
+        }
+
+        let price = calculate_price_with_fees(U128(raffle.price), None);
+        let key = sale_key(&raffle.nft_contract_id, &raffle.series_id);
+        let start = raffle.minted as usize;
+        let end = raffle.winners.len().min(start + RAFFLE_MINT_BATCH);
+        for winner_id in raffle.winners[start..end].iter() {
+            // `resolve_mint` counts the copy as sold (or returns it) if the series sale exists
+            if let Some(mut series_sale) = self.market.series_sales.get(&key) {
+                // the series sale could have been removed and listed again meanwhile
+                series_sale.raffled = series_sale.raffled.saturating_sub(1);
+                series_sale.minting += 1;
+                self.market.series_sales.insert(&key, &series_sale);
+            }
+            ext_contract::nft_mint(
+                raffle.series_id.clone(),
+                winner_id.clone(),
+                Some(winner_id.clone()),
+                raffle.nft_contract_id.clone(),
+                STORAGE_FOR_MINT,
+                GAS_FOR_MINT,
+            )
+            .then(ext_self::resolve_mint(
+                raffle.nft_contract_id.clone(),
+                raffle.series_id.clone(),
+                raffle.owner_id.clone(),
+                winner_id.clone(),
+                U128(price),
+                fees_with_protocol(Origins::new(), &Origins::new()),
+                None,
+                env::current_account_id(),
+                NO_DEPOSIT,
+                GAS_FOR_RESOLVE_MINT,
+            ));
+        }
+        raffle.minted = end as u64;
+        if raffle.minted == raffle.winners.len() as u64 {
+            // the finished raffle is removed with its entries, the `draw` event keeps the winners
+            self.internal_release_listing_storage(&raffle.owner_id);
+            raffle.entries.clear();
+            self.market.raffles.remove(&raffle_id.0);
+            env::log_str(
+                &json!({
+                    "type": "raffle_finished",
+                    "params": raffle_json(raffle_id.0, &raffle),
+                })
+                .to_string(),
+            );
+        } else {
+            self.market.raffles.insert(&raffle_id.0, &raffle);
+        }
+        U64(raffle.winners.len() as u64 - raffle.minted)
+    }
+
+    pub fn get_raffle(&self, raffle_id: U64) -> Option<RaffleJson> {
+        self.market
+            .raffles
+            .get(&raffle_id.0)
+            .map(|raffle| raffle_json(raffle_id.0, &raffle))
+    }
+
+    pub fn get_raffles(&self, cursor: Option<U64>, limit: Option<u64>) -> Page<RaffleJson> {
+        let (raffles, next_cursor) = take_page(
+            iter_after(&self.market.raffles, cursor.map(u64::from)),
+            limit,
+            |(raffle_id, _)| U64(*raffle_id),
+        );
+        Page {
+            items: raffles
+                .iter()
+                .map(|(raffle_id, raffle)| raffle_json(*raffle_id, raffle))
+                .collect(),
+            next_cursor,
+        }
+    }
+
+    pub fn get_raffle_entries(
+        &self,
+        raffle_id: U64,
+        cursor: Option<U64>,
+        limit: Option<u64>,
+    ) -> Page<AccountId> {
+        let raffle = self.market.raffles.get(&raffle_id.0).expect("No raffle");
+        // the entries are only added while the raffle exists,
+        // so the position of the last entry on the page is a stable cursor
+        let entries = raffle.entries.as_vector();
+        let start = cursor.map_or(0, |cursor| cursor.0.saturating_add(1));
+        let (page, next_cursor) = take_page(
+            (start..entries.len()).filter_map(|index| Some((index, entries.get(index)?))),
+            limit,
+            |(index, _)| U64(*index),
+        );
+        Page {
+            items: page.into_iter().map(|(_, account_id)| account_id).collect(),
+            next_cursor,
+        }
+    }
+
+    // Empty until the raffle is drawn
+    pub fn get_raffle_winners(&self, raffle_id: U64) -> Vec<AccountId> {
+        self.market
+            .raffles
+            .get(&raffle_id.0)
+            .expect("No raffle")
+            .winners
+    }
+}
+
+impl Market {
+    // Picks the winners with a partial Fisher-Yates shuffle seeded by `env::random_seed`,
+    // refunds the losers and returns the copies nobody won to the series sale
+    fn internal_draw_winners(&mut self, raffle_id: u64, raffle: &mut Raffle) {
+        let entries = raffle.entries.to_vec();
+        let winners_len = entries.len().min(raffle.copies as usize);
+        let seed = env::random_seed();
+        let mut indexes: Vec<usize> = (0..entries.len()).collect();
+        for i in 0..winners_len {
+            let j = i + (random_u64(&seed, i as u64) % (entries.len() - i) as u64) as usize;
+            indexes.swap(i, j);
+        }
+        raffle.winners = indexes[..winners_len]
+            .iter()
+            .map(|index| entries[*index].clone())
+            .collect();
+        raffle.drawn = true;
+
+        let ft_token_id: AccountId = "near".parse().unwrap();
+        let refund = U128(raffle.entry_deposit());
+        let refunds = indexes[winners_len..]
+            .iter()
+            .map(|index| PendingTransfer {
+                ft_token_id: ft_token_id.clone(),
+                receiver_id: entries[*index].clone(),
+                amount: refund,
+            })
+            .collect();
+        self.internal_settle(refunds);
+
+        let unused = raffle.copies - winners_len as u64;
+        let key = sale_key(&raffle.nft_contract_id, &raffle.series_id);
+        if unused > 0 {
+            if let Some(mut series_sale) = self.market.series_sales.get(&key) {
+                series_sale.copies += unused;
+                series_sale.raffled = series_sale.raffled.saturating_sub(unused);
+                self.market.series_sales.insert(&key, &series_sale);
+            }
+        }
+        env::log_str(
+            &json!({
+                "type": "draw",
+                "params": {
+                    "raffle_id": U64(raffle_id),
+                    "winners": raffle.winners,
+                    "entries": entries.len(),
+                }
+            })
+            .to_string(),
+        );
+    }
+}
+
+// The seed is extended with the counter to get a new number for every winner
+fn random_u64(seed: &[u8], counter: u64) -> u64 {
+    let mut input = seed.to_vec();
+    input.extend_from_slice(&counter.to_le_bytes());
+    let hash = env::sha256(&input);
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&hash[..8]);
+    u64::from_le_bytes(bytes)
+}
+
+fn raffle_json(raffle_id: u64, raffle: &Raffle) -> RaffleJson {
+    RaffleJson {
+        raffle_id: U64(raffle_id),
+        owner_id: raffle.owner_id.clone(),
+        nft_contract_id: raffle.nft_contract_id.clone(),
+        series_id: raffle.series_id.clone(),
+        price: U128(raffle.price),
+        copies: U64(raffle.copies),
+        end: U64(raffle.end),
+        entries: U64(raffle.entries.len()),
+        status: raffle.status(),
+        minted: U64(raffle.minted),
+    }
+}
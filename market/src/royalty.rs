@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+
+use crate::fee::{PayoutMode, PAYOUT_TOTAL_VALUE};
+use crate::sale::Payout;
+use crate::*;
+
+// 10_000 is 100%, the same cap as in nft-contract
+pub const MAXIMUM_ROYALTY: u32 = 5_000;
+
+pub type Royalty = HashMap<AccountId, u32>;
+
+#[near_bindgen]
+impl Market {
+    // The creator can set the royalty of the collection
+    pub fn set_collection_creator(
+        &mut self,
+        nft_contract_id: AccountId,
+        creator_id: Option<AccountId>,
+    ) {
+        require!(
+            env::predecessor_account_id() == self.market.owner_id,
+            "Only the market owner can verify the creator"
+        );
+        if let Some(creator_id) = creator_id {
+            self.market
+                .collection_creators
+                .insert(&nft_contract_id, &creator_id);
+        } else {
+            self.market.collection_creators.remove(&nft_contract_id);
+        }
+    }
+
+    pub fn get_collection_creator(&self, nft_contract_id: AccountId) -> Option<AccountId> {
+        self.market.collection_creators.get(&nft_contract_id)
+    }
+
+    // The royalty is used only if the tokens are sold in the `Transfer` payout mode
+    // Can be set by the market owner, the NFT contract itself or the verified creator
+    pub fn set_collection_royalty(&mut self, nft_contract_id: AccountId, royalty: Royalty) {
+        let caller_id = env::predecessor_account_id();
+        require!(
+            caller_id == self.market.owner_id
+                || caller_id == nft_contract_id
+                || self.market.collection_creators.get(&nft_contract_id) == Some(caller_id),
+            "Only the market owner, the NFT contract or the verified creator can set the royalty"
+        );
+        require!(
+            royalty.values().sum::<u32>() <= MAXIMUM_ROYALTY,
+            format!("maximum royalty cap exceeded {}", MAXIMUM_ROYALTY)
+        );
+        // the seller takes one entry of the payout, the origin fees are added after
+        // the length check, so they don't take any
+        let max_receivers = self.market.max_payout_len as usize - 1;
+        require!(
+            royalty.len() <= max_receivers,
+            format!(
+                "Too many royalty receivers, the maximum is {}",
+                max_receivers
+            )
+        );
+        if royalty.is_empty() {
+            self.market.collection_royalties.remove(&nft_contract_id);
+        } else {
+            self.market
+                .collection_royalties
+                .insert(&nft_contract_id, &royalty);
+        }
+    }
+
+    pub fn get_collection_royalty(&self, nft_contract_id: AccountId) -> Royalty {
+        self.market
+            .collection_royalties
+            .get(&nft_contract_id)
+            .unwrap_or_default()
+    }
+}
+
+impl Market {
+    // The payout of `nft_transfer_payout`, or the payout by the market-side royalty
+    // if the token has been transferred with `nft_transfer`
+    pub(crate) fn internal_payout_from_result(
+        &self,
+        payout_mode: PayoutMode,
+        result: &[u8],
+        nft_contract_id: &AccountId,
+        owner_id: &AccountId,
+        balance: u128,
+    ) -> Option<Payout> {
+        match payout_mode {
+            PayoutMode::Memo | PayoutMode::Standard => {
+                near_sdk::serde_json::from_slice::<Payout>(result).ok()
+            }
+            PayoutMode::Transfer => {
+                let royalty = self.get_collection_royalty(nft_contract_id.clone());
+                let mut payout = HashMap::new();
+                let mut total = 0;
+                for (account_id, value) in royalty {
+                    if &account_id != owner_id {
+                        let amount = balance * value as u128 / PAYOUT_TOTAL_VALUE;
+                        payout.insert(account_id, U128(amount));
+                        total += amount;
+                    }
+                }
+                payout.insert(owner_id.clone(), U128(balance - total));
+                Some(Payout { payout })
+            }
+        }
+    }
+}
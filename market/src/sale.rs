@@ -3,22 +3,35 @@ use std::collections::HashMap;
 
 use near_sdk::ext_contract;
 use near_sdk::serde_json::json;
-use near_sdk::{promise_result_as_success, Gas};
+use near_sdk::{promise_result_as_success, Gas, PromiseOrValue};
 
-use crate::fee::calculate_price_with_fees;
-use crate::market_core::SaleArgs;
+use crate::custody::{custody_payout, custody_status_from_promise, return_custody_token};
+use crate::fee::{
+    calculate_price_with_fees, fees_with_protocol, market_fee_payouts, payout_balance, payout_memo,
+    Fees, PayoutMode,
+};
+use crate::market_core::{ArgsKind, SaleArgs};
 use crate::*;
 use common::*;
 use near_contract_standards::non_fungible_token::hash_account_id;
 
-use bid::{Bids, Origins};
+use bid::{bid_refunds, Bids, Origins};
+use settlement::payout_transfers;
 pub type TokenSeriesId = String;
 
 pub const GAS_FOR_FT_TRANSFER: Gas = Gas(5_000_000_000_000);
 pub const GAS_FOR_ROYALTIES: Gas = Gas(115_000_000_000_000);
 pub const GAS_FOR_NFT_TRANSFER: Gas = Gas(30_000_000_000_000);
-// pub const GAS_FOR_MINT: Gas = Gas(20_000_000_000_000);
+pub const GAS_FOR_NFT_TOKEN: Gas = Gas(10_000_000_000_000);
+pub const GAS_FOR_RESOLVE_VERIFY: Gas = Gas(10_000_000_000_000);
+// nft_transfer_payout and its callback are called after the listing is verified
+pub const GAS_FOR_VERIFIED_TRANSFER: Gas = Gas(
+    GAS_FOR_NFT_TRANSFER.0 + GAS_FOR_ROYALTIES.0 + GAS_FOR_RESOLVE_VERIFY.0,
+);
 pub const BID_HISTORY_LENGTH_DEFAULT: u8 = 5;
+// A purchase which hasn't been resolved in this time has failed in a callback,
+// its lock is ignored so the sale can be bought, changed or removed again
+pub const SALE_LOCK_TIMEOUT: u64 = 10 * 60 * NANOS_PER_SEC;
 pub(crate) const NO_DEPOSIT: Balance = 0;
 pub static DELIMETER: &str = "||";
 
@@ -30,9 +43,86 @@ pub struct Payout {
     pub payout: HashMap<AccountId, U128>,
 }
 
+// Adds the fees paid by the market to the payout of the NFT contract
+pub(crate) fn with_market_fees(
+    mut payout: Payout,
+    market_fees: HashMap<AccountId, u128>,
+) -> Payout {
+    for (account_id, amount) in market_fees {
+        payout.payout.entry(account_id).or_insert(U128(0)).0 += amount;
+    }
+    payout
+}
+
+// Used when the token has been transferred but the payout of the NFT contract is not valid,
+// the fees are paid by the market as in the `Standard` mode and the seller gets the rest
+pub(crate) fn seller_payout(seller_id: &AccountId, price: u128, fees: &Fees) -> Payout {
+    let payout = Payout {
+        payout: HashMap::from([(
+            seller_id.clone(),
+            U128(payout_balance(PayoutMode::Standard, price, fees)),
+        )]),
+    };
+    with_market_fees(
+        payout,
+        market_fee_payouts(PayoutMode::Standard, price, fees),
+    )
+}
+
+// Transfers the token to the buyer; the payout comes from the NFT contract,
+// or from the market-side royalty in the `Transfer` mode
+#[allow(clippy::too_many_arguments)]
+pub(crate) fn nft_transfer_with_payout(
+    payout_mode: PayoutMode,
+    nft_contract_id: AccountId,
+    buyer_id: AccountId,
+    token_id: TokenId,
+    approval_id: u64,
+    price: u128,
+    fees: &Fees,
+    max_len_payout: u32,
+) -> Promise {
+    match payout_mode {
+        PayoutMode::Transfer => ext_contract::nft_transfer(
+            buyer_id,
+            token_id,
+            Some(approval_id),
+            None,
+            nft_contract_id,
+            1,
+            GAS_FOR_NFT_TRANSFER,
+        ),
+        _ => ext_contract::nft_transfer_payout(
+            buyer_id,
+            token_id,
+            approval_id,
+            payout_memo(payout_mode, fees),
+            U128(payout_balance(payout_mode, price, fees)),
+            max_len_payout,
+            nft_contract_id,
+            1,
+            GAS_FOR_NFT_TRANSFER,
+        ),
+    }
+}
+
 pub type ContractAndTokenId = String;
 pub type FungibleTokenId = AccountId;
 pub type TokenType = Option<String>;
+// Fixed-size key of the sale, the hash of `ContractAndTokenId`
+pub type SaleKey = CryptoHash;
+// Bids of the sale for the given fungible token are stored separately from the sale
+pub type BidsKey = (SaleKey, FungibleTokenId);
+
+pub fn sale_key(nft_contract_id: &AccountId, token_id: &str) -> SaleKey {
+    contract_and_token_id_to_key(&format!("{}{}{}", nft_contract_id, DELIMETER, token_id))
+}
+
+pub fn contract_and_token_id_to_key(contract_and_token_id: &str) -> SaleKey {
+    let mut key = SaleKey::default();
+    key.copy_from_slice(&env::sha256(contract_and_token_id.as_bytes()));
+    key
+}
 
 #[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
 #[serde(crate = "near_sdk::serde")]
@@ -42,7 +132,6 @@ pub struct Sale {
     pub nft_contract_id: AccountId,
     pub token_id: String,
     pub sale_conditions: SaleConditions,
-    pub bids: Bids,
     pub created_at: u64,
     pub token_type: TokenType,
 
@@ -50,6 +139,24 @@ pub struct Sale {
     pub end: Option<u64>,
 
     pub origins: Origins,
+
+    pub allowed_buyers: Option<Vec<AccountId>>,
+    pub reserved_buyer: Option<ReservedBuyer>,
+
+    // A purchase is in progress since this time, the sale can't be bought, changed or removed
+    // until the purchase is resolved or `SALE_LOCK_TIMEOUT` passes
+    pub locked_at: Option<u64>,
+    // The token was sent with `nft_transfer_call` and is held by the market
+    pub custody: bool,
+}
+
+// A single buyer who can purchase the token at a private price
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub struct ReservedBuyer {
+    pub buyer_id: AccountId,
+    pub sale_conditions: SaleConditions,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -66,19 +173,22 @@ pub struct SaleJson {
     pub start: Option<U64>,
     pub end: Option<U64>,
     pub origins: Origins,
-}
 
-#[derive(BorshDeserialize, BorshSerialize)]
-pub struct SeriesSale {
-    pub owner_id: AccountId,
-    pub nft_contract_id: AccountId,
-    pub series_id: String,
-    pub sale_conditions: SaleConditions,
-    pub created_at: u64,
-    pub copies: u64,
+    // The private price of the reserved buyer is not shown
+    pub allowed_buyers: Option<Vec<AccountId>>,
+    pub reserved_buyer: Option<AccountId>,
+    pub is_private: bool,
+    pub locked: bool,
+    pub custody: bool,
 }
 
 impl Sale {
+    pub fn is_locked(&self) -> bool {
+        self.locked_at.map_or(false, |locked_at| {
+            env::block_timestamp() < locked_at + SALE_LOCK_TIMEOUT
+        })
+    }
+
     pub fn in_limits(&self) -> bool {
         let mut res = true;
         let now = env::block_timestamp();
@@ -91,6 +201,34 @@ impl Sale {
         res
     }
 
+    pub fn is_private(&self) -> bool {
+        self.allowed_buyers.is_some() || self.reserved_buyer.is_some()
+    }
+
+    // The reserved buyer is always allowed, others only if they are in `allowed_buyers`
+    // (or there is no such list)
+    pub fn is_buyer_allowed(&self, buyer_id: &AccountId) -> bool {
+        if let Some(reserved) = &self.reserved_buyer {
+            if &reserved.buyer_id == buyer_id {
+                return true;
+            }
+        }
+        self.allowed_buyers
+            .as_ref()
+            .map_or(true, |allowed| allowed.contains(buyer_id))
+    }
+
+    // The reserved buyer pays the private price (if it is set for `ft_token_id`),
+    // everyone else pays the price from `sale_conditions`
+    pub fn price_for(&self, buyer_id: &AccountId, ft_token_id: &FungibleTokenId) -> Option<U128> {
+        self.reserved_buyer
+            .as_ref()
+            .filter(|reserved| &reserved.buyer_id == buyer_id)
+            .and_then(|reserved| reserved.sale_conditions.get(ft_token_id))
+            .or_else(|| self.sale_conditions.get(ft_token_id))
+            .copied()
+    }
+
     pub fn extend(&mut self, time: u64) -> bool {
         if let Some(end) = self.end {
             self.end = Some(end + time);
@@ -101,11 +239,31 @@ impl Sale {
     }
 }
 
+// Checks the result of `nft_token`: the token should still belong to `owner_id`
+// and the market should still be approved with `approval_id`
+// Returns `None` if the NFT contract call failed
+pub(crate) fn listing_status_from_promise(
+    owner_id: &AccountId,
+    approval_id: u64,
+) -> Option<bool> {
+    let value = promise_result_as_success()?;
+    let token = near_sdk::serde_json::from_slice::<Option<Token>>(&value).ok()?;
+    Some(token.map_or(false, |token| {
+        &token.owner_id == owner_id
+            && token
+                .approved_account_ids
+                .and_then(|approvals| approvals.get(&env::current_account_id()).copied())
+                == Some(approval_id)
+    }))
+}
+
+// `msg` of `ft_transfer_call` to buy the nft
 #[derive(Serialize, Deserialize)]
 #[serde(crate = "near_sdk::serde")]
 pub struct PurchaseArgs {
     pub nft_contract_id: AccountId,
     pub token_id: TokenId,
+    pub origins: Option<Origins>,
 }
 
 #[near_bindgen]
@@ -117,6 +275,7 @@ impl Market {
         owner_id: AccountId,
         approval_id: u64,
         nft_contract_id: AccountId,
+        custody: bool,
     ) -> SaleJson {
         let SaleArgs {
             sale_conditions,
@@ -124,44 +283,56 @@ impl Market {
             start,
             end,
             origins,
+            allowed_buyers,
+            reserved_buyer,
         } = args;
 
         // check that the offered ft token is supported
 
-        for ft_token_id in sale_conditions.keys() {
-            if !self.market.ft_token_ids.contains(ft_token_id) {
-                env::panic_str(&format!(
-                    "Token {} not supported by this market",
-                    ft_token_id
-                ));
-            }
-            //*price = U128::from(calculate_price_with_fees(*price, None));
-        }
+        self.assert_supported_ft_tokens(&sale_conditions, reserved_buyer.as_ref());
+        self.assert_valid_origins(origins.as_ref());
 
-        // Create a new sale with given arguments and empty list of bids
+        // Create a new sale with given arguments, the sale has no bids yet
 
-        let bids = HashMap::new();
-        let contract_and_token_id = format!("{}{}{}", nft_contract_id, DELIMETER, token_id);
         let start = start.map(|s| s.into()).unwrap_or_else(env::block_timestamp);
-        let sale = Sale {
-            owner_id: owner_id.clone(),
+        self.internal_insert_sale(Sale {
+            owner_id,
             approval_id,
-            nft_contract_id: nft_contract_id.clone(),
-            token_id: token_id.clone(),
+            nft_contract_id,
+            token_id,
             sale_conditions,
-            bids,
             created_at: env::block_timestamp(),
-            token_type: token_type.clone(),
+            token_type,
             start: Some(start),
             end: end.map(|e| e.into()),
             origins: origins.unwrap_or_default(),
-        };
-        self.market.sales.insert(&contract_and_token_id, &sale);
+            allowed_buyers,
+            reserved_buyer,
+            locked_at: None,
+            custody,
+        })
+    }
+
+    // Lists the sale without checking its arguments, they are checked by the caller
+    pub(crate) fn internal_insert_sale(&mut self, sale: Sale) -> SaleJson {
+        let owner_id = sale.owner_id.clone();
+        let nft_contract_id = sale.nft_contract_id.clone();
+        let token_id = sale.token_id.clone();
+        let token_type = sale.token_type.clone();
+        let sale_key = sale_key(&nft_contract_id, &token_id);
+        // the token is listed again, the previous sale is replaced and its bids are refunded
+        if let Some(previous) = self.market.sales.get(&sale_key) {
+            require!(!previous.is_locked(), "The sale is locked by a purchase");
+            let (_, bids) = self.internal_remove_sale(nft_contract_id.clone(), token_id.clone());
+            self.refund_all_bids(&bids);
+        }
+        self.market.sales.insert(&sale_key, &sale);
+        self.internal_index_sale(&sale_key, &sale);
 
         // extra for views
 
         let mut by_owner_id = self.market.by_owner_id.get(&owner_id).unwrap_or_else(|| {
-            UnorderedSet::new(
+            TreeMap::new(
                 StorageKey::ByOwnerIdInner {
                     account_id_hash: hash_account_id(&owner_id),
                 }
@@ -171,17 +342,13 @@ impl Market {
         });
 
         // Check that the paid storage amount is enough
-        let owner_paid_storage = self
-            .market
-            .storage_deposits
-            .get(&env::signer_account_id())
-            .unwrap_or(0);
+        let owner_paid_storage = self.market.storage_deposits.get(&owner_id).unwrap_or(0);
         let owner_occupied_storage = u128::from(by_owner_id.len()) * STORAGE_PER_SALE;
         assert!(
             owner_paid_storage > owner_occupied_storage,
             "User has more sales than storage paid"
         );
-        by_owner_id.insert(&contract_and_token_id);
+        by_owner_id.insert(&sale_key, &());
         self.market.by_owner_id.insert(&owner_id, &by_owner_id);
 
         let mut by_nft_contract_id = self
@@ -189,7 +356,7 @@ impl Market {
             .by_nft_contract_id
             .get(&nft_contract_id)
             .unwrap_or_else(|| {
-                UnorderedSet::new(
+                TreeMap::new(
                     StorageKey::ByNFTContractIdInner {
                         account_id_hash: hash_account_id(&nft_contract_id),
                     }
@@ -197,36 +364,12 @@ impl Market {
                     .unwrap(),
                 )
             });
-        by_nft_contract_id.insert(&token_id);
+        by_nft_contract_id.insert(&sale_key, &());
         self.market
             .by_nft_contract_id
             .insert(&nft_contract_id, &by_nft_contract_id);
 
-        if let Some(token_type) = token_type {
-            assert!(
-                token_id.contains(token_type.as_str()),
-                "TokenType should be substr of TokenId"
-            );
-            let mut by_nft_token_type = self
-                .market
-                .by_nft_token_type
-                .get(&token_type)
-                .unwrap_or_else(|| {
-                    UnorderedSet::new(
-                        StorageKey::ByNFTTokenTypeInner {
-                            token_type_hash: hash_account_id(&AccountId::new_unchecked(
-                                token_type.clone(),
-                            )),
-                        }
-                        .try_to_vec()
-                        .unwrap(),
-                    )
-                });
-            by_nft_token_type.insert(&contract_and_token_id);
-            self.market
-                .by_nft_token_type
-                .insert(&token_type, &by_nft_token_type);
-        }
+        self.internal_index_token_type(&sale_key, &token_id, &token_type);
 
         self.json_from_sale(sale)
     }
@@ -235,7 +378,8 @@ impl Market {
     #[payable]
     pub fn remove_sale(&mut self, nft_contract_id: AccountId, token_id: String) {
         assert_one_yocto();
-        let sale = self.internal_remove_sale(nft_contract_id, token_id);
+        let (sale, bids) = self.internal_remove_sale(nft_contract_id, token_id);
+        require!(!sale.is_locked(), "The sale is locked by a purchase");
         let owner_id = env::predecessor_account_id();
         if sale.in_limits() {
             assert_eq!(
@@ -243,7 +387,75 @@ impl Market {
                 "Until the sale is finished, it can only be removed by the sale owner"
             );
         };
-        self.refund_all_bids(&sale.bids);
+        self.refund_all_bids(&bids);
+        if sale.custody {
+            return_custody_token(sale.nft_contract_id, sale.token_id, sale.owner_id);
+        }
+    }
+
+    // Checks that the owner of the token hasn't changed and the market is still approved
+    // Can be called by anyone, the stale sale is removed and its bids are refunded
+    pub fn verify_listing(&mut self, nft_contract_id: AccountId, token_id: String) -> Promise {
+        let sale = self
+            .market
+            .sales
+            .get(&sale_key(&nft_contract_id, &token_id))
+            .expect("No sale");
+        require!(!sale.custody, "The token is held by the market");
+        ext_contract::nft_token(
+            token_id.clone(),
+            nft_contract_id.clone(),
+            NO_DEPOSIT,
+            GAS_FOR_NFT_TOKEN,
+        )
+        .then(ext_self::resolve_verify_listing(
+            nft_contract_id,
+            token_id,
+            sale.owner_id,
+            sale.approval_id,
+            env::current_account_id(),
+            NO_DEPOSIT,
+            GAS_FOR_RESOLVE_VERIFY,
+        ))
+    }
+
+    // self callback
+    // Returns whether the sale is still valid
+    #[private]
+    pub fn resolve_verify_listing(
+        &mut self,
+        nft_contract_id: AccountId,
+        token_id: String,
+        owner_id: AccountId,
+        approval_id: u64,
+    ) -> bool {
+        if listing_status_from_promise(&owner_id, approval_id) != Some(false) {
+            return true;
+        }
+        // the sale could have been replaced while the NFT contract was called
+        let is_same_sale = self
+            .market
+            .sales
+            .get(&sale_key(&nft_contract_id, &token_id))
+            .map_or(false, |sale| {
+                sale.owner_id == owner_id && sale.approval_id == approval_id && !sale.is_locked()
+            });
+        if is_same_sale {
+            let (sale, bids) = self.internal_remove_sale(nft_contract_id, token_id);
+            self.refund_all_bids(&bids);
+            env::log_str(
+                &json!({
+                    "type": "remove_stale_sale",
+                    "params": {
+                        "owner_id": sale.owner_id,
+                        "nft_contract_id": sale.nft_contract_id,
+                        "token_id": sale.token_id,
+                    }
+                })
+                .to_string(),
+            );
+        }
+        false
     }
 
     #[payable]
@@ -255,30 +467,114 @@ impl Market {
         price: U128,
     ) {
         assert_one_yocto();
-        let contract_id: AccountId = nft_contract_id;
-        let contract_and_token_id = format!("{}{}{}", contract_id, DELIMETER, token_id);
-        let mut sale = self
-            .market
-            .sales
-            .get(&contract_and_token_id)
-            .expect("No sale");
+        let sale_key = sale_key(&nft_contract_id, &token_id);
+        let mut sale = self.market.sales.get(&sale_key).expect("No sale");
         assert_eq!(
             env::predecessor_account_id(),
             sale.owner_id,
             "Must be sale owner"
         );
+        require!(!sale.is_locked(), "The sale is locked by a purchase");
         if !self.market.ft_token_ids.contains(&ft_token_id) {
             env::panic_str(&format!(
                 "Token '{}' is not supported by this market",
                 ft_token_id
             ));
         }
-        sale.sale_conditions.insert(ft_token_id, price);
-        self.market.sales.insert(&contract_and_token_id, &sale);
+        if let Some(old_price) = sale.sale_conditions.insert(ft_token_id.clone(), price) {
+            self.internal_unindex_price(
+                &sale_key,
+                &nft_contract_id,
+                &token_id,
+                &ft_token_id,
+                old_price.0,
+            );
+        }
+        self.internal_index_price(&sale_key, &nft_contract_id, &token_id, &ft_token_id, price.0);
+        self.market.sales.insert(&sale_key, &sale);
+    }
+
+    // The sale can't be bought with `ft_token_id` anymore, the bids in it are refunded
+    #[payable]
+    pub fn remove_price(
+        &mut self,
+        nft_contract_id: AccountId,
+        token_id: String,
+        ft_token_id: FungibleTokenId,
+    ) {
+        assert_one_yocto();
+        let sale_key = sale_key(&nft_contract_id, &token_id);
+        let mut sale = self.market.sales.get(&sale_key).expect("No sale");
+        require!(
+            env::predecessor_account_id() == sale.owner_id,
+            "Only the sale owner can remove a price"
+        );
+        require!(!sale.is_locked(), "The sale is locked by a purchase");
+        let price = sale
+            .sale_conditions
+            .remove(&ft_token_id)
+            .unwrap_or_else(|| env::panic_str("The sale has no price in this token"));
+        require!(
+            !sale.sale_conditions.is_empty(),
+            "Cannot remove the last price, use remove_sale"
+        );
+        self.internal_unindex_price(
+            &sale_key,
+            &nft_contract_id,
+            &token_id,
+            &ft_token_id,
+            price.0,
+        );
+        self.market.sales.insert(&sale_key, &sale);
+        // the reserved buyer can still bid in the token of the private price
+        let refunds = self.internal_retain_bids(&sale_key, |ft_token_id, bid| {
+            sale.price_for(&bid.owner_id, ft_token_id).is_some()
+        });
+        self.internal_settle(refunds);
+    }
+
+    // Replaces the arguments of the sale as if the token was listed again with `args`,
+    // the approval, the creation time and the bids which are still valid are kept
+    // The bids in a removed token and the bids of the buyers who are not allowed anymore
+    // are refunded
+    // With `Auction` arguments the sale becomes an auction and all its bids are refunded
+    #[payable]
+    pub fn update_sale(&mut self, nft_contract_id: AccountId, token_id: String, args: ArgsKind) {
+        assert_one_yocto();
+        let sale_key = sale_key(&nft_contract_id, &token_id);
+        let sale = self.market.sales.get(&sale_key).expect("No sale");
+        require!(
+            env::predecessor_account_id() == sale.owner_id,
+            "Only the sale owner can update the sale"
+        );
+        require!(!sale.is_locked(), "The sale is locked by a purchase");
+        match args {
+            ArgsKind::Sale(sale_args) => self.internal_update_sale(sale_key, sale, sale_args),
+            ArgsKind::Auction(auction_args) => {
+                let (sale, bids) = self.internal_remove_sale(nft_contract_id, token_id);
+                self.refund_all_bids(&bids);
+                let (id, auction_json) = self.start_auction(
+                    auction_args,
+                    sale.token_id,
+                    sale.owner_id,
+                    sale.approval_id,
+                    sale.nft_contract_id,
+                    sale.custody,
+                );
+                env::log_str(
+                    &json!({
+                        "auction_id": U128(id),
+                        "auction_json": auction_json
+                    })
+                    .to_string(),
+                );
+            }
+            ArgsKind::Swap(_) => env::panic_str("A sale can't be turned into a swap"),
+        }
     }
 
-    // Offer to buy the nft
-    // Buy nft if the attached deposit equal to the price, otherwise adds a bid
+    // Offer to buy the nft, adds a bid
+    // To buy the nft at the price of the sale use `buy`
     #[payable]
     pub fn offer(
         &mut self,
@@ -289,52 +585,140 @@ impl Market {
         duration: Option<U64>,
         origins: Option<Origins>,
     ) {
-        let contract_id: AccountId = nft_contract_id;
-        let contract_and_token_id = format!("{}{}{}", contract_id, DELIMETER, token_id);
-        let mut sale = self
-            .market
-            .sales
-            .get(&contract_and_token_id)
-            .expect("No sale");
+        let sale_key = sale_key(&nft_contract_id, &token_id);
+        let sale = self.market.sales.get(&sale_key).expect("No sale");
         // Check that the sale is in progress
         require!(
             sale.in_limits(),
             "Either the sale is finished or it hasn't started yet"
         );
+        require!(!sale.is_locked(), "The sale is locked by a purchase");
 
         let buyer_id = env::predecessor_account_id();
         require!(sale.owner_id != buyer_id, "Cannot bid on your own sale.");
-        let price = *sale
-            .sale_conditions
-            .get(&ft_token_id)
-            .unwrap_or_else(|| env::panic_str("Not supported ft"));
+        require!(
+            sale.is_buyer_allowed(&buyer_id),
+            "The sale is private, the buyer is not allowed"
+        );
+        require!(
+            sale.price_for(&buyer_id, &ft_token_id).is_some(),
+            "Not supported ft"
+        );
 
         let deposit = env::attached_deposit();
         require!(deposit > 0, "Attached deposit must be greater than 0");
 
-        if deposit == calculate_price_with_fees(price, origins.as_ref()) {
-            self.process_purchase(
-                contract_id,
-                token_id,
-                ft_token_id,
-                U128(deposit),
-                buyer_id,
-                origins.unwrap_or_default(),
-            );
-        } else {
-            let start = start.unwrap_or(env::block_timestamp().into());
-            let end = duration.map(|d| U64(d.0 + start.0));
-            self.add_bid(
-                contract_and_token_id,
-                deposit,
-                ft_token_id,
-                buyer_id,
-                &mut sale,
-                start,
-                end,
-                origins,
-            );
+        let start = start.unwrap_or(env::block_timestamp().into());
+        let end = duration.map(|d| U64(d.0 + start.0));
+        self.add_bid(sale_key, deposit, ft_token_id, buyer_id, start, end, origins);
+    }
+
+    // Buys the nft for NEAR at the price of the sale plus fees, the change is refunded
+    #[payable]
+    pub fn buy(
+        &mut self,
+        nft_contract_id: AccountId,
+        token_id: String,
+        origins: Option<Origins>,
+    ) -> Promise {
+        let buyer_id = env::predecessor_account_id();
+        let ft_token_id: AccountId = "near".parse().unwrap();
+        let price = self.internal_buy_price(
+            &nft_contract_id,
+            &token_id,
+            &ft_token_id,
+            &buyer_id,
+            origins.as_ref(),
+        );
+        let deposit = env::attached_deposit();
+        require!(
+            deposit >= price,
+            format!("Attached deposit must be at least {}", price)
+        );
+        if deposit > price {
+            Promise::new(buyer_id.clone()).transfer(deposit - price);
         }
+        self.process_purchase(
+            nft_contract_id,
+            token_id,
+            ft_token_id,
+            U128(price),
+            buyer_id,
+            origins.unwrap_or_default(),
+        )
+    }
+
+    // Buys the nft for the fungible token, `msg` is the JSON of `PurchaseArgs`
+    // The change is refunded, the price is returned to the buyer if the purchase fails
+    pub fn ft_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        amount: U128,
+        msg: String,
+    ) -> PromiseOrValue<U128> {
+        let ft_token_id = env::predecessor_account_id();
+        require!(
+            self.market.ft_token_ids.contains(&ft_token_id),
+            format!("Token {} not supported by this market", ft_token_id)
+        );
+        let PurchaseArgs {
+            nft_contract_id,
+            token_id,
+            origins,
+        } = near_sdk::serde_json::from_str(&msg).expect("Not valid PurchaseArgs");
+        let price = self.internal_buy_price(
+            &nft_contract_id,
+            &token_id,
+            &ft_token_id,
+            &sender_id,
+            origins.as_ref(),
+        );
+        require!(
+            amount.0 >= price,
+            format!("Transferred amount must be at least {}", price)
+        );
+        if amount.0 > price {
+            self.internal_transfer(ft_token_id.clone(), sender_id.clone(), U128(amount.0 - price));
+        }
+        PromiseOrValue::Promise(self.process_purchase(
+            nft_contract_id,
+            token_id,
+            ft_token_id,
+            U128(price),
+            sender_id,
+            origins.unwrap_or_default(),
+        ))
+    }
+
+    // The price with fees the buyer should pay
+    pub(crate) fn internal_buy_price(
+        &self,
+        nft_contract_id: &AccountId,
+        token_id: &str,
+        ft_token_id: &FungibleTokenId,
+        buyer_id: &AccountId,
+        origins: Option<&Origins>,
+    ) -> u128 {
+        let sale = self
+            .market
+            .sales
+            .get(&sale_key(nft_contract_id, token_id))
+            .expect("No sale");
+        require!(
+            sale.in_limits(),
+            "Either the sale is finished or it hasn't started yet"
+        );
+        require!(!sale.is_locked(), "The sale is locked by a purchase");
+        require!(&sale.owner_id != buyer_id, "Cannot buy your own sale.");
+        require!(
+            sale.is_buyer_allowed(buyer_id),
+            "The sale is private, the buyer is not allowed"
+        );
+        let price = sale
+            .price_for(buyer_id, ft_token_id)
+            .unwrap_or_else(|| env::panic_str("Not supported ft"));
+        self.assert_valid_origins(origins);
+        calculate_price_with_fees(price, origins)
     }
 
     // Accepts the last (highest) offer
@@ -344,30 +728,35 @@ impl Market {
         token_id: String,
         ft_token_id: AccountId,
     ) {
-        let contract_id: AccountId = nft_contract_id;
-        let contract_and_token_id = format!("{}{}{}", contract_id, DELIMETER, token_id);
+        let sale_key = sale_key(&nft_contract_id, &token_id);
         // Check that the sale is in progress and remove bid before proceeding to process purchase
-        let mut sale = self
-            .market
-            .sales
-            .get(&contract_and_token_id)
-            .expect("No sale");
+        let sale = self.market.sales.get(&sale_key).expect("No sale");
         require!(
             sale.in_limits(),
             "Either the sale is finished or it hasn't started yet"
         );
-        let bids_for_token_id = sale.bids.remove(&ft_token_id).expect("No bids");
-        let bid = &bids_for_token_id[bids_for_token_id.len() - 1];
+        require!(!sale.is_locked(), "The sale is locked by a purchase");
+        require!(
+            env::predecessor_account_id() == sale.owner_id,
+            "Only the sale owner can accept an offer"
+        );
+        let bids_key = (sale_key, ft_token_id.clone());
+        let mut bids_for_token_id = self.market.bids.get(&bids_key).expect("No bids");
+        let bid = bids_for_token_id.pop().expect("No bids");
         require!(bid.in_limits(), "Out of time limit of the bid");
-        self.market.sales.insert(&contract_and_token_id, &sale);
-        // panics at `self.internal_remove_sale` and reverts above if predecessor is not sale.owner_id
+        require!(
+            sale.is_buyer_allowed(&bid.owner_id),
+            "The sale is private, the buyer is not allowed"
+        );
+        // the rest of the bids are refunded after the purchase
+        self.internal_set_bids(&bids_key, bids_for_token_id);
         self.process_purchase(
-            contract_id,
+            nft_contract_id,
             token_id,
             ft_token_id,
             bid.price,
-            bid.owner_id.clone(),
-            bid.origins.clone(),
+            bid.owner_id,
+            bid.origins,
         );
     }
 
@@ -381,41 +770,99 @@ impl Market {
         buyer_id: AccountId,
         origins: Origins,
     ) -> Promise {
-        let sale = self.internal_remove_sale(nft_contract_id.clone(), token_id.clone());
-        let mut buyer = origins;
-        buyer.insert(env::current_account_id(), PROTOCOL_FEE as u32);
-        let mut seller_fee = HashMap::with_capacity(sale.origins.len() + 1);
-        seller_fee.extend(sale.origins.clone()); // TODO: dodge this clone
-        seller_fee.insert(env::current_account_id(), PROTOCOL_FEE as u32);
-        let fees = fee::Fees {
-            buyer,
-            seller: seller_fee,
+        // the sale is removed only after the token is transferred
+        let sale = self.internal_lock_sale(&nft_contract_id, &token_id);
+        let fees = fees_with_protocol(origins, &sale.origins);
+        // make sure that the listing is still valid before transferring the token
+        ext_contract::nft_token(token_id, nft_contract_id, NO_DEPOSIT, GAS_FOR_NFT_TOKEN).then(
+            ext_self::resolve_verified_purchase(
+                ft_token_id,
+                buyer_id,
+                sale,
+                price,
+                fees,
+                env::current_account_id(),
+                NO_DEPOSIT,
+                GAS_FOR_VERIFIED_TRANSFER,
+            ),
+        )
+    }
+
+    // self callback
+    // If the listing is still valid - transfer the token with payouts
+    // If not - remove the sale, refund price to buyer and all the bids
+    #[private]
+    pub fn resolve_verified_purchase(
+        &mut self,
+        ft_token_id: AccountId,
+        buyer_id: AccountId,
+        sale: Sale,
+        price: U128,
+        fees: Fees,
+    ) -> PromiseOrValue<U128> {
+        let listing_status = if sale.custody {
+            custody_status_from_promise()
+        } else {
+            listing_status_from_promise(&sale.owner_id, sale.approval_id)
         };
-        ext_contract::nft_transfer_payout(
+        if listing_status != Some(true) {
+            if ft_token_id == "near".parse().unwrap() {
+                self.internal_transfer(ft_token_id.clone(), buyer_id.clone(), price);
+            }
+            let bids = self.internal_remove_locked_sale(&sale);
+            self.refund_all_bids(&bids);
+            if sale.custody {
+                return_custody_token(
+                    sale.nft_contract_id.clone(),
+                    sale.token_id.clone(),
+                    sale.owner_id.clone(),
+                );
+            }
+            env::log_str(
+                &json!({
+                    "type": "remove_stale_sale",
+                    "params": {
+                        "owner_id": sale.owner_id,
+                        "nft_contract_id": sale.nft_contract_id,
+                        "token_id": sale.token_id,
+                        "ft_token_id": ft_token_id,
+                        "price": price,
+                        "buyer_id": buyer_id,
+                    }
+                })
+                .to_string(),
+            );
+            return PromiseOrValue::Value(price);
+        }
+        let payout_mode = self.get_payout_mode(sale.nft_contract_id.clone());
+        nft_transfer_with_payout(
+            payout_mode,
+            sale.nft_contract_id.clone(),
             buyer_id.clone(),
-            token_id,
+            sale.token_id.clone(),
             sale.approval_id,
-            Some(near_sdk::serde_json::to_string(&fees).expect("Failed to sereailize")),
-            price,
-            10,
-            nft_contract_id,
-            1,
-            GAS_FOR_NFT_TRANSFER,
+            price.0,
+            &fees,
+            self.market.max_payout_len,
         )
         .then(ext_self::resolve_purchase(
             ft_token_id,
             buyer_id,
             sale,
             price,
+            fees,
+            payout_mode,
             env::current_account_id(),
             NO_DEPOSIT,
             GAS_FOR_ROYALTIES,
         ))
+        .into()
     }
 
     // self callback
-    // If transfer of token succeded - count fees and transfer payouts
-    // If failed - refund price to buyer
+    // If transfer of token succeded - remove the sale, count fees and transfer payouts,
+    // the seller gets the price without fees if the payout is not valid
+    // If failed - unlock the sale with its bids and refund price to buyer
     #[private]
     pub fn resolve_purchase(
         &mut self,
@@ -423,37 +870,59 @@ impl Market {
         buyer_id: AccountId,
         sale: Sale,
         price: U128,
+        fees: Fees,
+        payout_mode: PayoutMode,
     ) -> U128 {
+        let market_fees = market_fee_payouts(payout_mode, price.0, &fees);
+        let max_payout_len = self.market.max_payout_len as usize;
+        let result = promise_result_as_success();
+        // the token belongs to the buyer even if the payout is not valid
+        let transferred = result.is_some();
         // checking for payout information
-        let payout_option = promise_result_as_success().and_then(|value| {
+        let payout_option = result.and_then(|value| {
             // None means a bad payout from bad NFT contract
-            near_sdk::serde_json::from_slice::<Payout>(&value)
-                .ok()
-                .and_then(|payout| {
-                    // gas to do 10 FT transfers (and definitely 10 NEAR transfers)
-                    if payout.payout.len() + sale.bids.len() > 10 || payout.payout.is_empty() {
-                        env::log_str("Cannot have more than 10 royalties and sale.bids refunds");
-                        None
-                    } else {
-                        let mut remainder = price.0;
-                        for &value in payout.payout.values() {
-                            remainder = remainder.checked_sub(value.0)?;
-                        }
-                        if remainder <= 1 {
-                            Some(payout)
+            self.internal_payout_from_result(
+                payout_mode,
+                &value,
+                &sale.nft_contract_id,
+                &sale.owner_id,
+                payout_balance(payout_mode, price.0, &fees),
+            )
+            .and_then(|payout| {
+                // the transfers which don't fit this call are left for `settle`,
+                // the length is limited by the gas of the callback
+                if payout.payout.len() > max_payout_len || payout.payout.is_empty() {
+                    env::log_str(&format!("Cannot have more than {} payouts", max_payout_len));
+                    None
+                } else {
+                    let mut remainder = payout_balance(payout_mode, price.0, &fees);
+                    for &value in payout.payout.values() {
+                        remainder = remainder.checked_sub(value.0)?;
+                    }
+                    if remainder <= 1 {
+                        let payout = if sale.custody {
+                            custody_payout(payout_mode, payout, &sale.owner_id, price.0, &fees)
                         } else {
-                            None
-                        }
+                            payout
+                        };
+                        Some(with_market_fees(payout, market_fees))
+                    } else {
+                        None
                     }
-                })
+                }
+            })
         });
         // is payout option valid?
         let payout = if let Some(payout_option) = payout_option {
             payout_option
+        } else if transferred {
+            env::log_str("The payout is not valid, the seller gets the price without fees");
+            seller_payout(&sale.owner_id, price.0, &fees)
         } else {
             if ft_token_id == "near".parse().unwrap() {
-                Promise::new(buyer_id.clone()).transfer(u128::from(price));
+                self.internal_transfer(ft_token_id.clone(), buyer_id.clone(), price);
             }
+            self.internal_unlock_sale(&sale);
             // leave function and return all FTs in ft_resolve_transfer
             env::log_str(
                 &json!({
@@ -471,61 +940,92 @@ impl Market {
             );
             return price;
         };
-        // Going to payout everyone, first return all outstanding bids (accepted offer bid was already removed)
-        self.refund_all_bids(&sale.bids); // TODO: maybe should do this outside of this call, to lower gas for this call
+        let bids = self.internal_remove_locked_sale(&sale);
+        self.internal_record_trade(&sale.nft_contract_id, &sale.token_id, &ft_token_id, price.0);
+        self.internal_record_origin_earnings(&ft_token_id, price.0, &fees);
+        self.internal_add_trade(
+            sale.nft_contract_id.clone(),
+            sale.token_id.clone(),
+            sale.owner_id.clone(),
+            buyer_id.clone(),
+            ft_token_id.clone(),
+            price.0,
+            &fees,
+            &payout,
+        );
+        // Going to payout everyone and return all outstanding bids
+        // (accepted offer bid was already removed)
+        // The transfers which don't fit this call are left for `settle`
+        let mut transfers = payout_transfers(&ft_token_id, payout);
+        transfers.extend(bid_refunds(&bids));
+        self.internal_settle(transfers);
 
-        // NEAR payouts
         if ft_token_id == "near".parse().unwrap() {
-            for (receiver_id, amount) in payout.payout {
-                Promise::new(receiver_id).transfer(amount.0);
-            }
             price
         } else {
-            // FT payouts
-            for (receiver_id, amount) in payout.payout {
-                ext_contract::ft_transfer(
-                    receiver_id,
-                    amount,
-                    None,
-                    ft_token_id.clone(),
-                    1,
-                    GAS_FOR_FT_TRANSFER,
-                );
-            }
-            // keep all FTs (already transferred for payouts)
+            // keep all FTs (transferred for payouts)
             U128(0)
         }
     }
+}
 
-    // For lazy-mint situations easier resolver
-    #[private]
-    pub fn resolve_token_buy(&mut self, buyer_id: AccountId, deposit: U128, price: U128) -> U128 {
-        let payout_option = promise_result_as_success().and_then(|value| {
-            // None means a bad payout from bad NFT contract
-            near_sdk::serde_json::from_slice::<Payout>(&value)
-                .ok()
-                .and_then(|payout| {
-                    let mut remainder = price.0;
-                    for &value in payout.payout.values() {
-                        remainder = remainder.checked_sub(value.0)?;
-                    }
-                    if remainder <= 1 {
-                        Some(payout)
-                    } else {
-                        None
-                    }
-                })
-        });
-        let payout = if let Some(payout_option) = payout_option {
-            payout_option
-        } else {
-            Promise::new(buyer_id).transfer(u128::from(deposit));
-            return price;
-        };
-        for (receiver_id, amount) in payout.payout {
-            Promise::new(receiver_id).transfer(amount.0);
+impl Market {
+    pub(crate) fn assert_supported_ft_tokens(
+        &self,
+        sale_conditions: &SaleConditions,
+        reserved_buyer: Option<&ReservedBuyer>,
+    ) {
+        let reserved_ft_token_ids = reserved_buyer
+            .iter()
+            .flat_map(|reserved| reserved.sale_conditions.keys());
+        for ft_token_id in sale_conditions.keys().chain(reserved_ft_token_ids) {
+            if !self.market.ft_token_ids.contains(ft_token_id) {
+                env::panic_str(&format!(
+                    "Token {} not supported by this market",
+                    ft_token_id
+                ));
+            }
         }
-        price
+    }
+
+    fn internal_update_sale(&mut self, sale_key: SaleKey, mut sale: Sale, args: SaleArgs) {
+        let SaleArgs {
+            sale_conditions,
+            token_type,
+            start,
+            end,
+            origins,
+            allowed_buyers,
+            reserved_buyer,
+        } = args;
+        self.assert_supported_ft_tokens(&sale_conditions, reserved_buyer.as_ref());
+        self.assert_valid_origins(origins.as_ref());
+
+        self.internal_unindex_sale(&sale_key, &sale);
+        self.internal_unindex_token_type(&sale_key, &sale.token_type);
+        sale.sale_conditions = sale_conditions;
+        sale.token_type = token_type;
+        sale.start = Some(start.map(|s| s.into()).unwrap_or_else(env::block_timestamp));
+        sale.end = end.map(|e| e.into());
+        sale.origins = origins.unwrap_or_default();
+        sale.allowed_buyers = allowed_buyers;
+        sale.reserved_buyer = reserved_buyer;
+        self.internal_index_token_type(&sale_key, &sale.token_id, &sale.token_type);
+        self.internal_index_sale(&sale_key, &sale);
+        self.market.sales.insert(&sale_key, &sale);
+
+        let refunds = self.internal_retain_bids(&sale_key, |ft_token_id, bid| {
+            sale.is_buyer_allowed(&bid.owner_id)
+                && sale.price_for(&bid.owner_id, ft_token_id).is_some()
+        });
+        self.internal_settle(refunds);
+        env::log_str(
+            &json!({
+                "type": "update_sale",
+                "params": self.json_from_sale(sale),
+            })
+            .to_string(),
+        );
     }
 }
 
@@ -533,31 +1033,78 @@ impl Market {
 
 #[ext_contract(ext_self)]
 trait ExtSelf {
+    fn resolve_transfer(&mut self, ft_token_id: AccountId, receiver_id: AccountId, amount: U128);
+
+    fn resolve_cache_royalty(&mut self, nft_contract_id: AccountId, token_id: String);
+
+    fn resolve_verify_listing(
+        &mut self,
+        nft_contract_id: AccountId,
+        token_id: String,
+        owner_id: AccountId,
+        approval_id: u64,
+    ) -> bool;
+
+    fn resolve_verify_auction(
+        &mut self,
+        auction_id: U128,
+        owner_id: AccountId,
+        approval_id: u64,
+    ) -> bool;
+
+    fn resolve_verified_purchase(
+        &mut self,
+        ft_token_id: AccountId,
+        buyer_id: AccountId,
+        sale: Sale,
+        price: U128,
+        fees: Fees,
+    ) -> Promise;
+
+    fn resolve_verified_finish_auction(&mut self, auction: Auction, fees: Fees) -> Promise;
+
     fn resolve_purchase(
         &mut self,
         ft_token_id: AccountId,
         buyer_id: AccountId,
         sale: Sale,
         price: U128,
+        fees: Fees,
+        payout_mode: PayoutMode,
     ) -> Promise;
 
-    fn resolve_finish_auction(&mut self, ft_token_id: AccountId, buyer_id: AccountId, price: U128);
+    fn resolve_finish_auction(&mut self, auction: Auction, fees: Fees, payout_mode: PayoutMode);
+
+    fn resolve_swap(
+        &mut self,
+        swap_id: U64,
+        counterparty_id: AccountId,
+        approval_id: u64,
+    ) -> bool;
 
     fn resolve_mint(
         &mut self,
         nft_contract_id: AccountId,
+        series_id: TokenSeriesId,
+        owner_id: AccountId,
         buyer_id: AccountId,
-        deposit: U128,
         price: U128,
-    ) -> Promise;
-
-    fn resolve_token_buy(&mut self, buyer_id: AccountId, deposit: U128, price: U128) -> Promise;
+        fees: Fees,
+        phase: Option<u32>,
+    ) -> Option<TokenId>;
 }
 
 /// external contract calls
 
 #[ext_contract(ext_contract)]
 trait ExtContract {
+    fn nft_transfer(
+        &mut self,
+        receiver_id: AccountId,
+        token_id: TokenId,
+        approval_id: Option<u64>,
+        memo: Option<String>,
+    );
     fn nft_transfer_payout(
         &mut self,
         receiver_id: AccountId,
@@ -568,6 +1115,12 @@ trait ExtContract {
         max_len_payout: u32,
     ) -> Promise;
     fn ft_transfer(&mut self, receiver_id: AccountId, amount: U128, memo: Option<String>);
-    fn nft_mint(&mut self, token_series_id: TokenSeriesId, receiver_id: AccountId);
+    fn nft_mint(
+        &mut self,
+        token_series_id: TokenSeriesId,
+        receiver_id: AccountId,
+        refund_id: Option<AccountId>,
+    ) -> TokenId;
     fn nft_payout(&self, token_id: String, balance: U128, max_len_payout: u32) -> Payout;
+    fn nft_token(&self, token_id: TokenId) -> Option<Token>;
 }
@@ -1,8 +1,12 @@
+use near_sdk::json_types::Base58CryptoHash;
+
 use crate::common::*;
 use crate::*;
 
-use crate::sale::{SaleJson, DELIMETER};
-use std::cmp::min;
+use crate::fee::{calculate_actual_amount, calculate_origins};
+use crate::pagination::{iter_after, take_page, Page};
+use crate::sale::{sale_key, SaleJson};
+use crate::search::{SearchCursor, SortKey};
 
 #[near_bindgen]
 impl Market {
@@ -11,20 +15,26 @@ impl Market {
         U64(self.market.sales.len())
     }
 
+    // The oldest sales first
     pub fn get_sales(
         &self,
-        from_index: Option<U128>,
+        cursor: Option<SearchCursor>,
         limit: Option<u64>,
-    ) -> Vec<SaleJson> {
-        let sales = &self.market.sales;
-        let start_index: u128 = from_index.map(From::from).unwrap_or_default();
-        let limit = limit.map(|v| v as usize).unwrap_or(usize::MAX);
-        sales
-            .values()
-            .skip(start_index as usize)
-            .take(limit)
-            .map(|sale| self.json_from_sale(sale))
-            .collect()
+    ) -> Page<SaleJson, SearchCursor> {
+        let (keys, next_cursor) = take_page(
+            iter_after(&self.market.by_created_at, cursor.map(SortKey::from)),
+            limit,
+            |(key, _)| SearchCursor::from(*key),
+        );
+        Page {
+            items: keys
+                .into_iter()
+                .map(|((_, sale_key), _)| {
+                    self.json_from_sale(self.market.sales.get(&sale_key).unwrap())
+                })
+                .collect(),
+            next_cursor,
+        }
     }
 
     pub fn get_supply_by_owner_id(&self, account_id: AccountId) -> U64 {
@@ -39,24 +49,10 @@ impl Market {
     pub fn get_sales_by_owner_id(
         &self,
         account_id: AccountId,
-        from_index: U64,
-        limit: u64,
-    ) -> Vec<SaleJson> {
-        let mut tmp = vec![];
-        let by_owner_id = self.market.by_owner_id.get(&account_id);
-        let sales = if let Some(by_owner_id) = by_owner_id {
-            by_owner_id
-        } else {
-            return vec![];
-        };
-        let keys = sales.as_vector();
-        let start = u64::from(from_index);
-        let end = min(start + limit, sales.len());
-        for i in start..end {
-            let sale = self.market.sales.get(&keys.get(i).unwrap()).unwrap();
-            tmp.push(self.json_from_sale(sale));
-        }
-        tmp
+        cursor: Option<Base58CryptoHash>,
+        limit: Option<u64>,
+    ) -> Page<SaleJson, Base58CryptoHash> {
+        self.sales_page(self.market.by_owner_id.get(&account_id), cursor, limit)
     }
 
     pub fn get_supply_by_nft_contract_id(&self, nft_contract_id: AccountId) -> U64 {
@@ -71,34 +67,10 @@ impl Market {
     pub fn get_sales_by_nft_contract_id(
         &self,
         nft_contract_id: AccountId,
-        from_index: U64,
-        limit: u64,
-    ) -> Vec<SaleJson> {
-        let mut tmp = vec![];
-        let by_nft_contract_id = self.market.by_nft_contract_id.get(&nft_contract_id);
-        let sales = if let Some(by_nft_contract_id) = by_nft_contract_id {
-            by_nft_contract_id
-        } else {
-            return vec![];
-        };
-        let keys = sales.as_vector();
-        let start = u64::from(from_index);
-        let end = min(start + limit, sales.len());
-        for i in start..end {
-            let sale = self
-                .market
-                .sales
-                .get(&format!(
-                    "{}{}{}",
-                    &nft_contract_id,
-                    DELIMETER,
-                    &keys.get(i).unwrap()
-                ))
-                .unwrap();
-            let sale_json = self.json_from_sale(sale);
-            tmp.push(sale_json);
-        }
-        tmp
+        cursor: Option<Base58CryptoHash>,
+        limit: Option<u64>,
+    ) -> Page<SaleJson, Base58CryptoHash> {
+        self.sales_page(self.market.by_nft_contract_id.get(&nft_contract_id), cursor, limit)
     }
 
     pub fn get_supply_by_nft_token_type(&self, token_type: String) -> U64 {
@@ -113,48 +85,90 @@ impl Market {
     pub fn get_sales_by_nft_token_type(
         &self,
         token_type: String,
-        from_index: U64,
-        limit: u64,
-    ) -> Vec<SaleJson> {
-        let mut tmp = vec![];
-        let by_nft_token_type = self.market.by_nft_token_type.get(&token_type);
-        let sales = if let Some(by_nft_token_type) = by_nft_token_type {
-            by_nft_token_type
-        } else {
-            return vec![];
-        };
-        let keys = sales.as_vector();
-        let start = u64::from(from_index);
-        let end = min(start + limit, sales.len());
-        for i in start..end {
-            let sale = self.market.sales.get(&keys.get(i).unwrap()).unwrap();
-            let sale_json = self.json_from_sale(sale);
-            tmp.push(sale_json);
-        }
-        tmp
+        cursor: Option<Base58CryptoHash>,
+        limit: Option<u64>,
+    ) -> Page<SaleJson, Base58CryptoHash> {
+        self.sales_page(self.market.by_nft_token_type.get(&token_type), cursor, limit)
     }
 
     pub fn get_sale(&self, nft_contract_id: AccountId, token_id: TokenId) -> Option<SaleJson> {
-        let contract_and_token_id = format!("{}{}{}", &nft_contract_id, DELIMETER, token_id);
         self.market
             .sales
-            .get(&contract_and_token_id)
+            .get(&sale_key(&nft_contract_id, &token_id))
             .map(|sale| self.json_from_sale(sale))
     }
 
+    pub fn get_bids(
+        &self,
+        nft_contract_id: AccountId,
+        token_id: TokenId,
+        ft_token_id: AccountId,
+        cursor: Option<U128>,
+        limit: Option<u64>,
+    ) -> Page<Bid, U128> {
+        let bids = self
+            .market
+            .bids
+            .get(&(sale_key(&nft_contract_id, &token_id), ft_token_id))
+            .unwrap_or_default();
+        // every bid is higher than the previous one without the origin fees,
+        // this amount of the last bid is the cursor
+        let amount =
+            |bid: &Bid| calculate_actual_amount(bid.price.0, calculate_origins(&bid.origins));
+        let (items, next_cursor) = take_page(
+            bids.into_iter()
+                .filter(|bid| cursor.map_or(true, |cursor| amount(bid) > cursor.0)),
+            limit,
+            |bid| U128(amount(bid)),
+        );
+        Page { items, next_cursor }
+    }
+
+    fn sales_page(
+        &self,
+        index: Option<TreeMap<SaleKey, ()>>,
+        cursor: Option<Base58CryptoHash>,
+        limit: Option<u64>,
+    ) -> Page<SaleJson, Base58CryptoHash> {
+        let index = if let Some(index) = index {
+            index
+        } else {
+            return Page { items: vec![], next_cursor: None };
+        };
+        let (keys, next_cursor) = take_page(
+            iter_after(&index, cursor.map(SaleKey::from)),
+            limit,
+            |(sale_key, _)| Base58CryptoHash::from(*sale_key),
+        );
+        Page {
+            items: keys
+                .into_iter()
+                .map(|(sale_key, _)| self.json_from_sale(self.market.sales.get(&sale_key).unwrap()))
+                .collect(),
+            next_cursor,
+        }
+    }
+
     pub(crate) fn json_from_sale(&self, sale: Sale) -> SaleJson {
+        let is_private = sale.is_private();
+        let bids = self.internal_get_bids(&sale_key(&sale.nft_contract_id, &sale.token_id));
         SaleJson {
             owner_id: sale.owner_id,
             nft_contract_id: sale.nft_contract_id,
             token_id: sale.token_id,
             sale_conditions: sale.sale_conditions,
-            bids: sale.bids,
+            bids,
             created_at: sale.created_at.into(),
             token_type: sale.token_type,
 
             start: sale.start.map(|s| s.into()),
             end: sale.end.map(|e| e.into()),
+            is_private,
             origins: sale.origins,
+            allowed_buyers: sale.allowed_buyers,
+            reserved_buyer: sale.reserved_buyer.map(|reserved| reserved.buyer_id),
+            locked: sale.is_locked(),
+            custody: sale.custody,
         }
     }
 }
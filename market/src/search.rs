@@ -0,0 +1,318 @@
+use std::ops::Bound;
+
+use near_sdk::json_types::Base58CryptoHash;
+
+use crate::pagination::{Page, PAGE_SIZE_DEFAULT, PAGE_SIZE_MAX};
+use crate::sale::{Sale, SaleJson, TokenSeriesId, DELIMETER};
+use crate::stats::series_id;
+use crate::*;
+
+// The number of index entries `search_sales` reads at most, even if the page is not full
+pub const SEARCH_SCAN_MAX: u64 = 200;
+
+// Key of the ordered indices: the price or the creation time, and the sale key for ties
+pub type SortKey = (u128, SaleKey);
+// Sales of the NFT contract (or of its series), ordered by the price in the given fungible token
+pub type PriceIndexKey = (AccountId, Option<TokenSeriesId>, FungibleTokenId);
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SaleFilter {
+    pub nft_contract_id: Option<AccountId>,
+    pub token_type: Option<String>,
+    pub ft_token_id: Option<FungibleTokenId>,
+    // The price filter requires `ft_token_id`
+    pub price_min: Option<U128>,
+    pub price_max: Option<U128>,
+    pub in_progress: Option<bool>,
+    pub has_bids: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy)]
+#[serde(crate = "near_sdk::serde")]
+pub enum SaleSort {
+    Newest,
+    Oldest,
+    // Sorting by price requires `nft_contract_id` and `ft_token_id`
+    PriceAsc,
+    PriceDesc,
+}
+
+// The position in the index after the last returned sale
+#[derive(Serialize, Deserialize, Clone)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub struct SearchCursor {
+    pub value: U128,
+    pub sale_key: Base58CryptoHash,
+}
+
+impl From<SortKey> for SearchCursor {
+    fn from((value, sale_key): SortKey) -> Self {
+        Self {
+            value: U128(value),
+            sale_key: sale_key.into(),
+        }
+    }
+}
+
+impl From<SearchCursor> for SortKey {
+    fn from(cursor: SearchCursor) -> Self {
+        (cursor.value.0, cursor.sale_key.into())
+    }
+}
+
+#[near_bindgen]
+impl Market {
+    // Returns the sales matching the filter, the newest first by default
+    // The page may have less than `limit` sales (even none) while `next_cursor` is not null,
+    // because at most `SEARCH_SCAN_MAX` sales are checked per call
+    pub fn search_sales(
+        &self,
+        filter: SaleFilter,
+        sort: Option<SaleSort>,
+        cursor: Option<SearchCursor>,
+        limit: Option<u64>,
+    ) -> Page<SaleJson, SearchCursor> {
+        let sort = sort.unwrap_or(SaleSort::Newest);
+        let limit = limit.unwrap_or(PAGE_SIZE_DEFAULT).min(PAGE_SIZE_MAX) as usize;
+        let cursor = cursor.map(SortKey::from);
+        let price_range = (
+            filter.price_min.map(u128::from).unwrap_or(0),
+            filter.price_max.map(u128::from).unwrap_or(u128::MAX),
+        );
+        require!(
+            (filter.price_min.is_none() && filter.price_max.is_none())
+                || filter.ft_token_id.is_some(),
+            "The price filter requires ft_token_id"
+        );
+
+        let price_index = match sort {
+            SaleSort::PriceAsc | SaleSort::PriceDesc => {
+                let index_key = match (&filter.nft_contract_id, &filter.ft_token_id) {
+                    (Some(nft_contract_id), Some(ft_token_id)) => {
+                        (nft_contract_id.clone(), None, ft_token_id.clone())
+                    }
+                    _ => env::panic_str(
+                        "Sorting by price requires nft_contract_id and ft_token_id",
+                    ),
+                };
+                match self.market.by_price.get(&index_key) {
+                    Some(index) => Some(index),
+                    None => {
+                        return Page {
+                            items: vec![],
+                            next_cursor: None,
+                        }
+                    }
+                }
+            }
+            _ => None,
+        };
+        let keys: Box<dyn Iterator<Item = SortKey> + '_> = match (sort, &price_index) {
+            (SaleSort::PriceAsc, Some(index)) => {
+                let lower = cursor
+                    .map_or(Bound::Included((price_range.0, [0; 32])), Bound::Excluded);
+                let upper = (price_range.1, [u8::MAX; 32]);
+                if cursor.map_or(price_range.0 > price_range.1, |cursor| cursor >= upper) {
+                    Box::new(std::iter::empty())
+                } else {
+                    Box::new(index.range((lower, Bound::Included(upper))).map(|(key, _)| key))
+                }
+            }
+            (SaleSort::PriceDesc, Some(index)) => {
+                let upper = cursor.unwrap_or((price_range.1, [u8::MAX; 32]));
+                Box::new(
+                    index
+                        .iter_rev_from(upper)
+                        .map(|(key, _)| key)
+                        .take_while(move |(price, _)| *price >= price_range.0),
+                )
+            }
+            (SaleSort::Oldest, _) => Box::new(
+                match cursor {
+                    Some(cursor) => self.market.by_created_at.iter_from(cursor),
+                    None => self.market.by_created_at.iter(),
+                }
+                .map(|(key, _)| key),
+            ),
+            _ => Box::new(
+                match cursor {
+                    Some(cursor) => self.market.by_created_at.iter_rev_from(cursor),
+                    None => self.market.by_created_at.iter_rev(),
+                }
+                .map(|(key, _)| key),
+            ),
+        };
+
+        let mut items = vec![];
+        let mut last_key = None;
+        let mut scanned = 0;
+        let mut keys = keys.peekable();
+        while items.len() < limit && scanned < SEARCH_SCAN_MAX {
+            let key = match keys.next() {
+                Some(key) => key,
+                None => break,
+            };
+            scanned += 1;
+            last_key = Some(key);
+            let sale = match self.market.sales.get(&key.1) {
+                Some(sale) => sale,
+                None => continue,
+            };
+            if self.sale_matches(&key.1, &sale, &filter, price_range) {
+                items.push(self.json_from_sale(sale));
+            }
+        }
+        let next_cursor = if keys.peek().is_some() {
+            last_key.map(SearchCursor::from)
+        } else {
+            None
+        };
+        Page { items, next_cursor }
+    }
+}
+
+impl Market {
+    fn sale_matches(
+        &self,
+        sale_key: &SaleKey,
+        sale: &Sale,
+        filter: &SaleFilter,
+        price_range: (u128, u128),
+    ) -> bool {
+        if let Some(nft_contract_id) = &filter.nft_contract_id {
+            if &sale.nft_contract_id != nft_contract_id {
+                return false;
+            }
+        }
+        if filter.token_type.is_some() && sale.token_type != filter.token_type {
+            return false;
+        }
+        if let Some(ft_token_id) = &filter.ft_token_id {
+            match sale.sale_conditions.get(ft_token_id) {
+                Some(price) if price_range.0 <= price.0 && price.0 <= price_range.1 => {}
+                _ => return false,
+            }
+        }
+        if let Some(in_progress) = filter.in_progress {
+            if sale.in_limits() != in_progress {
+                return false;
+            }
+        }
+        if let Some(has_bids) = filter.has_bids {
+            let bids = self.internal_get_bids(sale_key);
+            if bids.values().any(|bids| !bids.is_empty()) != has_bids {
+                return false;
+            }
+        }
+        true
+    }
+
+    // Adds the sale to the creation time index and to the price indices of every its currency
+    pub(crate) fn internal_index_sale(&mut self, sale_key: &SaleKey, sale: &Sale) {
+        // the sale which is already indexed is counted as listed once
+        let first_insert = self
+            .market
+            .by_created_at
+            .insert(&(u128::from(sale.created_at), *sale_key), &())
+            .is_none();
+        for (ft_token_id, price) in sale.sale_conditions.iter() {
+            self.internal_index_price(
+                sale_key,
+                &sale.nft_contract_id,
+                &sale.token_id,
+                ft_token_id,
+                price.0,
+            );
+        }
+        if first_insert {
+            self.internal_update_listed(&sale.nft_contract_id, &sale.token_id, true);
+        }
+    }
+
+    pub(crate) fn internal_unindex_sale(&mut self, sale_key: &SaleKey, sale: &Sale) {
+        let was_indexed = self
+            .market
+            .by_created_at
+            .remove(&(u128::from(sale.created_at), *sale_key))
+            .is_some();
+        for (ft_token_id, price) in sale.sale_conditions.iter() {
+            self.internal_unindex_price(
+                sale_key,
+                &sale.nft_contract_id,
+                &sale.token_id,
+                ft_token_id,
+                price.0,
+            );
+        }
+        if was_indexed {
+            self.internal_update_listed(&sale.nft_contract_id, &sale.token_id, false);
+        }
+    }
+
+    // The sale is indexed both for the whole contract and for its series
+    pub(crate) fn internal_index_price(
+        &mut self,
+        sale_key: &SaleKey,
+        nft_contract_id: &AccountId,
+        token_id: &str,
+        ft_token_id: &FungibleTokenId,
+        price: u128,
+    ) {
+        for series_id in price_index_series(token_id) {
+            let index_key = (nft_contract_id.clone(), series_id, ft_token_id.clone());
+            let mut by_price = self.market.by_price.get(&index_key).unwrap_or_else(|| {
+                TreeMap::new(
+                    StorageKey::ByPriceInner {
+                        index_hash: price_index_hash(&index_key),
+                    }
+                    .try_to_vec()
+                    .unwrap(),
+                )
+            });
+            by_price.insert(&(price, *sale_key), &());
+            self.market.by_price.insert(&index_key, &by_price);
+        }
+    }
+
+    pub(crate) fn internal_unindex_price(
+        &mut self,
+        sale_key: &SaleKey,
+        nft_contract_id: &AccountId,
+        token_id: &str,
+        ft_token_id: &FungibleTokenId,
+        price: u128,
+    ) {
+        for series_id in price_index_series(token_id) {
+            let index_key = (nft_contract_id.clone(), series_id, ft_token_id.clone());
+            let mut by_price = self
+                .market
+                .by_price
+                .get(&index_key)
+                .expect("No sale by price");
+            by_price.remove(&(price, *sale_key));
+            if by_price.is_empty() {
+                self.market.by_price.remove(&index_key);
+            } else {
+                self.market.by_price.insert(&index_key, &by_price);
+            }
+        }
+    }
+}
+
+// The index of the whole contract and the index of the series (if the token has one)
+fn price_index_series(token_id: &str) -> impl Iterator<Item = Option<TokenSeriesId>> {
+    std::iter::once(None).chain(series_id(token_id).map(Some))
+}
+
+fn price_index_hash((nft_contract_id, series_id, ft_token_id): &PriceIndexKey) -> CryptoHash {
+    let index = if let Some(series_id) = series_id {
+        format!("{}{}{}{}{}", nft_contract_id, DELIMETER, series_id, DELIMETER, ft_token_id)
+    } else {
+        format!("{}{}{}", nft_contract_id, DELIMETER, ft_token_id)
+    };
+    let mut hash = CryptoHash::default();
+    hash.copy_from_slice(&env::sha256(index.as_bytes()));
+    hash
+}
@@ -0,0 +1,329 @@
+use near_sdk::json_types::Base58CryptoHash;
+use near_sdk::serde_json::json;
+use near_sdk::{promise_result_as_success, Gas};
+
+use crate::bid::Origins;
+use crate::drop_phase::DropPhase;
+use crate::fee::{
+    calculate_fees_amount, calculate_price_with_fees, fee_payouts, fees_with_protocol, Fees,
+};
+use crate::pagination::{iter_after, take_page, Page};
+use crate::sale::{
+    ext_contract, ext_self, sale_key, Payout, SaleConditions, TokenSeriesId, NO_DEPOSIT,
+};
+use crate::settlement::payout_transfers;
+use crate::token::TokenSeriesSale;
+use crate::*;
+
+pub const GAS_FOR_MINT: Gas = Gas(20_000_000_000_000);
+pub const GAS_FOR_RESOLVE_MINT: Gas = Gas(60_000_000_000_000);
+// Attached to `nft_mint` for the storage of the new token, the rest is refunded to the buyer
+pub const STORAGE_FOR_MINT: Balance = 10_000_000_000_000_000_000_000;
+
+// Copies of the series which are minted to the buyer on purchase (primary sale)
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct SeriesSale {
+    pub owner_id: AccountId,
+    pub nft_contract_id: AccountId,
+    pub series_id: TokenSeriesId,
+    pub sale_conditions: SaleConditions,
+    pub created_at: u64,
+    // The copies which can still be bought
+    pub copies: u64,
+    // The copies paid for, but not minted yet
+    pub minting: u64,
+    // The copies taken by raffles which are not drawn or minted yet,
+    // the series sale is kept until they are minted
+    pub raffled: u64,
+    pub sold: u64,
+    // Empty if the series sale is public all the time
+    pub phases: Vec<DropPhase>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub struct SeriesSaleJson {
+    pub owner_id: AccountId,
+    pub nft_contract_id: AccountId,
+    pub series_id: TokenSeriesId,
+    pub sale_conditions: SaleConditions,
+    pub created_at: U64,
+    pub copies: U64,
+    pub minting: U64,
+    pub raffled: U64,
+    pub sold: U64,
+    pub phases: Vec<DropPhase>,
+}
+
+#[near_bindgen]
+impl Market {
+    // Called by `nft_series_market_approve` of the NFT contract
+    // Creates the series sale or replaces its copies and prices
+    pub fn nft_on_series_approve(&mut self, token_series: TokenSeriesSale) {
+        let nft_contract_id = env::predecessor_account_id();
+        let signer_id = env::signer_account_id();
+        require!(
+            nft_contract_id != signer_id,
+            "nft_on_series_approve should only be called via cross-contract call"
+        );
+        require!(
+            token_series.owner_id == signer_id,
+            "owner_id should be signer_id"
+        );
+        require!(
+            token_series.copies > 0,
+            "The number of copies should be positive"
+        );
+        for ft_token_id in token_series.sale_conditions.keys() {
+            if !self.market.ft_token_ids.contains(ft_token_id) {
+                env::panic_str(&format!(
+                    "Token {} not supported by this market",
+                    ft_token_id
+                ));
+            }
+        }
+
+        let key = sale_key(&nft_contract_id, &token_series.series_id);
+        let previous = self.market.series_sales.get(&key);
+        if previous.is_none() {
+            self.internal_add_listing_storage(&signer_id);
+        }
+        let (minting, raffled, sold, phases) = previous.map_or((0, 0, 0, Vec::new()), |sale| {
+            (sale.minting, sale.raffled, sale.sold, sale.phases)
+        });
+        let series_sale = SeriesSale {
+            owner_id: token_series.owner_id,
+            nft_contract_id,
+            series_id: token_series.series_id,
+            sale_conditions: token_series.sale_conditions,
+            created_at: env::block_timestamp(),
+            copies: token_series.copies,
+            minting,
+            raffled,
+            sold,
+            phases,
+        };
+        self.market.series_sales.insert(&key, &series_sale);
+        env::log_str(
+            &json!({
+                "type": "series_sale",
+                "params": json_from_series_sale(series_sale),
+            })
+            .to_string(),
+        );
+    }
+
+    // Buys one copy of the series for NEAR, the market mints it to the buyer
+    // The active phase decides who can buy, at which price and how many copies
+    // The deposit should cover the price with fees and `STORAGE_FOR_MINT`, the change is refunded
+    #[payable]
+    pub fn buy_series(
+        &mut self,
+        nft_contract_id: AccountId,
+        series_id: TokenSeriesId,
+        origins: Option<Origins>,
+    ) -> Promise {
+        let buyer_id = env::predecessor_account_id();
+        let key = sale_key(&nft_contract_id, &series_id);
+        let mut series_sale = self.market.series_sales.get(&key).expect("No series sale");
+        require!(
+            series_sale.owner_id != buyer_id,
+            "Cannot buy your own sale."
+        );
+        require!(series_sale.copies > 0, "No copies left");
+        self.assert_valid_origins(origins.as_ref());
+        let ft_token_id: AccountId = "near".parse().unwrap();
+        let (phase, phase_price) = self.internal_use_phase(&key, &series_sale, &buyer_id);
+        let price = phase_price
+            .or_else(|| series_sale.sale_conditions.get(&ft_token_id).copied())
+            .unwrap_or_else(|| env::panic_str("Not supported ft"));
+        let price = calculate_price_with_fees(price, origins.as_ref());
+        let required = price + STORAGE_FOR_MINT;
+        let deposit = env::attached_deposit();
+        require!(
+            deposit >= required,
+            format!("Attached deposit must be at least {}", required)
+        );
+        if deposit > required {
+            Promise::new(buyer_id.clone()).transfer(deposit - required);
+        }
+        // the copy is reserved until the token is minted
+        series_sale.copies -= 1;
+        series_sale.minting += 1;
+        self.market.series_sales.insert(&key, &series_sale);
+        let fees = fees_with_protocol(origins.unwrap_or_default(), &Origins::new());
+        ext_contract::nft_mint(
+            series_id.clone(),
+            buyer_id.clone(),
+            Some(buyer_id.clone()),
+            nft_contract_id.clone(),
+            STORAGE_FOR_MINT,
+            GAS_FOR_MINT,
+        )
+        .then(ext_self::resolve_mint(
+            nft_contract_id,
+            series_id,
+            series_sale.owner_id,
+            buyer_id,
+            U128(price),
+            fees,
+            phase,
+            env::current_account_id(),
+            NO_DEPOSIT,
+            GAS_FOR_RESOLVE_MINT,
+        ))
+    }
+
+    // self callback
+    // If the token is minted - pay the fees and the proceeds to the series owner
+    // If not - return the copy to the sale and refund the price with the storage deposit
+    #[private]
+    #[allow(clippy::too_many_arguments)]
+    pub fn resolve_mint(
+        &mut self,
+        nft_contract_id: AccountId,
+        series_id: TokenSeriesId,
+        owner_id: AccountId,
+        buyer_id: AccountId,
+        price: U128,
+        fees: Fees,
+        phase: Option<u32>,
+    ) -> Option<TokenId> {
+        let ft_token_id: AccountId = "near".parse().unwrap();
+        let key = sale_key(&nft_contract_id, &series_id);
+        let series_sale = self.market.series_sales.get(&key);
+        let token_id = promise_result_as_success()
+            .and_then(|value| near_sdk::serde_json::from_slice::<TokenId>(&value).ok());
+        let token_id = if let Some(token_id) = token_id {
+            token_id
+        } else {
+            // the series sale could have been removed meanwhile
+            if let Some(mut series_sale) = series_sale {
+                series_sale.copies += 1;
+                series_sale.minting -= 1;
+                self.market.series_sales.insert(&key, &series_sale);
+            }
+            self.internal_revert_phase(&key, phase, &buyer_id);
+            self.internal_transfer(
+                ft_token_id,
+                buyer_id.clone(),
+                U128(price.0 + STORAGE_FOR_MINT),
+            );
+            env::log_str(
+                &json!({
+                    "type": "resolve_mint_fail",
+                    "params": {
+                        "nft_contract_id": nft_contract_id,
+                        "series_id": series_id,
+                        "buyer_id": buyer_id,
+                        "price": price,
+                    }
+                })
+                .to_string(),
+            );
+            return None;
+        };
+        if let Some(mut series_sale) = series_sale {
+            series_sale.minting -= 1;
+            series_sale.sold += 1;
+            if series_sale.copies == 0 && series_sale.minting == 0 && series_sale.raffled == 0 {
+                self.internal_remove_series_sale(&key, &series_sale.owner_id);
+            } else {
+                self.market.series_sales.insert(&key, &series_sale);
+            }
+        }
+
+        // The protocol fee stays on the market, the rest of the fees go to the origins
+        let mut payout = fee_payouts(price.0, &fees);
+        payout.remove(&env::current_account_id());
+        payout.retain(|_, amount| *amount > 0);
+        *payout.entry(owner_id.clone()).or_insert(0) +=
+            price.0 - calculate_fees_amount(price.0, &fees);
+        let payout = Payout {
+            payout: payout
+                .into_iter()
+                .map(|(account_id, amount)| (account_id, U128(amount)))
+                .collect(),
+        };
+        self.internal_record_trade(&nft_contract_id, &token_id, &ft_token_id, price.0);
+        self.internal_record_origin_earnings(&ft_token_id, price.0, &fees);
+        self.internal_add_trade(
+            nft_contract_id,
+            token_id.clone(),
+            owner_id,
+            buyer_id,
+            ft_token_id.clone(),
+            price.0,
+            &fees,
+            &payout,
+        );
+        self.internal_settle(payout_transfers(&ft_token_id, payout));
+        Some(token_id)
+    }
+
+    // The copies being minted are still paid to the owner
+    #[payable]
+    pub fn remove_series_sale(&mut self, nft_contract_id: AccountId, series_id: TokenSeriesId) {
+        assert_one_yocto();
+        let key = sale_key(&nft_contract_id, &series_id);
+        let series_sale = self.market.series_sales.get(&key).expect("No series sale");
+        require!(
+            env::predecessor_account_id() == series_sale.owner_id,
+            "Only the series owner can remove the series sale"
+        );
+        self.internal_remove_series_sale(&key, &series_sale.owner_id);
+    }
+
+    pub fn get_series_sale(
+        &self,
+        nft_contract_id: AccountId,
+        series_id: TokenSeriesId,
+    ) -> Option<SeriesSaleJson> {
+        self.market
+            .series_sales
+            .get(&sale_key(&nft_contract_id, &series_id))
+            .map(json_from_series_sale)
+    }
+
+    pub fn get_series_sales(
+        &self,
+        cursor: Option<Base58CryptoHash>,
+        limit: Option<u64>,
+    ) -> Page<SeriesSaleJson, Base58CryptoHash> {
+        let (series_sales, next_cursor) = take_page(
+            iter_after(&self.market.series_sales, cursor.map(SaleKey::from)),
+            limit,
+            |(key, _)| Base58CryptoHash::from(*key),
+        );
+        Page {
+            items: series_sales
+                .into_iter()
+                .map(|(_, series_sale)| json_from_series_sale(series_sale))
+                .collect(),
+            next_cursor,
+        }
+    }
+}
+
+impl Market {
+    fn internal_remove_series_sale(&mut self, key: &SaleKey, owner_id: &AccountId) {
+        self.market.series_sales.remove(key);
+        self.internal_release_listing_storage(owner_id);
+    }
+}
+
+fn json_from_series_sale(series_sale: SeriesSale) -> SeriesSaleJson {
+    SeriesSaleJson {
+        owner_id: series_sale.owner_id,
+        nft_contract_id: series_sale.nft_contract_id,
+        series_id: series_sale.series_id,
+        sale_conditions: series_sale.sale_conditions,
+        created_at: U64(series_sale.created_at),
+        copies: U64(series_sale.copies),
+        minting: U64(series_sale.minting),
+        raffled: U64(series_sale.raffled),
+        sold: U64(series_sale.sold),
+        phases: series_sale.phases,
+    }
+}
@@ -0,0 +1,149 @@
+use near_sdk::serde_json::json;
+
+use near_sdk::Gas;
+
+use crate::pagination::{iter_after, take_page, Page};
+use crate::sale::{Payout, GAS_FOR_ROYALTIES};
+use crate::*;
+
+// The number of transfers done in one call, 5 FT transfers with their callbacks fit the gas
+pub const SETTLEMENT_BATCH_SIZE: usize = 5;
+// `max_len_payout` passed to the NFT contract, a longer payout is rejected by the market
+pub const MAX_PAYOUT_LEN_DEFAULT: u32 = 10;
+// The gas of `resolve_purchase` and `resolve_finish_auction` without the payout entries
+// left for `settle`: the trade record, the fees and the first batch of transfers
+const GAS_FOR_RESOLVE_BASE: Gas = Gas(65_000_000_000_000);
+// Parsing one more payout entry and storing it in the settlement
+const GAS_PER_PAYOUT_ENTRY: Gas = Gas(1_000_000_000_000);
+// The longest payout the callbacks can handle with `GAS_FOR_ROYALTIES`
+pub const MAX_PAYOUT_LEN_LIMIT: u32 = SETTLEMENT_BATCH_SIZE as u32
+    + ((GAS_FOR_ROYALTIES.0 - GAS_FOR_RESOLVE_BASE.0) / GAS_PER_PAYOUT_ENTRY.0) as u32;
+
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub struct PendingTransfer {
+    pub ft_token_id: FungibleTokenId,
+    pub receiver_id: AccountId,
+    pub amount: U128,
+}
+
+// Payouts and bid refunds which didn't fit one call
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub struct Settlement {
+    pub id: U64,
+    pub transfers: Vec<PendingTransfer>,
+}
+
+#[near_bindgen]
+impl Market {
+    // Does the next batch of transfers of the settlement
+    // Can be called by anyone, returns the number of the remaining transfers
+    pub fn settle(&mut self, settlement_id: U64) -> U64 {
+        let mut settlement = self
+            .market
+            .settlements
+            .get(&settlement_id.0)
+            .unwrap_or_else(|| env::panic_str("No settlement"));
+        let batch_len = SETTLEMENT_BATCH_SIZE.min(settlement.transfers.len());
+        for transfer in settlement.transfers.drain(..batch_len) {
+            self.internal_transfer(transfer.ft_token_id, transfer.receiver_id, transfer.amount);
+        }
+        let remaining = settlement.transfers.len() as u64;
+        if remaining == 0 {
+            self.market.settlements.remove(&settlement_id.0);
+        } else {
+            self.market
+                .settlements
+                .insert(&settlement_id.0, &settlement);
+        }
+        U64(remaining)
+    }
+
+    pub fn get_settlement(&self, settlement_id: U64) -> Option<Settlement> {
+        self.market.settlements.get(&settlement_id.0)
+    }
+
+    pub fn get_settlements(&self, cursor: Option<U64>, limit: Option<u64>) -> Page<Settlement> {
+        let (settlements, next_cursor) = take_page(
+            iter_after(&self.market.settlements, cursor.map(u64::from)),
+            limit,
+            |(settlement_id, _)| U64(*settlement_id),
+        );
+        Page {
+            items: settlements
+                .into_iter()
+                .map(|(_, settlement)| settlement)
+                .collect(),
+            next_cursor,
+        }
+    }
+
+    pub fn set_max_payout_len(&mut self, max_payout_len: u32) {
+        require!(
+            env::predecessor_account_id() == self.market.owner_id,
+            "Only the market owner can set the payout length"
+        );
+        require!(
+            max_payout_len > 0 && max_payout_len <= MAX_PAYOUT_LEN_LIMIT,
+            format!(
+                "The payout length should be from 1 to {}",
+                MAX_PAYOUT_LEN_LIMIT
+            )
+        );
+        self.market.max_payout_len = max_payout_len;
+    }
+
+    pub fn get_max_payout_len(&self) -> u32 {
+        self.market.max_payout_len
+    }
+}
+
+impl Market {
+    // Does the first batch of transfers now, the rest is left for `settle`
+    pub(crate) fn internal_settle(&mut self, mut transfers: Vec<PendingTransfer>) {
+        let batch_len = SETTLEMENT_BATCH_SIZE.min(transfers.len());
+        for transfer in transfers.drain(..batch_len) {
+            self.internal_transfer(transfer.ft_token_id, transfer.receiver_id, transfer.amount);
+        }
+        if transfers.is_empty() {
+            return;
+        }
+        let settlement_id = self.market.next_settlement_id;
+        self.market.next_settlement_id += 1;
+        env::log_str(
+            &json!({
+                "type": "settlement_pending",
+                "params": {
+                    "settlement_id": U64(settlement_id),
+                    "transfers": transfers.len(),
+                }
+            })
+            .to_string(),
+        );
+        self.market.settlements.insert(
+            &settlement_id,
+            &Settlement {
+                id: U64(settlement_id),
+                transfers,
+            },
+        );
+    }
+}
+
+pub(crate) fn payout_transfers(
+    ft_token_id: &FungibleTokenId,
+    payout: Payout,
+) -> Vec<PendingTransfer> {
+    payout
+        .payout
+        .into_iter()
+        .map(|(receiver_id, amount)| PendingTransfer {
+            ft_token_id: ft_token_id.clone(),
+            receiver_id,
+            amount,
+        })
+        .collect()
+}
@@ -0,0 +1,134 @@
+use std::collections::HashMap;
+
+use crate::sale::{FungibleTokenId, TokenSeriesId};
+use crate::search::SEARCH_SCAN_MAX;
+use crate::*;
+
+// Token ids of the series are "{series_id}:{edition}"
+const SERIES_DELIMETER: char = ':';
+
+// Statistics of the collection: the whole NFT contract or one of its series
+pub type StatsKey = (AccountId, Option<TokenSeriesId>);
+
+pub fn series_id(token_id: &str) -> Option<TokenSeriesId> {
+    token_id
+        .split_once(SERIES_DELIMETER)
+        .map(|(series_id, _)| series_id.to_string())
+}
+
+// Completed purchases in one currency, the prices include the buyer fees
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone, Default)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub struct TradeStats {
+    pub volume: U128,
+    pub sales_count: U64,
+    pub last_price: U128,
+    pub highest_price: U128,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+pub struct Stats {
+    pub listed: u64,
+    pub trades: HashMap<FungibleTokenId, TradeStats>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub struct CollectionStats {
+    // The number of active sales
+    pub listed: U64,
+    // The lowest price in every currency of the sales anyone can buy now,
+    // the private, locked and not started or ended sales are skipped
+    pub floor_price: HashMap<FungibleTokenId, U128>,
+    pub trades: HashMap<FungibleTokenId, TradeStats>,
+}
+
+#[near_bindgen]
+impl Market {
+    // Statistics of the NFT contract, or of the series if `series_id` is given
+    pub fn get_collection_stats(
+        &self,
+        nft_contract_id: AccountId,
+        series_id: Option<TokenSeriesId>,
+    ) -> CollectionStats {
+        let stats = self
+            .market
+            .stats
+            .get(&(nft_contract_id.clone(), series_id.clone()))
+            .unwrap_or_default();
+        let floor_price = self
+            .market
+            .ft_token_ids
+            .iter()
+            .filter_map(|ft_token_id| {
+                let index_key = (nft_contract_id.clone(), series_id.clone(), ft_token_id.clone());
+                // only the cheapest sales are checked to keep the view within the gas limit
+                let (price, _) = self
+                    .market
+                    .by_price
+                    .get(&index_key)?
+                    .iter()
+                    .take(SEARCH_SCAN_MAX as usize)
+                    .map(|(key, _)| key)
+                    .find(|(_, sale_key)| {
+                        self.market.sales.get(sale_key).map_or(false, |sale| {
+                            !sale.is_locked() && sale.in_limits() && !sale.is_private()
+                        })
+                    })?;
+                Some((ft_token_id, U128(price)))
+            })
+            .collect();
+        CollectionStats {
+            listed: U64(stats.listed),
+            floor_price,
+            trades: stats.trades,
+        }
+    }
+}
+
+impl Market {
+    pub(crate) fn internal_update_listed(
+        &mut self,
+        nft_contract_id: &AccountId,
+        token_id: &str,
+        listed: bool,
+    ) {
+        for stats_key in stats_keys(nft_contract_id, token_id) {
+            let mut stats = self.market.stats.get(&stats_key).unwrap_or_default();
+            if listed {
+                stats.listed += 1;
+            } else {
+                stats.listed -= 1;
+            }
+            self.market.stats.insert(&stats_key, &stats);
+        }
+    }
+
+    pub(crate) fn internal_record_trade(
+        &mut self,
+        nft_contract_id: &AccountId,
+        token_id: &str,
+        ft_token_id: &FungibleTokenId,
+        price: u128,
+    ) {
+        for stats_key in stats_keys(nft_contract_id, token_id) {
+            let mut stats = self.market.stats.get(&stats_key).unwrap_or_default();
+            let trades = stats.trades.entry(ft_token_id.clone()).or_default();
+            trades.volume = U128(trades.volume.0 + price);
+            trades.sales_count = U64(trades.sales_count.0 + 1);
+            trades.last_price = U128(price);
+            trades.highest_price = U128(trades.highest_price.0.max(price));
+            self.market.stats.insert(&stats_key, &stats);
+        }
+    }
+}
+
+fn stats_keys(nft_contract_id: &AccountId, token_id: &str) -> Vec<StatsKey> {
+    let mut keys = vec![(nft_contract_id.clone(), None)];
+    if let Some(series_id) = series_id(token_id) {
+        keys.push((nft_contract_id.clone(), Some(series_id)));
+    }
+    keys
+}
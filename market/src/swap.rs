@@ -0,0 +1,286 @@
+use near_sdk::serde_json::json;
+use near_sdk::{Gas, PromiseResult};
+
+use crate::pagination::{iter_after, take_page, Page};
+use crate::sale::{ext_contract, ext_self, sale_key, NO_DEPOSIT};
+use crate::settlement::PendingTransfer;
+use crate::*;
+
+// The number of tokens the proposer can offer in one swap
+pub const SWAP_TOKENS_MAX: usize = 3;
+pub const GAS_FOR_SWAP_TRANSFER: Gas = Gas(15_000_000_000_000);
+// Delivers or returns every token of the swap
+pub const GAS_FOR_RESOLVE_SWAP: Gas =
+    Gas(GAS_FOR_SWAP_TRANSFER.0 * (SWAP_TOKENS_MAX as u64 + 1) + 20_000_000_000_000);
+
+// A token approved to the market for the swap
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize, Clone)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub struct SwapToken {
+    pub nft_contract_id: AccountId,
+    pub token_id: TokenId,
+    pub approval_id: u64,
+}
+
+// The proposer gives the offered tokens and `amount` of NEAR for the wanted token
+#[derive(BorshDeserialize, BorshSerialize, Serialize, Deserialize)]
+#[cfg_attr(not(target_arch = "wasm32"), derive(Debug, PartialEq))]
+#[serde(crate = "near_sdk::serde")]
+pub struct Swap {
+    pub swap_id: U64,
+    pub proposer_id: AccountId,
+    pub offered: Vec<SwapToken>,
+    pub wanted_nft_contract_id: AccountId,
+    pub wanted_token_id: TokenId,
+    pub amount: U128,
+    pub created_at: U64,
+    // The tokens are being exchanged, the swap can't be accepted or cancelled
+    pub locked: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SwapArgs {
+    pub swap_id: U64,
+}
+
+#[near_bindgen]
+impl Market {
+    // The offered tokens should be approved to the market,
+    // the attached deposit is the currency leg of the swap
+    #[payable]
+    pub fn propose_swap(
+        &mut self,
+        offered: Vec<SwapToken>,
+        wanted_nft_contract_id: AccountId,
+        wanted_token_id: TokenId,
+    ) -> U64 {
+        let proposer_id = env::predecessor_account_id();
+        require!(
+            !offered.is_empty() && offered.len() <= SWAP_TOKENS_MAX,
+            format!(
+                "The number of offered tokens should be from 1 to {}",
+                SWAP_TOKENS_MAX
+            )
+        );
+        require!(
+            offered
+                .iter()
+                .all(|token| token.nft_contract_id != wanted_nft_contract_id
+                    || token.token_id != wanted_token_id),
+            "Cannot swap the token for itself"
+        );
+        self.internal_add_listing_storage(&proposer_id);
+
+        let swap_id = self.market.next_swap_id;
+        self.market.next_swap_id += 1;
+        let swap = Swap {
+            swap_id: U64(swap_id),
+            proposer_id,
+            offered,
+            wanted_nft_contract_id,
+            wanted_token_id,
+            amount: U128(env::attached_deposit()),
+            created_at: U64(env::block_timestamp()),
+            locked: false,
+        };
+        let key = sale_key(&swap.wanted_nft_contract_id, &swap.wanted_token_id);
+        let mut swaps = self.market.swaps_by_token.get(&key).unwrap_or_default();
+        swaps.push(swap_id);
+        self.market.swaps_by_token.insert(&key, &swaps);
+        env::log_str(
+            &json!({
+                "type": "propose_swap",
+                "params": swap,
+            })
+            .to_string(),
+        );
+        self.market.swaps.insert(&swap_id, &swap);
+        U64(swap_id)
+    }
+
+    // The currency is refunded, the approvals of the offered tokens are left as they are
+    #[payable]
+    pub fn cancel_swap(&mut self, swap_id: U64) {
+        assert_one_yocto();
+        let swap = self.market.swaps.get(&swap_id.0).expect("No swap");
+        require!(
+            env::predecessor_account_id() == swap.proposer_id,
+            "Only the proposer can cancel the swap"
+        );
+        require!(!swap.locked, "The swap is locked by the exchange");
+        self.internal_remove_swap(&swap);
+        if swap.amount.0 > 0 {
+            self.internal_transfer("near".parse().unwrap(), swap.proposer_id, swap.amount);
+        }
+    }
+
+    // self callback
+    // If every token has been moved to the market, delivers them and pays the currency,
+    // otherwise returns the escrowed tokens and refunds the currency
+    #[private]
+    pub fn resolve_swap(
+        &mut self,
+        swap_id: U64,
+        counterparty_id: AccountId,
+        approval_id: u64,
+    ) -> bool {
+        let swap = self.market.swaps.get(&swap_id.0).expect("No swap");
+        self.internal_remove_swap(&swap);
+        let escrowed: Vec<bool> = (0..=swap.offered.len())
+            .map(|index| {
+                matches!(
+                    env::promise_result(index as u64),
+                    PromiseResult::Successful(_)
+                )
+            })
+            .collect();
+        let success = escrowed.iter().all(|escrowed| *escrowed);
+        let wanted = SwapToken {
+            nft_contract_id: swap.wanted_nft_contract_id.clone(),
+            token_id: swap.wanted_token_id.clone(),
+            approval_id,
+        };
+        let (offered_receiver, wanted_receiver, currency_receiver) = if success {
+            (&counterparty_id, &swap.proposer_id, &counterparty_id)
+        } else {
+            (&swap.proposer_id, &counterparty_id, &swap.proposer_id)
+        };
+        let receivers = swap
+            .offered
+            .iter()
+            .map(|token| (token, offered_receiver))
+            .chain(std::iter::once((&wanted, wanted_receiver)));
+        for ((token, receiver_id), escrowed) in receivers.zip(escrowed) {
+            // the market owns the escrowed tokens, no approval is needed
+            if escrowed {
+                ext_contract::nft_transfer(
+                    receiver_id.clone(),
+                    token.token_id.clone(),
+                    None,
+                    None,
+                    token.nft_contract_id.clone(),
+                    1,
+                    GAS_FOR_SWAP_TRANSFER,
+                );
+            }
+        }
+        if swap.amount.0 > 0 {
+            self.internal_settle(vec![PendingTransfer {
+                ft_token_id: "near".parse().unwrap(),
+                receiver_id: currency_receiver.clone(),
+                amount: swap.amount,
+            }]);
+        }
+        env::log_str(
+            &json!({
+                "type": if success { "swap" } else { "swap_fail" },
+                "params": {
+                    "swap_id": swap_id,
+                    "proposer_id": swap.proposer_id,
+                    "counterparty_id": counterparty_id,
+                }
+            })
+            .to_string(),
+        );
+        success
+    }
+
+    pub fn get_swap(&self, swap_id: U64) -> Option<Swap> {
+        self.market.swaps.get(&swap_id.0)
+    }
+
+    pub fn get_swaps(&self, cursor: Option<U64>, limit: Option<u64>) -> Page<Swap> {
+        let (swaps, next_cursor) = take_page(
+            iter_after(&self.market.swaps, cursor.map(u64::from)),
+            limit,
+            |(swap_id, _)| U64(*swap_id),
+        );
+        Page {
+            items: swaps.into_iter().map(|(_, swap)| swap).collect(),
+            next_cursor,
+        }
+    }
+
+    // The swaps offered for the token
+    pub fn get_swaps_for_token(&self, nft_contract_id: AccountId, token_id: TokenId) -> Vec<Swap> {
+        self.market
+            .swaps_by_token
+            .get(&sale_key(&nft_contract_id, &token_id))
+            .unwrap_or_default()
+            .into_iter()
+            .filter_map(|swap_id| self.market.swaps.get(&swap_id))
+            .collect()
+    }
+}
+
+impl Market {
+    // Called from `nft_on_approve` when the owner of the wanted token approves it with `SwapArgs`
+    // Moves every token of the swap to the market first, so both sides can be rolled back
+    pub(crate) fn accept_swap(
+        &mut self,
+        args: SwapArgs,
+        nft_contract_id: AccountId,
+        token_id: TokenId,
+        owner_id: AccountId,
+        approval_id: u64,
+    ) {
+        let mut swap = self.market.swaps.get(&args.swap_id.0).expect("No swap");
+        require!(
+            swap.wanted_nft_contract_id == nft_contract_id && swap.wanted_token_id == token_id,
+            "The token is not wanted by the swap"
+        );
+        require!(swap.proposer_id != owner_id, "Cannot accept your own swap");
+        require!(!swap.locked, "The swap is locked by the exchange");
+        swap.locked = true;
+        self.market.swaps.insert(&args.swap_id.0, &swap);
+
+        let market_id = env::current_account_id();
+        let escrow = |token: &SwapToken| {
+            ext_contract::nft_transfer(
+                market_id.clone(),
+                token.token_id.clone(),
+                Some(token.approval_id),
+                None,
+                token.nft_contract_id.clone(),
+                1,
+                GAS_FOR_SWAP_TRANSFER,
+            )
+        };
+        let wanted = SwapToken {
+            nft_contract_id,
+            token_id,
+            approval_id,
+        };
+        // the callback gets the results of the offered tokens, then of the wanted one
+        swap.offered
+            .iter()
+            .skip(1)
+            .chain(std::iter::once(&wanted))
+            .fold(escrow(&swap.offered[0]), |promise, token| {
+                promise.and(escrow(token))
+            })
+            .then(ext_self::resolve_swap(
+                args.swap_id,
+                owner_id,
+                approval_id,
+                market_id.clone(),
+                NO_DEPOSIT,
+                GAS_FOR_RESOLVE_SWAP,
+            ));
+    }
+
+    fn internal_remove_swap(&mut self, swap: &Swap) {
+        self.market.swaps.remove(&swap.swap_id.0);
+        let key = sale_key(&swap.wanted_nft_contract_id, &swap.wanted_token_id);
+        let mut swaps = self.market.swaps_by_token.get(&key).unwrap_or_default();
+        swaps.retain(|swap_id| *swap_id != swap.swap_id.0);
+        if swaps.is_empty() {
+            self.market.swaps_by_token.remove(&key);
+        } else {
+            self.market.swaps_by_token.insert(&key, &swaps);
+        }
+        self.internal_release_listing_storage(&swap.proposer_id);
+    }
+}
@@ -0,0 +1,5 @@
+#!/bin/bash
+set -e
+
+RUSTFLAGS='-C link-arg=-s' cargo build --target wasm32-unknown-unknown --release
+cp ../target/wasm32-unknown-unknown/release/nft_failing.wasm ../res/
@@ -0,0 +1,132 @@
+// A plain NEP-171 contract with the standard NEP-199 payouts.
+// It doesn't know anything about the market fees and is used to test
+// the market with NFT contracts other than nft-contract of this repo.
+use std::collections::HashMap;
+
+use near_contract_standards::non_fungible_token::core::NonFungibleTokenCore;
+use near_contract_standards::non_fungible_token::metadata::{
+    NFTContractMetadata, NonFungibleTokenMetadataProvider, TokenMetadata, NFT_METADATA_SPEC,
+};
+use near_contract_standards::non_fungible_token::{NonFungibleToken, Token, TokenId};
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::LazyOption;
+use near_sdk::json_types::U128;
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{
+    assert_one_yocto, env, near_bindgen, require, AccountId, BorshStorageKey, PanicOnDefault,
+    Promise, PromiseOrValue,
+};
+
+pub const ROYALTY_TOTAL_VALUE: u128 = 10_000;
+
+#[derive(Serialize, Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Payout {
+    pub payout: HashMap<AccountId, U128>,
+}
+
+#[near_bindgen]
+#[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
+pub struct Contract {
+    tokens: NonFungibleToken,
+    metadata: LazyOption<NFTContractMetadata>,
+    // The same royalty for every token, 10_000 is 100%
+    royalty: HashMap<AccountId, u32>,
+}
+
+#[derive(BorshSerialize, BorshStorageKey)]
+enum StorageKey {
+    NonFungibleToken,
+    Metadata,
+    TokenMetadata,
+    Enumeration,
+    Approval,
+}
+
+#[near_bindgen]
+impl Contract {
+    #[init]
+    pub fn new(owner_id: AccountId, royalty: HashMap<AccountId, u32>) -> Self {
+        require!(!env::state_exists(), "Already initialized");
+        require!(
+            royalty.values().map(|value| *value as u128).sum::<u128>() <= ROYALTY_TOTAL_VALUE,
+            "Royalty total value should be <= 10000"
+        );
+        let metadata = NFTContractMetadata {
+            spec: NFT_METADATA_SPEC.to_string(),
+            name: "Reference NEAR non-fungible token".to_string(),
+            symbol: "REFERENCE".to_string(),
+            icon: None,
+            base_uri: None,
+            reference: None,
+            reference_hash: None,
+        };
+        Self {
+            tokens: NonFungibleToken::new(
+                StorageKey::NonFungibleToken,
+                owner_id,
+                Some(StorageKey::TokenMetadata),
+                Some(StorageKey::Enumeration),
+                Some(StorageKey::Approval),
+            ),
+            metadata: LazyOption::new(StorageKey::Metadata, Some(&metadata)),
+            royalty,
+        }
+    }
+
+    // Only the owner of the contract can mint
+    #[payable]
+    pub fn nft_mint(
+        &mut self,
+        token_id: TokenId,
+        receiver_id: AccountId,
+        token_metadata: TokenMetadata,
+    ) -> Token {
+        self.tokens.mint(token_id, receiver_id, Some(token_metadata))
+    }
+
+    pub fn nft_payout(&self, token_id: TokenId, balance: U128, max_len_payout: u32) -> Payout {
+        let owner_id = self.tokens.owner_by_id.get(&token_id).expect("No token");
+        let mut payout = HashMap::new();
+        let mut total = 0;
+        for (account_id, value) in self.royalty.iter() {
+            if *account_id != owner_id {
+                let amount = balance.0 * *value as u128 / ROYALTY_TOTAL_VALUE;
+                payout.insert(account_id.clone(), U128(amount));
+                total += amount;
+            }
+        }
+        payout.insert(owner_id, U128(balance.0 - total));
+        require!(payout.len() as u32 <= max_len_payout, "Too many receivers");
+        Payout { payout }
+    }
+
+    // `memo` is passed to nft_transfer as is
+    #[payable]
+    pub fn nft_transfer_payout(
+        &mut self,
+        receiver_id: AccountId,
+        token_id: TokenId,
+        approval_id: u64,
+        memo: Option<String>,
+        balance: U128,
+        max_len_payout: u32,
+    ) -> Payout {
+        assert_one_yocto();
+        let payout = self.nft_payout(token_id.clone(), balance, max_len_payout);
+        self.tokens
+            .nft_transfer(receiver_id, token_id, Some(approval_id), memo);
+        payout
+    }
+}
+
+near_contract_standards::impl_non_fungible_token_core!(Contract, tokens);
+near_contract_standards::impl_non_fungible_token_approval!(Contract, tokens);
+near_contract_standards::impl_non_fungible_token_enumeration!(Contract, tokens);
+
+#[near_bindgen]
+impl NonFungibleTokenMetadataProvider for Contract {
+    fn nft_metadata(&self) -> NFTContractMetadata {
+        self.metadata.get().unwrap()
+    }
+}
@@ -11,7 +11,7 @@ use common::*;
 mod token_series;
 use event::NearEvent;
 use near_contract_standards::non_fungible_token::refund_deposit_to_account;
-use near_sdk::{ext_contract, Promise};
+use near_sdk::{ext_contract, Gas, Promise};
 use permissions::PrivateMint;
 use token_series::{TokenSeries, TokenSeriesId, TokenSeriesSale, TOKEN_DELIMETER};
 
@@ -21,6 +21,9 @@ use crate::{event::NftMintData, payouts::MAXIMUM_ROYALTY};
 use std::collections::HashMap;
 
 pub use token_series::TokenSeriesJson;
+
+// Left to `nft_series_market_approve` itself, the rest is passed to `nft_on_series_approve`
+const GAS_FOR_NFT_APPROVE: Gas = Gas(20_000_000_000_000);
 pub use payouts::Payout;
 
 // Since Near doesn't support multitoken(yet) by default we need to create some workaround
@@ -47,6 +50,7 @@ enum StorageKey {
     TokensBySeriesInner { token_series: String },
     TokensPerOwner { account_hash: Vec<u8> },
     Minters,
+    ApprovedMarkets,
 }
 
 #[near_bindgen]
@@ -103,8 +107,6 @@ impl Nft {
         receiver_id: AccountId,
         refund_id: Option<AccountId>,
     ) -> TokenId {
-        self.private_mint
-            .panic_if_not_allowed(&env::predecessor_account_id());
         let refund_id = refund_id.unwrap_or_else(env::predecessor_account_id);
         let initial_storage_usage = env::storage_usage();
 
@@ -112,10 +114,16 @@ impl Nft {
             .token_series_by_id
             .get(&token_series_id)
             .expect("Token series does not exist");
-        require!(
-            env::predecessor_account_id().eq(&token_series.owner_id),
-            "permission denied"
-        );
+        // the approved market mints on behalf of the series owner
+        let minter_id = if self.approved_markets().get(&token_series_id)
+            == Some(env::predecessor_account_id())
+        {
+            token_series.owner_id.clone()
+        } else {
+            env::predecessor_account_id()
+        };
+        self.private_mint.panic_if_not_allowed(&minter_id);
+        require!(minter_id.eq(&token_series.owner_id), "permission denied");
         require!(
             token_series.tokens.len() < token_series.metadata.copies.unwrap_or(u64::MAX),
             "Max token minted"
@@ -224,45 +232,50 @@ impl Nft {
         token_series_id
     }
 
-    /* Lazy_mint if needed
-       #[payable]
-       pub fn nft_series_market_approve(
-           &mut self,
-           token_series_id: TokenId,
-           sale_conditions: token_series::SaleConditions,
-           copies: u64,
-           approved_market_id: AccountId,
-       ) -> Promise {
-           let initial_storage_usage = env::storage_usage();
-           let mut token_series = self
-               .token_series_by_id
-               .get(&token_series_id)
-               .expect("Series not found");
-           require!(
-               env::predecessor_account_id().eq(&token_series.owner_id),
-               "Not token owner"
-           );
-           require!(
-               token_series.metadata.copies.unwrap_or(u64::MAX) - token_series.tokens.len() >= copies,
-               "Too many copies"
-           );
-           token_series.approved_market_id = Some(approved_market_id.clone());
-           self.token_series_by_id
-               .insert(&token_series_id, &token_series);
-           refund_deposit(env::storage_usage() - initial_storage_usage);
-           ext_contract::nft_on_series_approve(
-               TokenSeriesSale {
-                   sale_conditions,
-                   series_id: token_series_id,
-                   owner_id: token_series.owner_id,
-                   copies,
-               },
-               approved_market_id,
-               0,
-               env::prepaid_gas() - GAS_FOR_NFT_APPROVE,
-           )
-       }
-    */
+    // Approves the market to sell `copies` of the series, the market mints them on purchase
+    #[payable]
+    pub fn nft_series_market_approve(
+        &mut self,
+        token_series_id: TokenSeriesId,
+        sale_conditions: token_series::SaleConditions,
+        copies: u64,
+        approved_market_id: AccountId,
+    ) -> Promise {
+        let initial_storage_usage = env::storage_usage();
+        let token_series = self
+            .token_series_by_id
+            .get(&token_series_id)
+            .expect("Series not found");
+        require!(
+            env::predecessor_account_id().eq(&token_series.owner_id),
+            "Not token owner"
+        );
+        require!(
+            token_series.metadata.copies.unwrap_or(u64::MAX) - token_series.tokens.len() >= copies,
+            "Too many copies"
+        );
+        self.approved_markets().insert(&token_series_id, &approved_market_id);
+        refund_deposit(env::storage_usage() - initial_storage_usage);
+        ext_contract::nft_on_series_approve(
+            TokenSeriesSale {
+                sale_conditions,
+                series_id: token_series_id,
+                owner_id: token_series.owner_id,
+                copies,
+            },
+            approved_market_id,
+            0,
+            env::prepaid_gas() - GAS_FOR_NFT_APPROVE,
+        )
+    }
+}
+
+impl Nft {
+    // The market which can mint the tokens of the series on purchase. The map isn't a field
+    // of the contract, so the contracts deployed before it keep their state layout
+    fn approved_markets(&self) -> LookupMap<TokenSeriesId, AccountId> {
+        LookupMap::new(StorageKey::ApprovedMarkets)
+    }
 }
 
 near_contract_standards::impl_non_fungible_token_approval!(Nft, tokens);
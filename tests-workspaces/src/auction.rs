@@ -1,4 +1,5 @@
 use std::{
+    collections::HashMap,
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
@@ -7,7 +8,9 @@ use near_units::{parse_gas, parse_near};
 use crate::utils::{init_market, init_nft, create_subaccount, create_series, deposit,
     mint_token, check_outcome_success, check_outcome_fail
 };
-use nft_bid_market::{ArgsKind, AuctionArgs, AuctionJson};
+use nft_bid_market::{ArgsKind, AuctionArgs, AuctionFallback, AuctionJson, Page, SaleJson};
+use nft_contract::common::{AccountId, U128};
+use workspaces::{Account, Contract, DevNetwork, Worker};
 //use workspaces::{Contract, Account, Worker};
 
 const THIRTY_SECONDS: Duration = Duration::from_secs(30);
@@ -54,6 +57,8 @@ async fn nft_on_approve_auction_positive() -> anyhow::Result<()> {
                 duration: 900000000000.into(),
                 buy_out_price: Some(10000000000.into()),
                 origins: None,
+                allowed_buyers: None,
+                fallback: None,
             })).to_string()
         }))?
         .deposit(parse_near!("1 N"))
@@ -114,6 +119,8 @@ async fn auction_add_bid_negative() -> anyhow::Result<()> {
                 duration: 900000000000.into(),
                 buy_out_price: Some(10000000000.into()),
                 origins: None,
+                allowed_buyers: None,
+                fallback: None,
             })).to_string()
         }))?
         .deposit(parse_near!("1 N"))
@@ -237,6 +244,8 @@ async fn auction_add_bid_positive() -> anyhow::Result<()> {
                 duration: 900000000000.into(),
                 buy_out_price: Some(10000000000.into()),
                 origins: None,
+                allowed_buyers: None,
+                fallback: None,
             })).to_string()
         }))?
         .deposit(parse_near!("1 N"))
@@ -376,6 +385,8 @@ async fn cancel_auction_negative() -> anyhow::Result<()> {
                 duration: 900000000000.into(),
                 buy_out_price: Some(10000000000.into()),
                 origins: None,
+                allowed_buyers: None,
+                fallback: None,
             })).to_string()
         }))?
         .deposit(parse_near!("1 N"))
@@ -442,12 +453,13 @@ async fn cancel_auction_negative() -> anyhow::Result<()> {
     let vector_auctions: Vec<AuctionJson> = market.view(
         &worker,
         "get_auctions",
-        serde_json::json!({"from_index": null, "limit": null})
+        serde_json::json!({"cursor": null, "limit": null})
             .to_string()
             .into_bytes(),
         )
         .await?
-        .json()?;
+        .json::<Page<AuctionJson, U128>>()?
+        .items;
     assert!(!vector_auctions.is_empty(), "Deleted the auction");
     Ok(())
 }
@@ -497,6 +509,8 @@ async fn cancel_auction_positive() -> anyhow::Result<()> {
                 duration: 900000000000.into(),
                 buy_out_price: Some(10000000000.into()),
                 origins: None,
+                allowed_buyers: None,
+                fallback: None,
             })).to_string()
         }))?
         .deposit(parse_near!("1 N"))
@@ -522,7 +536,8 @@ async fn cancel_auction_positive() -> anyhow::Result<()> {
             .into_bytes(),
         )
         .await?
-        .json()?;
+        .json::<Page<AuctionJson, U128>>()?
+        .items;
     assert!(vector_auctions.is_empty(), "Did not delete the auction");
     Ok(())
 }
@@ -576,6 +591,8 @@ async fn finish_auction_positive() -> anyhow::Result<()> {
                 duration: 900000000000.into(),
                 buy_out_price: Some(10000000000.into()),
                 origins: None,
+                allowed_buyers: None,
+                fallback: None,
             })).to_string()
         }))?
         .deposit(parse_near!("1 N"))
@@ -645,6 +662,8 @@ async fn finish_auction_negative() -> anyhow::Result<()> {
                 duration: 900000000000.into(),
                 buy_out_price: Some(10000000000.into()),
                 origins: None,
+                allowed_buyers: None,
+                fallback: None,
             })).to_string()
         }))?
         .deposit(parse_near!("1 N"))
@@ -680,3 +699,334 @@ async fn finish_auction_negative() -> anyhow::Result<()> {
 
     Ok(())
 }
+
+async fn list_auction(
+    worker: &Worker<impl DevNetwork>,
+    nft: &Contract,
+    market: &Contract,
+    user: &Account,
+    token_id: &str,
+    fallback: Option<AuctionFallback>,
+) -> anyhow::Result<()> {
+    let outcome = user
+        .call(worker, nft.id().clone(), "nft_approve")
+        .args_json(serde_json::json!({
+            "token_id": token_id,
+            "account_id": market.id(),
+            "msg": serde_json::json!(ArgsKind::Auction(AuctionArgs {
+                token_type: None,
+                minimal_step: 100.into(),
+                start_price: 10000.into(),
+                start: None,
+                duration: 900000000000.into(),
+                buy_out_price: None,
+                origins: None,
+                allowed_buyers: None,
+                fallback,
+            })).to_string()
+        }))?
+        .deposit(parse_near!("1 N"))
+        .gas(parse_gas!("200 Tgas") as u64)
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+    Ok(())
+}
+
+async fn close_auction(
+    worker: &Worker<impl DevNetwork>,
+    market: &Contract,
+    user: &Account,
+    auction_id: &str,
+) -> anyhow::Result<()> {
+    let outcome = user
+        .call(worker, market.id().clone(), "hack_finish_auction")
+        .args_json(serde_json::json!({ "auction_id": auction_id }))?
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+    let outcome = user
+        .call(worker, market.id().clone(), "close_auction")
+        .args_json(serde_json::json!({ "auction_id": auction_id }))?
+        .gas(parse_gas!("200 Tgas") as u64)
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+    Ok(())
+}
+
+/*
+    - Anyone can close the auction which has ended without a bid
+    - The `Relist` fallback starts a new auction with the lower start price and no fallback
+    - The `Sale` fallback puts the token on sale at the given price
+    - Without a fallback the auction is just removed
+*/
+#[tokio::test]
+async fn close_auction_positive() -> anyhow::Result<()> {
+    let worker = workspaces::sandbox();
+    let owner = worker.root_account();
+    let nft = init_nft(&worker, owner.id()).await?;
+    let market = init_market(&worker, worker.root_account().id(), vec![nft.id()]).await?;
+    let user1 = create_subaccount(&worker, &owner, "user1").await?;
+    let user2 = create_subaccount(&worker, &owner, "user2").await?;
+    let series = create_series(&worker, nft.id().clone(), &user1, owner.id().clone()).await?;
+    deposit(&worker, market.id().clone(), &user1).await;
+
+    let fallbacks = vec![
+        Some(AuctionFallback::Relist {
+            start_price: U128(5000),
+        }),
+        Some(AuctionFallback::Sale { price: U128(8000) }),
+        None,
+    ];
+    let mut tokens = vec![];
+    for fallback in fallbacks {
+        let token_id = mint_token(&worker, nft.id().clone(), &user1, user1.id(), &series).await?;
+        list_auction(&worker, &nft, &market, &user1, &token_id, fallback).await?;
+        tokens.push(token_id);
+    }
+
+    close_auction(&worker, &market, &user2, "0").await?;
+    let relisted: AuctionJson = market
+        .view(
+            &worker,
+            "get_auction",
+            serde_json::json!({ "auction_id": "3" })
+                .to_string()
+                .into_bytes(),
+        )
+        .await?
+        .json()?;
+    assert_eq!(relisted.token_id, tokens[0]);
+    assert_eq!(relisted.start_price, U128(5000));
+    assert_eq!(relisted.fallback, None);
+
+    close_auction(&worker, &market, &user2, "1").await?;
+    let sale: Option<SaleJson> = market
+        .view(
+            &worker,
+            "get_sale",
+            serde_json::json!({
+                "nft_contract_id": nft.id(),
+                "token_id": tokens[1]
+            })
+            .to_string()
+            .into_bytes(),
+        )
+        .await?
+        .json()?;
+    let sale = sale.expect("The auction wasn't turned into a sale");
+    assert_eq!(sale.owner_id.as_ref(), user1.id().as_ref());
+    assert_eq!(
+        sale.sale_conditions
+            .get(&"near".parse::<AccountId>().unwrap()),
+        Some(&U128(8000))
+    );
+
+    close_auction(&worker, &market, &user2, "2").await?;
+    let auctions: Page<AuctionJson, U128> = market
+        .view(
+            &worker,
+            "get_auctions",
+            serde_json::json!({ "cursor": null, "limit": null })
+                .to_string()
+                .into_bytes(),
+        )
+        .await?
+        .json()?;
+    assert_eq!(auctions.items.len(), 1);
+    assert_eq!(auctions.items[0].token_id, tokens[0]);
+    Ok(())
+}
+
+/*
+    - The `Sale` fallback is applied with the origins of the auction, even if they are
+      no longer registered
+    - Without the storage for one more sale the auction is just closed
+*/
+#[tokio::test]
+async fn close_auction_sale_fallback() -> anyhow::Result<()> {
+    let worker = workspaces::sandbox();
+    let owner = worker.root_account();
+    let nft = init_nft(&worker, owner.id()).await?;
+    let market = init_market(&worker, worker.root_account().id(), vec![nft.id()]).await?;
+    let user1 = create_subaccount(&worker, &owner, "user1").await?;
+    let user2 = create_subaccount(&worker, &owner, "user2").await?;
+    let series = create_series(&worker, nft.id().clone(), &user1, owner.id().clone()).await?;
+    deposit(&worker, market.id().clone(), &user1).await;
+
+    let outcome = owner
+        .call(&worker, market.id().clone(), "register_origin")
+        .args_json(serde_json::json!({ "origin_id": user2.id(), "max_fee": 100 }))?
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+    let token1 = mint_token(&worker, nft.id().clone(), &user1, user1.id(), &series).await?;
+    let outcome = user1
+        .call(&worker, nft.id().clone(), "nft_approve")
+        .args_json(serde_json::json!({
+            "token_id": token1,
+            "account_id": market.id(),
+            "msg": serde_json::json!(ArgsKind::Auction(AuctionArgs {
+                token_type: None,
+                minimal_step: 100.into(),
+                start_price: 10000.into(),
+                start: None,
+                duration: 900000000000.into(),
+                buy_out_price: None,
+                origins: Some(HashMap::from([(user2.id().as_ref().parse().unwrap(), 100)])),
+                allowed_buyers: None,
+                fallback: Some(AuctionFallback::Sale { price: U128(8000) }),
+            })).to_string()
+        }))?
+        .deposit(parse_near!("1 N"))
+        .gas(parse_gas!("200 Tgas") as u64)
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+    let outcome = owner
+        .call(&worker, market.id().clone(), "unregister_origin")
+        .args_json(serde_json::json!({ "origin_id": user2.id() }))?
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+
+    close_auction(&worker, &market, &user2, "0").await?;
+    let sale: Option<SaleJson> = market
+        .view(
+            &worker,
+            "get_sale",
+            serde_json::json!({
+                "nft_contract_id": nft.id(),
+                "token_id": token1
+            })
+            .to_string()
+            .into_bytes(),
+        )
+        .await?
+        .json()?;
+    assert!(sale.is_some(), "The auction wasn't turned into a sale");
+
+    let token2 = mint_token(&worker, nft.id().clone(), &user1, user1.id(), &series).await?;
+    list_auction(
+        &worker,
+        &nft,
+        &market,
+        &user1,
+        &token2,
+        Some(AuctionFallback::Sale { price: U128(8000) }),
+    )
+    .await?;
+    // the deposit left covers only the sale above
+    let outcome = user1
+        .call(&worker, market.id().clone(), "storage_withdraw")
+        .deposit(1)
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+    close_auction(&worker, &market, &user2, "1").await?;
+    let sale: Option<SaleJson> = market
+        .view(
+            &worker,
+            "get_sale",
+            serde_json::json!({
+                "nft_contract_id": nft.id(),
+                "token_id": token2
+            })
+            .to_string()
+            .into_bytes(),
+        )
+        .await?
+        .json()?;
+    assert!(sale.is_none());
+    Ok(())
+}
+
+/*
+    - Panics if the relist start price is not lower than the start price
+    - Panics if called before the auction ends
+    - Panics if the auction has a bid
+*/
+#[tokio::test]
+async fn close_auction_negative() -> anyhow::Result<()> {
+    let worker = workspaces::sandbox();
+    let owner = worker.root_account();
+    let nft = init_nft(&worker, owner.id()).await?;
+    let market = init_market(&worker, worker.root_account().id(), vec![nft.id()]).await?;
+    let user1 = create_subaccount(&worker, &owner, "user1").await?;
+    let user2 = create_subaccount(&worker, &owner, "user2").await?;
+    let series = create_series(&worker, nft.id().clone(), &user1, owner.id().clone()).await?;
+    let token1 = mint_token(&worker, nft.id().clone(), &user1, user1.id(), &series).await?;
+    deposit(&worker, market.id().clone(), &user1).await;
+
+    // Panics if the relist start price is not lower than the start price
+    let outcome = user1
+        .call(&worker, nft.id().clone(), "nft_approve")
+        .args_json(serde_json::json!({
+            "token_id": token1,
+            "account_id": market.id(),
+            "msg": serde_json::json!(ArgsKind::Auction(AuctionArgs {
+                token_type: None,
+                minimal_step: 100.into(),
+                start_price: 10000.into(),
+                start: None,
+                duration: 900000000000.into(),
+                buy_out_price: None,
+                origins: None,
+                allowed_buyers: None,
+                fallback: Some(AuctionFallback::Relist {
+                    start_price: U128(10000),
+                }),
+            })).to_string()
+        }))?
+        .deposit(parse_near!("1 N"))
+        .gas(parse_gas!("200 Tgas") as u64)
+        .transact()
+        .await?;
+    check_outcome_fail(
+        outcome.status,
+        "The relist start price should be lower than the start price",
+    )
+    .await;
+
+    list_auction(&worker, &nft, &market, &user1, &token1, None).await?;
+
+    // Panics if called before the auction ends
+    let outcome = user2
+        .call(&worker, market.id().clone(), "close_auction")
+        .args_json(serde_json::json!({ "auction_id": "0" }))?
+        .gas(parse_gas!("200 Tgas") as u64)
+        .transact()
+        .await?;
+    check_outcome_fail(
+        outcome.status,
+        "Auction can be closed only after the end time",
+    )
+    .await;
+
+    // Panics if the auction has a bid
+    let outcome = user2
+        .call(&worker, market.id().clone(), "auction_add_bid")
+        .args_json(serde_json::json!({
+            "auction_id": "0",
+            "token_type": "near",
+        }))?
+        .deposit(10300)
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+    let outcome = user2
+        .call(&worker, market.id().clone(), "hack_finish_auction")
+        .args_json(serde_json::json!({ "auction_id": "0" }))?
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+    let outcome = user2
+        .call(&worker, market.id().clone(), "close_auction")
+        .args_json(serde_json::json!({ "auction_id": "0" }))?
+        .gas(parse_gas!("200 Tgas") as u64)
+        .transact()
+        .await?;
+    check_outcome_fail(outcome.status, "The auction has a bid, use finish_auction").await;
+    Ok(())
+}
@@ -6,7 +6,7 @@ use near_units::{parse_gas, parse_near};
 use crate::utils::{init_market, init_nft, create_subaccount, create_series, deposit,
     mint_token, check_outcome_success
 };
-use nft_bid_market::{ArgsKind, AuctionArgs, AuctionJson};
+use nft_bid_market::{ArgsKind, AuctionArgs, AuctionJson, Page};
 use nft_contract::common::AccountId;
 use nft_contract::common::{U64, U128};
 
@@ -51,6 +51,8 @@ async fn view_auction_get_auction() -> anyhow::Result<()> {
                 duration: 900000000000.into(),
                 buy_out_price: Some(10000000000.into()),
                 origins: None,
+                allowed_buyers: None,
+                fallback: None,
             })).to_string()
         }))?
         .deposit(parse_near!("1 N"))
@@ -160,6 +162,8 @@ async fn view_auction_get_auctions() -> anyhow::Result<()> {
                 duration: 900000000000.into(),
                 buy_out_price: Some(10000000000.into()),
                 origins: None,
+                allowed_buyers: None,
+                fallback: None,
             })).to_string()
         }))?
         .deposit(parse_near!("1 N"))
@@ -179,6 +183,8 @@ async fn view_auction_get_auctions() -> anyhow::Result<()> {
                 duration: 900000000000.into(),
                 buy_out_price: Some(1000000000.into()),
                 origins: None,
+                allowed_buyers: None,
+                fallback: None,
             })).to_string()
         }))?
         .deposit(parse_near!("1 N"))
@@ -190,12 +196,13 @@ async fn view_auction_get_auctions() -> anyhow::Result<()> {
         .view(
             &worker,
             "get_auctions",
-            serde_json::json!({ "from_index": null, "limit": null })
+            serde_json::json!({ "cursor": null, "limit": null })
                 .to_string()
                 .into_bytes(),
         )
         .await?
-        .json()?;
+        .json::<Page<AuctionJson, U128>>()?
+        .items;
     assert!(auctions.len() == 2, "wrong length");
     let auction1 = &auctions[0];
     let auction2 = &auctions[1];
@@ -259,6 +266,8 @@ async fn view_auction_get_current_buyer() -> anyhow::Result<()> {
                 duration: 900000000000.into(),
                 buy_out_price: Some(10000000000.into()),
                 origins: None,
+                allowed_buyers: None,
+                fallback: None,
             })).to_string()
         }))?
         .deposit(parse_near!("1 N"))
@@ -370,6 +379,8 @@ async fn view_auction_get_current_bid() -> anyhow::Result<()> {
                 duration: 900000000000.into(),
                 buy_out_price: Some(10000000000.into()),
                 origins: None,
+                allowed_buyers: None,
+                fallback: None,
             })).to_string()
         }))?
         .deposit(parse_near!("1 N"))
@@ -483,6 +494,8 @@ async fn view_auction_get_minimal_next_bid() -> anyhow::Result<()> {
                 duration: 900000000000.into(),
                 buy_out_price: Some(10000000000.into()),
                 origins: None,
+                allowed_buyers: None,
+                fallback: None,
             })).to_string()
         }))?
         .deposit(parse_near!("1 N"))
@@ -599,6 +612,8 @@ async fn view_auction_check_auction_in_progress() -> anyhow::Result<()> {
                 duration: 900000000000.into(),
                 buy_out_price: Some(10000000000.into()),
                 origins: None,
+                allowed_buyers: None,
+                fallback: None,
             })).to_string()
         }))?
         .deposit(parse_near!("1 N"))
@@ -658,6 +673,8 @@ async fn view_auction_check_auction_in_progress() -> anyhow::Result<()> {
                 duration: 900000000000.into(),
                 buy_out_price: Some(10000000000.into()),
                 origins: None,
+                allowed_buyers: None,
+                fallback: None,
             })).to_string()
         }))?
         .deposit(parse_near!("1 N"))
@@ -112,6 +112,7 @@ async fn remove_bid_positive() -> anyhow::Result<()> {
 - Should panic unless 1 yoctoNEAR is attached
 - Should panic if there is no sale with the given `nft_contract_id` and `token_id`
 - Should panic if there is no bids with `ft_token_id`
+- Should panic if the caller has no bid with the given price
 */
 #[tokio::test]
 async fn remove_bid_negative() -> anyhow::Result<()> {
@@ -211,6 +212,34 @@ async fn remove_bid_negative() -> anyhow::Result<()> {
         .await?;
     check_outcome_fail(outcome.status, "No token").await;
 
+    // Should panic if the caller has no bid with the given price
+    let outcome = user2
+        .call(&worker, market.id().clone(), "remove_bid")
+        .args_json(serde_json::json!({
+            "nft_contract_id": nft.id().clone(),
+            "token_id": token1.clone(),
+            "ft_token_id": "near",
+            "price": U128(price.0 + 1),
+        }))?
+        .deposit(1)
+        .gas(parse_gas!("300 Tgas") as u64)
+        .transact()
+        .await?;
+    check_outcome_fail(outcome.status, "No bid").await;
+    let outcome = user1
+        .call(&worker, market.id().clone(), "remove_bid")
+        .args_json(serde_json::json!({
+            "nft_contract_id": nft.id().clone(),
+            "token_id": token1.clone(),
+            "ft_token_id": "near",
+            "price": price,
+        }))?
+        .deposit(1)
+        .gas(parse_gas!("300 Tgas") as u64)
+        .transact()
+        .await?;
+    check_outcome_fail(outcome.status, "No bid").await;
+
     Ok(())
 }
 
@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+
+use near_units::{parse_gas, parse_near};
+use nft_bid_market::{
+    AccountAuctionBid, AccountBid, AccountBidsCursor, ArgsKind, AuctionArgs, BidStatus, Page,
+};
+use nft_contract::common::{AccountId, U128};
+use workspaces::{Account, Contract, DevNetwork, Worker};
+
+use crate::utils::{
+    check_outcome_success, create_series, create_subaccount, deposit, init_market, init_nft,
+    mint_token, nft_approve, offer,
+};
+
+async fn auction_bid(
+    worker: &Worker<impl DevNetwork>,
+    market: &Contract,
+    user: &Account,
+    amount: u128,
+) -> anyhow::Result<()> {
+    let outcome = user
+        .call(worker, market.id().clone(), "auction_add_bid")
+        .args_json(serde_json::json!({
+            "auction_id": "0",
+            "token_type": "near",
+        }))?
+        .deposit(amount)
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+    Ok(())
+}
+
+/*
+    - `get_bids_by_account` returns the bids of the account on sales with their status
+    - `get_auctions_bid_by_account` returns the auctions where the account has the current bid
+    - `get_escrowed_by_account` sums up all the bids of the account
+    - An outbid auction bid is refunded and disappears from the views
+*/
+#[tokio::test]
+async fn bid_views_positive() -> anyhow::Result<()> {
+    let worker = workspaces::sandbox();
+    let owner = worker.root_account();
+    let nft = init_nft(&worker, owner.id()).await?;
+    let market = init_market(&worker, worker.root_account().id(), vec![nft.id()]).await?;
+    let user1 = create_subaccount(&worker, &owner, "user1").await?;
+    let user2 = create_subaccount(&worker, &owner, "user2").await?;
+    let user3 = create_subaccount(&worker, &owner, "user3").await?;
+    let series = create_series(&worker, nft.id().clone(), &user1, owner.id().clone()).await?;
+    let token1 = mint_token(&worker, nft.id().clone(), &user1, user1.id(), &series).await?;
+    let token2 = mint_token(&worker, nft.id().clone(), &user1, user1.id(), &series).await?;
+    deposit(&worker, market.id().clone(), &user1).await;
+
+    let sale_conditions = HashMap::from([("near".parse().unwrap(), 10000.into())]);
+    nft_approve(
+        &worker,
+        nft.id().clone(),
+        market.id().clone(),
+        &user1,
+        token1.clone(),
+        sale_conditions,
+        series.clone(),
+    )
+    .await;
+    let outcome = user1
+        .call(&worker, nft.id().clone(), "nft_approve")
+        .args_json(serde_json::json!({
+            "token_id": token2,
+            "account_id": market.id(),
+            "msg": serde_json::json!(ArgsKind::Auction(AuctionArgs {
+                token_type: None,
+                minimal_step: 100.into(),
+                start_price: 10000.into(),
+                start: None,
+                duration: 900000000000.into(),
+                buy_out_price: None,
+                origins: None,
+                allowed_buyers: None,
+                fallback: None,
+            })).to_string()
+        }))?
+        .deposit(parse_near!("1 N"))
+        .gas(parse_gas!("200 Tgas") as u64)
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+
+    offer(
+        &worker,
+        nft.id().clone(),
+        market.id().clone(),
+        &user2,
+        token1.clone(),
+        U128(900),
+    )
+    .await;
+    offer(
+        &worker,
+        nft.id().clone(),
+        market.id().clone(),
+        &user3,
+        token1.clone(),
+        U128(1000),
+    )
+    .await;
+    auction_bid(&worker, &market, &user2, 10300).await?;
+
+    let bids: Page<AccountBid, AccountBidsCursor> = market
+        .view(
+            &worker,
+            "get_bids_by_account",
+            serde_json::json!({ "account_id": user2.id() })
+                .to_string()
+                .into_bytes(),
+        )
+        .await?
+        .json()?;
+    assert_eq!(bids.items.len(), 1);
+    assert_eq!(bids.items[0].token_id, token1);
+    assert_eq!(bids.items[0].bid.price, U128(900));
+    assert_eq!(bids.items[0].status, BidStatus::Outbid);
+    let bids: Page<AccountBid, AccountBidsCursor> = market
+        .view(
+            &worker,
+            "get_bids_by_account",
+            serde_json::json!({ "account_id": user3.id() })
+                .to_string()
+                .into_bytes(),
+        )
+        .await?
+        .json()?;
+    assert_eq!(bids.items[0].status, BidStatus::Leading);
+
+    let auction_bids: Page<AccountAuctionBid, U128> = market
+        .view(
+            &worker,
+            "get_auctions_bid_by_account",
+            serde_json::json!({ "account_id": user2.id() })
+                .to_string()
+                .into_bytes(),
+        )
+        .await?
+        .json()?;
+    assert_eq!(auction_bids.items.len(), 1);
+    assert_eq!(auction_bids.items[0].auction_id, U128(0));
+    assert_eq!(auction_bids.items[0].status, BidStatus::Leading);
+    let escrowed: HashMap<AccountId, U128> = market
+        .view(
+            &worker,
+            "get_escrowed_by_account",
+            serde_json::json!({ "account_id": user2.id() })
+                .to_string()
+                .into_bytes(),
+        )
+        .await?
+        .json()?;
+    assert_eq!(
+        escrowed.get(&"near".parse::<AccountId>().unwrap()),
+        Some(&U128(11200))
+    );
+
+    auction_bid(&worker, &market, &user3, 20000).await?;
+    let auction_bids: Page<AccountAuctionBid, U128> = market
+        .view(
+            &worker,
+            "get_auctions_bid_by_account",
+            serde_json::json!({ "account_id": user2.id() })
+                .to_string()
+                .into_bytes(),
+        )
+        .await?
+        .json()?;
+    assert!(auction_bids.items.is_empty());
+    Ok(())
+}
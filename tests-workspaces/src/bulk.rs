@@ -0,0 +1,158 @@
+use std::collections::HashMap;
+
+use near_units::{parse_gas, parse_near};
+use nft_bid_market::{ArgsKind, AuctionArgs, BulkRemoval, SaleJson};
+use nft_contract::common::{AccountId, U128, U64};
+
+use crate::utils::{
+    check_outcome_success, create_series, create_subaccount, deposit, init_market, init_nft,
+    mint_token, nft_approve, offer,
+};
+
+/*
+    - `remove_all_bids` refunds every bid of the caller, the bids of other accounts are kept
+    - `remove_all_sales` removes the sales of the caller and refunds their bids
+    - `cancel_all_auctions` cancels the auctions of the caller without a bid
+    - Every call removes at most `limit` items and returns how many are left
+*/
+#[tokio::test]
+async fn bulk_removal_positive() -> anyhow::Result<()> {
+    let worker = workspaces::sandbox();
+    let owner = worker.root_account();
+    let nft = init_nft(&worker, owner.id()).await?;
+    let market = init_market(&worker, worker.root_account().id(), vec![nft.id()]).await?;
+    let user1 = create_subaccount(&worker, &owner, "user1").await?;
+    let user2 = create_subaccount(&worker, &owner, "user2").await?;
+    let user3 = create_subaccount(&worker, &owner, "user3").await?;
+    let series = create_series(&worker, nft.id().clone(), &user1, owner.id().clone()).await?;
+    deposit(&worker, market.id().clone(), &user1).await;
+
+    let sale_conditions = HashMap::from([("near".parse().unwrap(), 10000.into())]);
+    let mut tokens = vec![];
+    for _ in 0..3 {
+        let token_id = mint_token(&worker, nft.id().clone(), &user1, user1.id(), &series).await?;
+        nft_approve(
+            &worker,
+            nft.id().clone(),
+            market.id().clone(),
+            &user1,
+            token_id.clone(),
+            sale_conditions.clone(),
+            series.clone(),
+        )
+        .await;
+        tokens.push(token_id);
+    }
+    for token_id in tokens.iter().take(2) {
+        offer(
+            &worker,
+            nft.id().clone(),
+            market.id().clone(),
+            &user2,
+            token_id.clone(),
+            U128(900),
+        )
+        .await;
+    }
+    offer(
+        &worker,
+        nft.id().clone(),
+        market.id().clone(),
+        &user3,
+        tokens[0].clone(),
+        U128(1000),
+    )
+    .await;
+
+    let result: BulkRemoval = user2
+        .call(&worker, market.id().clone(), "remove_all_bids")
+        .deposit(1)
+        .gas(parse_gas!("300 Tgas") as u64)
+        .transact()
+        .await?
+        .json()?;
+    assert_eq!(result.removed, U64(2));
+    assert_eq!(result.remaining, U64(0));
+    assert_eq!(
+        result.refunded.get(&"near".parse::<AccountId>().unwrap()),
+        Some(&U128(1800))
+    );
+    let sale: Option<SaleJson> = market
+        .view(
+            &worker,
+            "get_sale",
+            serde_json::json!({
+                "nft_contract_id": nft.id(),
+                "token_id": tokens[0]
+            })
+            .to_string()
+            .into_bytes(),
+        )
+        .await?
+        .json()?;
+    let bids = sale.unwrap().bids;
+    let bids = bids.get(&"near".parse::<AccountId>().unwrap()).unwrap();
+    assert_eq!(bids.len(), 1);
+    assert_eq!(bids[0].owner_id.as_ref(), user3.id().as_ref());
+
+    let auction_token = mint_token(&worker, nft.id().clone(), &user1, user1.id(), &series).await?;
+    let outcome = user1
+        .call(&worker, nft.id().clone(), "nft_approve")
+        .args_json(serde_json::json!({
+            "token_id": auction_token,
+            "account_id": market.id(),
+            "msg": serde_json::json!(ArgsKind::Auction(AuctionArgs {
+                token_type: None,
+                minimal_step: 100.into(),
+                start_price: 10000.into(),
+                start: None,
+                duration: 900000000000.into(),
+                buy_out_price: None,
+                origins: None,
+                allowed_buyers: None,
+                fallback: None,
+            })).to_string()
+        }))?
+        .deposit(parse_near!("1 N"))
+        .gas(parse_gas!("200 Tgas") as u64)
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+
+    let result: BulkRemoval = user1
+        .call(&worker, market.id().clone(), "remove_all_sales")
+        .args_json(serde_json::json!({ "limit": 2 }))?
+        .deposit(1)
+        .gas(parse_gas!("300 Tgas") as u64)
+        .transact()
+        .await?
+        .json()?;
+    assert_eq!(result.removed, U64(2));
+    assert_eq!(result.remaining, U64(1));
+    let result: BulkRemoval = user1
+        .call(&worker, market.id().clone(), "remove_all_sales")
+        .deposit(1)
+        .gas(parse_gas!("300 Tgas") as u64)
+        .transact()
+        .await?
+        .json()?;
+    assert_eq!(result.removed, U64(1));
+    assert_eq!(result.remaining, U64(0));
+    let supply: U64 = market
+        .view(&worker, "get_supply_sales", vec![])
+        .await?
+        .json()?;
+    assert_eq!(supply, U64(0));
+
+    let result: BulkRemoval = user1
+        .call(&worker, market.id().clone(), "cancel_all_auctions")
+        .deposit(1)
+        .gas(parse_gas!("300 Tgas") as u64)
+        .transact()
+        .await?
+        .json()?;
+    assert_eq!(result.removed, U64(1));
+    assert_eq!(result.remaining, U64(0));
+    assert!(result.refunded.is_empty());
+    Ok(())
+}
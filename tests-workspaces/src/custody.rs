@@ -0,0 +1,116 @@
+use std::collections::HashMap;
+
+use near_units::parse_gas;
+use nft_bid_market::{ArgsKind, SaleArgs, SaleJson};
+use nft_contract::common::U128;
+use workspaces::{Account, Contract, DevNetwork, Worker};
+
+use crate::utils::{
+    buy, check_outcome_success, create_series, create_subaccount, deposit, init_market, init_nft,
+    mint_token, nft_token,
+};
+
+async fn list_in_custody(
+    worker: &Worker<impl DevNetwork>,
+    nft: &Contract,
+    market: &Contract,
+    user: &Account,
+    token_id: &str,
+    series: &str,
+) -> anyhow::Result<()> {
+    let sale_conditions = HashMap::from([("near".parse().unwrap(), 10000.into())]);
+    let outcome = user
+        .call(worker, nft.id().clone(), "nft_transfer_call")
+        .args_json(serde_json::json!({
+            "receiver_id": market.id(),
+            "token_id": token_id,
+            "msg": serde_json::json!(ArgsKind::Sale(SaleArgs {
+                sale_conditions,
+                token_type: Some(series.to_string()),
+                start: None,
+                end: None,
+                origins: None,
+                allowed_buyers: None,
+                reserved_buyer: None,
+            })).to_string()
+        }))?
+        .deposit(1)
+        .gas(parse_gas!("300 Tgas") as u64)
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+    Ok(())
+}
+
+/*
+    - The owner sends the token with `nft_transfer_call`, the market holds it and lists it
+    - The buyer gets the token from the market
+    - `remove_sale` returns the token to the owner
+*/
+#[tokio::test]
+async fn custody_positive() -> anyhow::Result<()> {
+    let worker = workspaces::sandbox();
+    let owner = worker.root_account();
+    let nft = init_nft(&worker, owner.id()).await?;
+    let market = init_market(&worker, worker.root_account().id(), vec![nft.id()]).await?;
+    let user1 = create_subaccount(&worker, &owner, "user1").await?;
+    let user2 = create_subaccount(&worker, &owner, "user2").await?;
+    let series = create_series(&worker, nft.id().clone(), &user1, owner.id().clone()).await?;
+    let token1 = mint_token(&worker, nft.id().clone(), &user1, user1.id(), &series).await?;
+    let token2 = mint_token(&worker, nft.id().clone(), &user1, user1.id(), &series).await?;
+    deposit(&worker, market.id().clone(), &user1).await;
+
+    list_in_custody(&worker, &nft, &market, &user1, &token1, &series).await?;
+    assert_eq!(
+        nft_token(&worker, &nft, &token1).await?.owner_id.as_ref(),
+        market.id().as_ref()
+    );
+    let sale: Option<SaleJson> = market
+        .view(
+            &worker,
+            "get_sale",
+            serde_json::json!({
+                "nft_contract_id": nft.id(),
+                "token_id": token1
+            })
+            .to_string()
+            .into_bytes(),
+        )
+        .await?
+        .json()?;
+    let sale = sale.expect("The sale should exist");
+    assert!(sale.custody);
+    assert_eq!(sale.owner_id.as_ref(), user1.id().as_ref());
+
+    buy(
+        &worker,
+        nft.id().clone(),
+        market.id().clone(),
+        &user2,
+        token1.clone(),
+        U128(10300),
+    )
+    .await;
+    assert_eq!(
+        nft_token(&worker, &nft, &token1).await?.owner_id.as_ref(),
+        user2.id().as_ref()
+    );
+
+    list_in_custody(&worker, &nft, &market, &user1, &token2, &series).await?;
+    let outcome = user1
+        .call(&worker, market.id().clone(), "remove_sale")
+        .args_json(serde_json::json!({
+            "nft_contract_id": nft.id(),
+            "token_id": token2
+        }))?
+        .deposit(1)
+        .gas(parse_gas!("100 Tgas") as u64)
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+    assert_eq!(
+        nft_token(&worker, &nft, &token2).await?.owner_id.as_ref(),
+        user1.id().as_ref()
+    );
+    Ok(())
+}
@@ -1,10 +1,10 @@
 use std::collections::HashMap;
 
 use near_units::parse_near;
-use nft_bid_market::{PAYOUT_TOTAL_VALUE, PROTOCOL_FEE};
+use nft_bid_market::{OriginJson, Page, ORIGIN_FEE_MAX, PAYOUT_TOTAL_VALUE, PROTOCOL_FEE};
 use nft_contract::common::{AccountId, U128};
 
-use crate::utils::init_market;
+use crate::utils::{check_outcome_fail, check_outcome_success, create_subaccount, init_market};
 
 #[tokio::test]
 async fn price_with_fees() -> anyhow::Result<()> {
@@ -58,3 +58,82 @@ async fn price_with_fees() -> anyhow::Result<()> {
     );
     Ok(())
 }
+
+/*
+    - Only the market owner can register origins and set the origin fee cap
+    - The maximum fee of the origin can't exceed the cap
+    - The cap can't exceed `ORIGIN_FEE_MAX`
+    - Registered origins are listed with their maximum fees
+*/
+#[tokio::test]
+async fn origin_registry() -> anyhow::Result<()> {
+    let worker = workspaces::sandbox();
+    let owner = worker.root_account();
+    let market = init_market(&worker, owner.id(), vec![]).await?;
+    let user1 = create_subaccount(&worker, &owner, "user1").await?;
+
+    let outcome = user1
+        .call(&worker, market.id().clone(), "register_origin")
+        .args_json(serde_json::json!({ "origin_id": user1.id(), "max_fee": 100 }))?
+        .transact()
+        .await?;
+    check_outcome_fail(outcome.status, "Only the market owner can manage origins").await;
+
+    let outcome = owner
+        .call(&worker, market.id().clone(), "set_max_origin_fee")
+        .args_json(serde_json::json!({ "max_origin_fee": ORIGIN_FEE_MAX + 1 }))?
+        .transact()
+        .await?;
+    check_outcome_fail(outcome.status, "The origin fee cap can't exceed").await;
+    let outcome = owner
+        .call(&worker, market.id().clone(), "set_max_origin_fee")
+        .args_json(serde_json::json!({ "max_origin_fee": 1000 }))?
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+
+    let outcome = owner
+        .call(&worker, market.id().clone(), "register_origin")
+        .args_json(serde_json::json!({ "origin_id": user1.id(), "max_fee": 1001 }))?
+        .transact()
+        .await?;
+    check_outcome_fail(outcome.status, "The origin fee should be from 1 to 1000").await;
+    let outcome = owner
+        .call(&worker, market.id().clone(), "register_origin")
+        .args_json(serde_json::json!({ "origin_id": user1.id(), "max_fee": 500 }))?
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+
+    let origins: Page<OriginJson, AccountId> = market
+        .view(
+            &worker,
+            "get_origins",
+            serde_json::json!({}).to_string().into_bytes(),
+        )
+        .await?
+        .json()?;
+    assert_eq!(origins.items.len(), 1);
+    assert_eq!(origins.items[0].origin_id.as_str(), user1.id().as_ref());
+    assert_eq!(origins.items[0].max_fee, 500);
+    assert!(origins.items[0].earnings.is_empty());
+
+    let outcome = owner
+        .call(&worker, market.id().clone(), "unregister_origin")
+        .args_json(serde_json::json!({ "origin_id": user1.id() }))?
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+    let origin: Option<OriginJson> = market
+        .view(
+            &worker,
+            "get_origin",
+            serde_json::json!({ "origin_id": user1.id() })
+                .to_string()
+                .into_bytes(),
+        )
+        .await?
+        .json()?;
+    assert!(origin.is_none());
+    Ok(())
+}
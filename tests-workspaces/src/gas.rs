@@ -0,0 +1,70 @@
+use std::collections::HashMap;
+
+use near_units::{parse_gas, parse_near};
+use nft_bid_market::BID_HISTORY_LENGTH_DEFAULT;
+
+use crate::utils::{
+    check_outcome_success, create_series, create_subaccount, deposit, init_market, init_nft,
+    mint_token, nft_approve,
+};
+
+/*
+Gas benchmark for `offer` on a sale with many bids.
+Bids are stored separately from the sale and only the bids for the given `ft_token_id`
+are read and written, so the gas for a bid stays the same as the bid history grows.
+The gas burnt by every bid is printed (`cargo test -- --nocapture`).
+ */
+#[tokio::test]
+async fn offer_gas_with_many_bids() -> anyhow::Result<()> {
+    let worker = workspaces::sandbox();
+    let owner = worker.root_account();
+    let nft = init_nft(&worker, owner.id()).await?;
+    let market = init_market(&worker, worker.root_account().id(), vec![nft.id()]).await?;
+
+    let user1 = create_subaccount(&worker, &owner, "user1").await?;
+    let user2 = create_subaccount(&worker, &owner, "user2").await?;
+
+    let series = create_series(&worker, nft.id().clone(), &user1, owner.id().clone()).await?;
+    let token1 = mint_token(&worker, nft.id().clone(), &user1, user1.id(), &series).await?;
+    deposit(&worker, market.id().clone(), &user1).await;
+    nft_approve(
+        &worker,
+        nft.id().clone(),
+        market.id().clone(),
+        &user1,
+        token1.clone(),
+        HashMap::from([("near".parse().unwrap(), parse_near!("1 N").into())]),
+        series.clone(),
+    )
+    .await;
+
+    let mut gas_burnt = vec![];
+    for i in 1..=(2 * BID_HISTORY_LENGTH_DEFAULT as u128) {
+        let outcome = user2
+            .call(&worker, market.id().clone(), "offer")
+            .args_json(serde_json::json!({
+                "nft_contract_id": nft.id(),
+                "token_id": token1,
+                "ft_token_id": "near",
+            }))?
+            .deposit(1000 * i)
+            .gas(parse_gas!("300 Tgas") as u64)
+            .transact()
+            .await?;
+        gas_burnt.push(outcome.total_gas_burnt);
+        check_outcome_success(outcome.status).await;
+    }
+    // The first bid creates the storage record for the bids
+    let second_bid = gas_burnt[1];
+    let last_bid = *gas_burnt.last().unwrap();
+    assert!(
+        last_bid < second_bid * 3 / 2,
+        "gas grows with the number of bids: {} -> {}",
+        second_bid,
+        last_bid
+    );
+    for (i, gas) in gas_burnt.iter().enumerate() {
+        println!("bid #{}: {} Tgas", i + 1, *gas as f64 / 1e12);
+    }
+    Ok(())
+}
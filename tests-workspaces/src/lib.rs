@@ -11,3 +11,10 @@ mod auction_views;
 mod sale_views;
 mod series_views;
 mod fee;
+mod gas;
+mod standard_nft;
+mod series_sale;
+mod swap;
+mod custody;
+mod bulk;
+mod bid_views;
@@ -1,10 +1,17 @@
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 
-use near_units::parse_near;
+use near_units::{parse_gas, parse_near};
+use nft_bid_market::{
+    ArgsKind, CleanedListing, CleanupCursor, Page, SaleArgs, SaleJson, KEEPER_REWARD_DEFAULT,
+};
+use nft_contract::common::{U128, U64};
 
 use crate::utils::{
     check_outcome_fail, check_outcome_success, create_series_raw, init_market, init_nft,
-    mint_token, nft_approve,
+    mint_token, nft_approve, offer,
 };
 
 #[tokio::test]
@@ -103,3 +110,133 @@ async fn storage_withdraw() -> anyhow::Result<()> {
     // TODO: check balances
     Ok(())
 }
+
+/*
+- Removes the sales which have reached their end
+- Refunds the bids of the removed sales
+- Pays the keeper reward to the caller
+*/
+#[tokio::test]
+async fn cleanup_expired() -> anyhow::Result<()> {
+    let worker = workspaces::sandbox();
+    let owner = worker.root_account();
+    let nft = init_nft(&worker, owner.id()).await?;
+    let market = init_market(&worker, worker.root_account().id(), vec![nft.id()]).await?;
+
+    let user1 = owner
+        .create_subaccount(&worker, "user1")
+        .initial_balance(parse_near!("10 N"))
+        .transact()
+        .await?
+        .unwrap();
+    let user2 = owner
+        .create_subaccount(&worker, "user2")
+        .initial_balance(parse_near!("10 N"))
+        .transact()
+        .await?
+        .unwrap();
+    let user3 = owner
+        .create_subaccount(&worker, "user3")
+        .initial_balance(parse_near!("10 N"))
+        .transact()
+        .await?
+        .unwrap();
+
+    user1
+        .call(&worker, market.id().clone(), "storage_deposit")
+        .deposit(parse_near!("1 N"))
+        .transact()
+        .await?;
+    let series = create_series_raw(
+        &worker,
+        nft.id().clone(),
+        &user1,
+        Some(4),
+        HashMap::from([(user1.id(), 500)]),
+    )
+    .await?;
+    let token1 = mint_token(&worker, nft.id().clone(), &user1, user1.id(), &series).await?;
+    let since_the_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards");
+    let waiting_time = Duration::from_secs(15);
+    let epoch_plus_waiting_time = (since_the_epoch + waiting_time).as_nanos();
+    user1
+        .call(&worker, nft.id().clone(), "nft_approve")
+        .args_json(serde_json::json!({
+            "token_id": token1,
+            "account_id": market.id(),
+            "msg": serde_json::json!(ArgsKind::Sale(SaleArgs {
+                sale_conditions: HashMap::from([("near".parse().unwrap(), 42000.into())]),
+                token_type: Some(series.clone()),
+                start: None,
+                end: Some(U64(epoch_plus_waiting_time as u64)),
+                origins: None,
+                allowed_buyers: None,
+                reserved_buyer: None,
+            })).to_string()
+        }))?
+        .deposit(parse_near!("1 N"))
+        .gas(parse_gas!("200 Tgas") as u64)
+        .transact()
+        .await?;
+    offer(
+        &worker,
+        nft.id().clone(),
+        market.id().clone(),
+        &user2,
+        token1.clone(),
+        4000.into(),
+    )
+    .await;
+
+    // Nothing to clean up before the end of the sale
+    let result: Page<CleanedListing, CleanupCursor> = user3
+        .call(&worker, market.id().clone(), "cleanup_expired")
+        .args_json(serde_json::json!({
+            "cursor": null,
+            "limit": 10,
+        }))?
+        .gas(parse_gas!("300 Tgas") as u64)
+        .transact()
+        .await?
+        .json()?;
+    assert!(result.items.is_empty());
+
+    tokio::time::sleep(waiting_time).await;
+    let result: Page<CleanedListing, CleanupCursor> = user3
+        .call(&worker, market.id().clone(), "cleanup_expired")
+        .args_json(serde_json::json!({
+            "cursor": null,
+            "limit": 10,
+        }))?
+        .gas(parse_gas!("300 Tgas") as u64)
+        .transact()
+        .await?
+        .json()?;
+    assert_eq!(
+        result.items,
+        vec![CleanedListing {
+            nft_contract_id: nft.id().as_ref().parse().unwrap(),
+            token_id: token1.clone(),
+            auction_id: None,
+            reward: U128(KEEPER_REWARD_DEFAULT),
+        }]
+    );
+    assert_eq!(result.next_cursor, None);
+    let sale_json: Option<SaleJson> = market
+        .view(
+            &worker,
+            "get_sale",
+            serde_json::json!({
+               "nft_contract_id": nft.id(),
+               "token_id": token1
+            })
+            .to_string()
+            .into_bytes(),
+        )
+        .await?
+        .json()?;
+    assert!(sale_json.is_none());
+    Ok(())
+}
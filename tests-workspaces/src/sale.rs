@@ -4,12 +4,15 @@ use std::{
 };
 
 use crate::utils::{
-    check_outcome_fail, create_series, create_series_raw, deposit, init_market, init_nft,
-    mint_token, nft_approve, offer,
+    check_outcome_fail, check_outcome_success, create_series, create_series_raw, create_subaccount,
+    deposit, init_market, init_nft, mint_token, nft_approve, offer,
 };
 use near_contract_standards::non_fungible_token::Token;
 use near_units::{parse_gas, parse_near};
-use nft_bid_market::{ArgsKind, SaleArgs, SaleJson, BID_HISTORY_LENGTH_DEFAULT};
+use nft_bid_market::{
+    ArgsKind, AuctionArgs, AuctionJson, ReservedBuyer, SaleArgs, SaleJson,
+    BID_HISTORY_LENGTH_DEFAULT, SALE_LOCK_TIMEOUT,
+};
 use nft_contract::common::{AccountId, U128, U64};
 
 /*
@@ -65,6 +68,8 @@ async fn nft_on_approve_negative() -> anyhow::Result<()> {
                 start: None,
                 end: None,
                 origins: None,
+                allowed_buyers: None,
+                reserved_buyer: None,
             })).to_string()
         }))?
         .transact()
@@ -89,6 +94,8 @@ async fn nft_on_approve_negative() -> anyhow::Result<()> {
                 start: None,
                 end: None,
                 origins: None,
+                allowed_buyers: None,
+                reserved_buyer: None,
             })).to_string()
         }))?
         .deposit(parse_near!("1 N"))
@@ -110,6 +117,8 @@ async fn nft_on_approve_negative() -> anyhow::Result<()> {
                 start: None,
                 end: None,
                 origins: None,
+                allowed_buyers: None,
+                reserved_buyer: None,
             })).to_string()
         }))?
         .deposit(parse_near!("1 N"))
@@ -186,6 +195,8 @@ async fn nft_on_approve_positive() -> anyhow::Result<()> {
                 start: None,
                 end: None,
                 origins: None,
+                allowed_buyers: None,
+                reserved_buyer: None,
             })).to_string()
         }))?
         .deposit(parse_near!("1 N"))
@@ -220,11 +231,10 @@ async fn nft_on_approve_positive() -> anyhow::Result<()> {
     - Should panic if the NFT owner tries to make a bid on his own sale
     - Should panic if the deposit equal to 0
     - Should panic if the NFT can't be bought by `ft_token_id`
-- If the `attached_deposit` is equal to the price + fees
-  -  panics if number of payouts plus number of bids exceeds 10
-- If the `attached_deposit` is not equal to the price + fees
+- The deposit is added as a bid, even if it's equal to the price + fees
   - should panic if `ft_token_id` is not supported
   - panics if the bid smaller or equal to the previous one
+  - panic if an origin is not registered or exceeds its maximum fee
   - panic if origin fee exceeds ORIGIN_FEE_MAX
     */
 #[tokio::test]
@@ -282,6 +292,8 @@ async fn offer_negative() -> anyhow::Result<()> {
                 start: Some(U64(epoch_plus_waiting_time as u64)),
                 end: None,
                 origins: None,
+                allowed_buyers: None,
+                reserved_buyer: None,
             })).to_string()
         }))?
         .deposit(parse_near!("1 N"))
@@ -400,6 +412,49 @@ async fn offer_negative() -> anyhow::Result<()> {
     )
     .await;
 
+    // Not registered origin
+    let outcome = user2
+        .call(&worker, market.id().clone(), "offer")
+        .args_json(serde_json::json!({
+            "nft_contract_id": nft.id(),
+            "token_id": token1,
+            "ft_token_id": "near",
+            "origins": { "user1": 100 },
+        }))?
+        .deposit(2000)
+        .gas(parse_gas!("300 Tgas") as u64)
+        .transact()
+        .await?;
+    check_outcome_fail(outcome.status, "user1 is not a registered origin").await;
+
+    for origin_id in ["user1", "user3"] {
+        let outcome = owner
+            .call(&worker, market.id().clone(), "register_origin")
+            .args_json(serde_json::json!({ "origin_id": origin_id, "max_fee": 2500 }))?
+            .transact()
+            .await?;
+        check_outcome_success(outcome.status).await;
+    }
+
+    // Exceeding the maximum fee of the origin
+    let outcome = user2
+        .call(&worker, market.id().clone(), "offer")
+        .args_json(serde_json::json!({
+            "nft_contract_id": nft.id(),
+            "token_id": token1,
+            "ft_token_id": "near",
+            "origins": { "user1": 2600 },
+        }))?
+        .deposit(2000)
+        .gas(parse_gas!("300 Tgas") as u64)
+        .transact()
+        .await?;
+    check_outcome_fail(
+        outcome.status,
+        "The fee of the origin user1 exceeds its maximum 2500",
+    )
+    .await;
+
     // Exceeding ORIGIN_FEE_MAX
     let outcome = user2
         .call(&worker, market.id().clone(), "offer")
@@ -407,11 +462,9 @@ async fn offer_negative() -> anyhow::Result<()> {
             "nft_contract_id": nft.id(),
             "token_id": token1,
             "ft_token_id": "near",
-            "origins": {
-                "user1": 4701,
-            }
+            "origins": { "user1": 2500, "user3": 2500 },
         }))?
-        .deposit(2000) // equal
+        .deposit(2000)
         .gas(parse_gas!("300 Tgas") as u64)
         .transact()
         .await?;
@@ -469,15 +522,14 @@ async fn offer_negative() -> anyhow::Result<()> {
 }
 
 /*
-- If the `attached_deposit` is equal to the price + fees
+- `buy` with the `attached_deposit` equal to the price + fees
     -  NFT is transferred to the buyer
     -  the sale is removed from the list of sales
     -  ft transferred to the previous owner
     -  protocol, royalty and origin fees are paid
     -  royalty paid from seller side
     -  previous bids refunded
-- If the `attached_deposit` is not equal to the price + fees
-  - a new bid should be added
+- `offer` adds a new bid
   - if the number of stored bids exceeds `bid_history_length`, the earliest bid is removed and refunded
 */
 #[tokio::test]
@@ -543,11 +595,10 @@ async fn offer_positive() -> anyhow::Result<()> {
         .await?
         .json()?;
     user2
-        .call(&worker, market.id().clone(), "offer")
+        .call(&worker, market.id().clone(), "buy")
         .args_json(serde_json::json!({
             "nft_contract_id": nft.id(),
             "token_id": token1,
-            "ft_token_id": "near",
         }))?
         .deposit(price.into())
         .gas(parse_gas!("300 Tgas") as u64)
@@ -693,6 +744,139 @@ async fn offer_positive() -> anyhow::Result<()> {
     Ok(())
 }
 
+/*
+- Should panic if the deposit is less than the price + fees
+- Should panic if the NFT owner tries to buy his own token
+- The purchase is made with the deposit above the price + fees
+- `offer` with the deposit equal to the price + fees adds a bid
+*/
+#[tokio::test]
+async fn buy() -> anyhow::Result<()> {
+    let worker = workspaces::sandbox();
+    let owner = worker.root_account();
+    let nft = init_nft(&worker, owner.id()).await?;
+    let market = init_market(&worker, worker.root_account().id(), vec![nft.id()]).await?;
+
+    let user1 = owner
+        .create_subaccount(&worker, "user1")
+        .initial_balance(parse_near!("10 N"))
+        .transact()
+        .await?
+        .unwrap();
+
+    let user2 = owner
+        .create_subaccount(&worker, "user2")
+        .initial_balance(parse_near!("10 N"))
+        .transact()
+        .await?
+        .unwrap();
+
+    let series = create_series(&worker, nft.id().clone(), &user1, owner.id().clone()).await?;
+    let token1 = mint_token(&worker, nft.id().clone(), &user1, user1.id(), &series).await?;
+
+    deposit(&worker, market.id().clone(), &user1).await;
+    let sale_conditions = HashMap::from([("near".parse().unwrap(), 10000.into())]);
+    nft_approve(
+        &worker,
+        nft.id().clone(),
+        market.id().clone(),
+        &user1,
+        token1.clone(),
+        sale_conditions.clone(),
+        series.clone(),
+    )
+    .await;
+    let price: U128 = market
+        .view(
+            &worker,
+            "price_with_fees",
+            serde_json::json!({
+                "price": sale_conditions.get(&AccountId::new_unchecked("near".to_string())).unwrap(),
+            })
+            .to_string()
+            .into_bytes(),
+        )
+        .await?
+        .json()?;
+
+    let outcome = user2
+        .call(&worker, market.id().clone(), "buy")
+        .args_json(serde_json::json!({
+            "nft_contract_id": nft.id(),
+            "token_id": token1,
+        }))?
+        .deposit(price.0 - 1)
+        .gas(parse_gas!("300 Tgas") as u64)
+        .transact()
+        .await?;
+    check_outcome_fail(
+        outcome.status,
+        &format!("Attached deposit must be at least {}", price.0),
+    )
+    .await;
+    let outcome = user1
+        .call(&worker, market.id().clone(), "buy")
+        .args_json(serde_json::json!({
+            "nft_contract_id": nft.id(),
+            "token_id": token1,
+        }))?
+        .deposit(price.into())
+        .gas(parse_gas!("300 Tgas") as u64)
+        .transact()
+        .await?;
+    check_outcome_fail(outcome.status, "Cannot buy your own sale.").await;
+
+    // the exact price is a bid now
+    offer(
+        &worker,
+        nft.id().clone(),
+        market.id().clone(),
+        &user2,
+        token1.clone(),
+        price,
+    )
+    .await;
+    let sale_json: Option<SaleJson> = market
+        .view(
+            &worker,
+            "get_sale",
+            serde_json::json!({
+               "nft_contract_id": nft.id(),
+               "token_id": token1
+            })
+            .to_string()
+            .into_bytes(),
+        )
+        .await?
+        .json()?;
+    let bids = sale_json.expect("No sale").bids;
+    assert_eq!(bids.values().flatten().count(), 1);
+
+    let outcome = user2
+        .call(&worker, market.id().clone(), "buy")
+        .args_json(serde_json::json!({
+            "nft_contract_id": nft.id(),
+            "token_id": token1,
+        }))?
+        .deposit(parse_near!("1 N"))
+        .gas(parse_gas!("300 Tgas") as u64)
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+    let token: Token = nft
+        .view(
+            &worker,
+            "nft_token",
+            serde_json::json!({ "token_id": token1 })
+                .to_string()
+                .into_bytes(),
+        )
+        .await?
+        .json()?;
+    assert_eq!(token.owner_id.as_str(), user2.id().as_ref());
+    Ok(())
+}
+
 /*
 - Should panic if there is no sale with the given `nft_contract_id` and `token_id`
 - Should panic if there are no bids with given fungible token
@@ -761,6 +945,8 @@ async fn accept_offer_negative() -> anyhow::Result<()> {
                 start: None,
                 end: Some(U64(epoch_plus_waiting_time as u64)),
                 origins: None,
+                allowed_buyers: None,
+                reserved_buyer: None,
             })).to_string()
         }))?
         .deposit(parse_near!("1 N"))
@@ -1079,105 +1265,261 @@ async fn update_price_positive() -> anyhow::Result<()> {
 }
 
 /*
-- Should panic unless 1 yoctoNEAR is attached
-- If the sale in progress, only the sale creator can remove the sale
- */
+- Replaces the arguments of the sale, keeps the bids of the allowed buyers
+- Refunds the bids of the buyers who are not allowed anymore
+- Panics on removing the last price
+- With `Auction` arguments the sale becomes an auction
+*/
 #[tokio::test]
-async fn remove_sale_negative() -> anyhow::Result<()> {
+async fn update_sale_positive() -> anyhow::Result<()> {
     let worker = workspaces::sandbox();
     let owner = worker.root_account();
     let nft = init_nft(&worker, owner.id()).await?;
     let market = init_market(&worker, worker.root_account().id(), vec![nft.id()]).await?;
+    let user1 = create_subaccount(&worker, &owner, "user1").await?;
+    let user2 = create_subaccount(&worker, &owner, "user2").await?;
+    let user3 = create_subaccount(&worker, &owner, "user3").await?;
 
-    let user1 = owner
-        .create_subaccount(&worker, "user1")
-        .initial_balance(parse_near!("10 N"))
-        .transact()
-        .await?
-        .unwrap();
-
-    let user2 = owner
-        .create_subaccount(&worker, "user2")
-        .initial_balance(parse_near!("10 N"))
-        .transact()
-        .await?
-        .unwrap();
-
-    let series = create_series_raw(
-        &worker,
-        nft.id().clone(),
-        &user1,
-        Some(4),
-        HashMap::from([(user1.id(), 500)]),
-    )
-    .await?;
+    let series = create_series(&worker, nft.id().clone(), &user1, owner.id().clone()).await?;
     let token1 = mint_token(&worker, nft.id().clone(), &user1, user1.id(), &series).await?;
     deposit(&worker, market.id().clone(), &user1).await;
-    let sale_conditions = HashMap::from([("near".parse().unwrap(), 42000.into())]);
+    let sale_conditions = HashMap::from([("near".parse().unwrap(), 10000.into())]);
     nft_approve(
         &worker,
         nft.id().clone(),
         market.id().clone(),
         &user1,
         token1.clone(),
-        sale_conditions.clone(),
+        sale_conditions,
         series.clone(),
     )
     .await;
+    offer(
+        &worker,
+        nft.id().clone(),
+        market.id().clone(),
+        &user2,
+        token1.clone(),
+        U128(900),
+    )
+    .await;
+    offer(
+        &worker,
+        nft.id().clone(),
+        market.id().clone(),
+        &user3,
+        token1.clone(),
+        U128(1000),
+    )
+    .await;
 
-    // 1 yocto is needed
     let outcome = user1
-        .call(&worker, market.id().clone(), "remove_sale")
+        .call(&worker, market.id().clone(), "update_sale")
         .args_json(serde_json::json!({
             "nft_contract_id": nft.id(),
-            "token_id": token1
+            "token_id": token1,
+            "args": ArgsKind::Sale(SaleArgs {
+                sale_conditions: HashMap::from([("near".parse().unwrap(), 20000.into())]),
+                token_type: Some(series.clone()),
+                start: None,
+                end: None,
+                origins: None,
+                allowed_buyers: Some(vec![user3.id().as_ref().parse().unwrap()]),
+                reserved_buyer: None,
+            }),
         }))?
+        .deposit(1)
+        .gas(parse_gas!("100 Tgas") as u64)
         .transact()
         .await?;
-    check_outcome_fail(
-        outcome.status,
-        "Requires attached deposit of exactly 1 yoctoNEAR",
-    )
-    .await;
+    check_outcome_success(outcome.status).await;
+    let sale_json: SaleJson = market
+        .view(
+            &worker,
+            "get_sale",
+            serde_json::json!({
+               "nft_contract_id": nft.id(),
+               "token_id": token1
+            })
+            .to_string()
+            .into_bytes(),
+        )
+        .await?
+        .json()?;
+    assert_eq!(
+        sale_json.sale_conditions.get(&"near".parse().unwrap()),
+        Some(&U128(20000))
+    );
+    let bids = sale_json.bids.get(&"near".parse().unwrap()).unwrap();
+    assert_eq!(bids.len(), 1);
+    assert_eq!(bids[0].owner_id.as_ref(), user3.id().as_ref());
 
-    // Can be removed only by the owner of the sale, if not finished
-    let outcome = user2
-        .call(&worker, market.id().clone(), "remove_sale")
+    let outcome = user1
+        .call(&worker, market.id().clone(), "remove_price")
         .args_json(serde_json::json!({
             "nft_contract_id": nft.id(),
-            "token_id": token1
+            "token_id": token1,
+            "ft_token_id": "near",
         }))?
         .deposit(1)
         .transact()
         .await?;
     check_outcome_fail(
         outcome.status,
-        "Until the sale is finished, it can only be removed by the sale owner",
+        "Cannot remove the last price, use remove_sale",
     )
     .await;
-    Ok(())
-}
-
-/*
-- Sale removed
-- Refunds all bids
-*/
-#[tokio::test]
-async fn remove_sale_positive() -> anyhow::Result<()> {
-    let worker = workspaces::sandbox();
-    let owner = worker.root_account();
-    let nft = init_nft(&worker, owner.id()).await?;
-    let market = init_market(&worker, worker.root_account().id(), vec![nft.id()]).await?;
-
-    let user1 = owner
-        .create_subaccount(&worker, "user1")
-        .initial_balance(parse_near!("10 N"))
-        .transact()
-        .await?
-        .unwrap();
 
-    let user2 = owner
-        .create_subaccount(&worker, "user2")
+    let outcome = user1
+        .call(&worker, market.id().clone(), "update_sale")
+        .args_json(serde_json::json!({
+            "nft_contract_id": nft.id(),
+            "token_id": token1,
+            "args": ArgsKind::Auction(AuctionArgs {
+                token_type: None,
+                minimal_step: 100.into(),
+                start_price: 10000.into(),
+                start: None,
+                duration: 900000000000.into(),
+                buy_out_price: None,
+                origins: None,
+                allowed_buyers: None,
+                fallback: None,
+            }),
+        }))?
+        .deposit(1)
+        .gas(parse_gas!("100 Tgas") as u64)
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+    let sale_json: Option<SaleJson> = market
+        .view(
+            &worker,
+            "get_sale",
+            serde_json::json!({
+               "nft_contract_id": nft.id(),
+               "token_id": token1
+            })
+            .to_string()
+            .into_bytes(),
+        )
+        .await?
+        .json()?;
+    assert!(sale_json.is_none());
+    let auction: AuctionJson = market
+        .view(
+            &worker,
+            "get_auction",
+            serde_json::json!({ "auction_id": "0" })
+                .to_string()
+                .into_bytes(),
+        )
+        .await?
+        .json()?;
+    assert_eq!(auction.token_id, token1);
+    Ok(())
+}
+
+/*
+- Should panic unless 1 yoctoNEAR is attached
+- If the sale in progress, only the sale creator can remove the sale
+ */
+#[tokio::test]
+async fn remove_sale_negative() -> anyhow::Result<()> {
+    let worker = workspaces::sandbox();
+    let owner = worker.root_account();
+    let nft = init_nft(&worker, owner.id()).await?;
+    let market = init_market(&worker, worker.root_account().id(), vec![nft.id()]).await?;
+
+    let user1 = owner
+        .create_subaccount(&worker, "user1")
+        .initial_balance(parse_near!("10 N"))
+        .transact()
+        .await?
+        .unwrap();
+
+    let user2 = owner
+        .create_subaccount(&worker, "user2")
+        .initial_balance(parse_near!("10 N"))
+        .transact()
+        .await?
+        .unwrap();
+
+    let series = create_series_raw(
+        &worker,
+        nft.id().clone(),
+        &user1,
+        Some(4),
+        HashMap::from([(user1.id(), 500)]),
+    )
+    .await?;
+    let token1 = mint_token(&worker, nft.id().clone(), &user1, user1.id(), &series).await?;
+    deposit(&worker, market.id().clone(), &user1).await;
+    let sale_conditions = HashMap::from([("near".parse().unwrap(), 42000.into())]);
+    nft_approve(
+        &worker,
+        nft.id().clone(),
+        market.id().clone(),
+        &user1,
+        token1.clone(),
+        sale_conditions.clone(),
+        series.clone(),
+    )
+    .await;
+
+    // 1 yocto is needed
+    let outcome = user1
+        .call(&worker, market.id().clone(), "remove_sale")
+        .args_json(serde_json::json!({
+            "nft_contract_id": nft.id(),
+            "token_id": token1
+        }))?
+        .transact()
+        .await?;
+    check_outcome_fail(
+        outcome.status,
+        "Requires attached deposit of exactly 1 yoctoNEAR",
+    )
+    .await;
+
+    // Can be removed only by the owner of the sale, if not finished
+    let outcome = user2
+        .call(&worker, market.id().clone(), "remove_sale")
+        .args_json(serde_json::json!({
+            "nft_contract_id": nft.id(),
+            "token_id": token1
+        }))?
+        .deposit(1)
+        .transact()
+        .await?;
+    check_outcome_fail(
+        outcome.status,
+        "Until the sale is finished, it can only be removed by the sale owner",
+    )
+    .await;
+    Ok(())
+}
+
+/*
+- Sale removed
+- Refunds all bids
+*/
+#[tokio::test]
+async fn remove_sale_positive() -> anyhow::Result<()> {
+    let worker = workspaces::sandbox();
+    let owner = worker.root_account();
+    let nft = init_nft(&worker, owner.id()).await?;
+    let market = init_market(&worker, worker.root_account().id(), vec![nft.id()]).await?;
+
+    let user1 = owner
+        .create_subaccount(&worker, "user1")
+        .initial_balance(parse_near!("10 N"))
+        .transact()
+        .await?
+        .unwrap();
+
+    let user2 = owner
+        .create_subaccount(&worker, "user2")
         .initial_balance(parse_near!("10 N"))
         .transact()
         .await?
@@ -1238,3 +1580,373 @@ async fn remove_sale_positive() -> anyhow::Result<()> {
     assert!(sale_json.is_none());
     Ok(())
 }
+
+/*
+- A sale locked by a purchase can't be removed
+- The lock of a purchase which hasn't been resolved in `SALE_LOCK_TIMEOUT` is ignored,
+  the sale can be removed and its bids are refunded
+*/
+#[tokio::test]
+async fn expired_sale_lock() -> anyhow::Result<()> {
+    let worker = workspaces::sandbox();
+    let owner = worker.root_account();
+    let nft = init_nft(&worker, owner.id()).await?;
+    let market = init_market(&worker, worker.root_account().id(), vec![nft.id()]).await?;
+    let user1 = create_subaccount(&worker, &owner, "user1").await?;
+    let user2 = create_subaccount(&worker, &owner, "user2").await?;
+    let series = create_series(&worker, nft.id().clone(), &user1, owner.id().clone()).await?;
+    let token1 = mint_token(&worker, nft.id().clone(), &user1, user1.id(), &series).await?;
+    deposit(&worker, market.id().clone(), &user1).await;
+    let sale_conditions = HashMap::from([("near".parse().unwrap(), 42000.into())]);
+    nft_approve(
+        &worker,
+        nft.id().clone(),
+        market.id().clone(),
+        &user1,
+        token1.clone(),
+        sale_conditions,
+        series,
+    )
+    .await;
+    offer(
+        &worker,
+        nft.id().clone(),
+        market.id().clone(),
+        &user2,
+        token1.clone(),
+        4000.into(),
+    )
+    .await;
+
+    // a purchase which is still in progress
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_nanos() as u64;
+    let nft_contract_token = format!("{}||{}", nft.id(), token1);
+    let outcome = user1
+        .call(&worker, market.id().clone(), "hack_lock_sale")
+        .args_json(serde_json::json!({
+            "nft_contract_token": nft_contract_token,
+            "locked_at": U64(now),
+        }))?
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+    let remove_args = serde_json::json!({
+        "nft_contract_id": nft.id(),
+        "token_id": token1
+    });
+    let outcome = user1
+        .call(&worker, market.id().clone(), "remove_sale")
+        .args_json(remove_args.clone())?
+        .deposit(1)
+        .transact()
+        .await?;
+    check_outcome_fail(outcome.status, "The sale is locked by a purchase").await;
+
+    // the callback of the purchase has failed long ago
+    let locked_at = now - SALE_LOCK_TIMEOUT - Duration::from_secs(60).as_nanos() as u64;
+    let outcome = user1
+        .call(&worker, market.id().clone(), "hack_lock_sale")
+        .args_json(serde_json::json!({
+            "nft_contract_token": nft_contract_token,
+            "locked_at": U64(locked_at),
+        }))?
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+    let sale_json: Option<SaleJson> = market
+        .view(
+            &worker,
+            "get_sale",
+            serde_json::json!({
+               "nft_contract_id": nft.id(),
+               "token_id": token1
+            })
+            .to_string()
+            .into_bytes(),
+        )
+        .await?
+        .json()?;
+    assert!(!sale_json.expect("No sale").locked);
+    let outcome = user1
+        .call(&worker, market.id().clone(), "remove_sale")
+        .args_json(remove_args)?
+        .deposit(1)
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+    let bids: serde_json::Value = market
+        .view(
+            &worker,
+            "get_bids_by_account",
+            serde_json::json!({ "account_id": user2.id() })
+                .to_string()
+                .into_bytes(),
+        )
+        .await?
+        .json()?;
+    assert_eq!(bids["items"].as_array().map(|items| items.len()), Some(0));
+    Ok(())
+}
+
+/*
+- Should panic if the buyer is not in `allowed_buyers`
+- The reserved buyer can buy the token at the private price
+- The private price is not shown in the sale
+ */
+#[tokio::test]
+async fn offer_private_sale() -> anyhow::Result<()> {
+    let worker = workspaces::sandbox();
+    let owner = worker.root_account();
+    let nft = init_nft(&worker, owner.id()).await?;
+    let market = init_market(&worker, worker.root_account().id(), vec![nft.id()]).await?;
+
+    let user1 = owner
+        .create_subaccount(&worker, "user1")
+        .initial_balance(parse_near!("10 N"))
+        .transact()
+        .await?
+        .unwrap();
+    let user2 = owner
+        .create_subaccount(&worker, "user2")
+        .initial_balance(parse_near!("10 N"))
+        .transact()
+        .await?
+        .unwrap();
+    let user3 = owner
+        .create_subaccount(&worker, "user3")
+        .initial_balance(parse_near!("10 N"))
+        .transact()
+        .await?
+        .unwrap();
+
+    let series = create_series(&worker, nft.id().clone(), &user1, owner.id().clone()).await?;
+    let token1 = mint_token(&worker, nft.id().clone(), &user1, user1.id(), &series).await?;
+    deposit(&worker, market.id().clone(), &user1).await;
+    let reserved_price = U128(5000);
+    user1
+        .call(&worker, nft.id().clone(), "nft_approve")
+        .args_json(serde_json::json!({
+            "token_id": token1,
+            "account_id": market.id(),
+            "msg": serde_json::json!(ArgsKind::Sale(SaleArgs {
+                sale_conditions: HashMap::from([("near".parse().unwrap(), 10000.into())]),
+                token_type: Some(series.clone()),
+                start: None,
+                end: None,
+                origins: None,
+                allowed_buyers: Some(vec![]),
+                reserved_buyer: Some(ReservedBuyer {
+                    buyer_id: user2.id().as_ref().parse().unwrap(),
+                    sale_conditions: HashMap::from([("near".parse().unwrap(), reserved_price)]),
+                }),
+            })).to_string()
+        }))?
+        .deposit(parse_near!("1 N"))
+        .gas(parse_gas!("200 Tgas") as u64)
+        .transact()
+        .await?;
+
+    let sale_json: SaleJson = market
+        .view(
+            &worker,
+            "get_sale",
+            serde_json::json!({
+               "nft_contract_id": nft.id(),
+               "token_id": token1
+            })
+            .to_string()
+            .into_bytes(),
+        )
+        .await?
+        .json()?;
+    assert!(sale_json.is_private);
+    assert_eq!(sale_json.reserved_buyer.unwrap().as_str(), user2.id().as_ref());
+
+    // Not allowed buyer
+    let outcome = user3
+        .call(&worker, market.id().clone(), "offer")
+        .args_json(serde_json::json!({
+            "nft_contract_id": nft.id(),
+            "token_id": token1,
+            "ft_token_id": "near",
+        }))?
+        .deposit(1000)
+        .gas(parse_gas!("300 Tgas") as u64)
+        .transact()
+        .await?;
+    check_outcome_fail(outcome.status, "The sale is private, the buyer is not allowed").await;
+
+    // The reserved buyer pays the private price
+    let price: U128 = market
+        .view(
+            &worker,
+            "price_with_fees",
+            serde_json::json!({ "price": reserved_price })
+                .to_string()
+                .into_bytes(),
+        )
+        .await?
+        .json()?;
+    let outcome = user2
+        .call(&worker, market.id().clone(), "buy")
+        .args_json(serde_json::json!({
+            "nft_contract_id": nft.id(),
+            "token_id": token1,
+        }))?
+        .deposit(price.into())
+        .gas(parse_gas!("300 Tgas") as u64)
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+    let token: Token = nft
+        .view(
+            &worker,
+            "nft_token",
+            serde_json::json!({ "token_id": token1 })
+                .to_string()
+                .into_bytes(),
+        )
+        .await?
+        .json()?;
+    assert_eq!(token.owner_id.as_str(), user2.id().as_ref());
+    Ok(())
+}
+
+/*
+- `verify_listing` removes the sale if the token was transferred by its owner
+- The purchase of a stale sale is refunded and the sale is removed
+ */
+#[tokio::test]
+async fn verify_listing() -> anyhow::Result<()> {
+    let worker = workspaces::sandbox();
+    let owner = worker.root_account();
+    let nft = init_nft(&worker, owner.id()).await?;
+    let market = init_market(&worker, worker.root_account().id(), vec![nft.id()]).await?;
+
+    let user1 = owner
+        .create_subaccount(&worker, "user1")
+        .initial_balance(parse_near!("10 N"))
+        .transact()
+        .await?
+        .unwrap();
+    let user2 = owner
+        .create_subaccount(&worker, "user2")
+        .initial_balance(parse_near!("10 N"))
+        .transact()
+        .await?
+        .unwrap();
+    let user3 = owner
+        .create_subaccount(&worker, "user3")
+        .initial_balance(parse_near!("10 N"))
+        .transact()
+        .await?
+        .unwrap();
+
+    let series = create_series(&worker, nft.id().clone(), &user1, owner.id().clone()).await?;
+    let token1 = mint_token(&worker, nft.id().clone(), &user1, user1.id(), &series).await?;
+    let token2 = mint_token(&worker, nft.id().clone(), &user1, user1.id(), &series).await?;
+    deposit(&worker, market.id().clone(), &user1).await;
+    let sale_conditions = HashMap::from([("near".parse().unwrap(), 10000.into())]);
+    for token in [&token1, &token2] {
+        nft_approve(
+            &worker,
+            nft.id().clone(),
+            market.id().clone(),
+            &user1,
+            token.clone(),
+            sale_conditions.clone(),
+            series.clone(),
+        )
+        .await;
+        // the owner transfers the token elsewhere, the approval is cleared
+        user1
+            .call(&worker, nft.id().clone(), "nft_transfer")
+            .args_json(serde_json::json!({
+                "receiver_id": user3.id(),
+                "token_id": token,
+            }))?
+            .deposit(1)
+            .transact()
+            .await?;
+    }
+
+    let is_valid: bool = user2
+        .call(&worker, market.id().clone(), "verify_listing")
+        .args_json(serde_json::json!({
+            "nft_contract_id": nft.id(),
+            "token_id": token1,
+        }))?
+        .gas(parse_gas!("100 Tgas") as u64)
+        .transact()
+        .await?
+        .json()?;
+    assert!(!is_valid);
+    let sale_json: Option<SaleJson> = market
+        .view(
+            &worker,
+            "get_sale",
+            serde_json::json!({
+               "nft_contract_id": nft.id(),
+               "token_id": token1
+            })
+            .to_string()
+            .into_bytes(),
+        )
+        .await?
+        .json()?;
+    assert!(sale_json.is_none());
+
+    // The buyer is refunded
+    let price: U128 = market
+        .view(
+            &worker,
+            "price_with_fees",
+            serde_json::json!({
+                "price": sale_conditions.get(&AccountId::new_unchecked("near".to_string())).unwrap(),
+            })
+            .to_string()
+            .into_bytes(),
+        )
+        .await?
+        .json()?;
+    let outcome = user2
+        .call(&worker, market.id().clone(), "buy")
+        .args_json(serde_json::json!({
+            "nft_contract_id": nft.id(),
+            "token_id": token2,
+        }))?
+        .deposit(price.into())
+        .gas(parse_gas!("300 Tgas") as u64)
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+    let token: Token = nft
+        .view(
+            &worker,
+            "nft_token",
+            serde_json::json!({ "token_id": token2 })
+                .to_string()
+                .into_bytes(),
+        )
+        .await?
+        .json()?;
+    assert_eq!(token.owner_id.as_str(), user3.id().as_ref());
+    let sale_json: Option<SaleJson> = market
+        .view(
+            &worker,
+            "get_sale",
+            serde_json::json!({
+               "nft_contract_id": nft.id(),
+               "token_id": token2
+            })
+            .to_string()
+            .into_bytes(),
+        )
+        .await?
+        .json()?;
+    assert!(sale_json.is_none());
+    Ok(())
+}
@@ -1,10 +1,13 @@
 use std::collections::HashMap;
 
 use near_units::{parse_gas, parse_near};
-use nft_bid_market::SaleJson;
-use nft_contract::common::U64;
+use nft_bid_market::{ArgsKind, CollectionStats, Page, SaleArgs, SaleJson, SearchCursor, Trade};
+use nft_contract::common::{AccountId, U128, U64};
 
-use crate::utils::{create_series, deposit, init_market, init_nft, mint_token, nft_approve, offer};
+use crate::utils::{
+    buy, check_outcome_success, create_series, create_subaccount, deposit, init_market, init_nft,
+    mint_token, nft_approve, offer, price_with_fees,
+};
 
 #[tokio::test]
 async fn sale_views() -> anyhow::Result<()> {
@@ -83,12 +86,54 @@ async fn sale_views() -> anyhow::Result<()> {
             serde_json::json!({}).to_string().into_bytes(),
         )
         .await?
-        .json()?;
+        .json::<Page<SaleJson, SearchCursor>>()?
+        .items;
     assert_eq!(sales.len(), tokens_series1.len() + tokens_series2.len());
     assert!(
         tokens_series1.contains(&sales[1].token_id) || tokens_series2.contains(&sales[1].token_id)
     );
 
+    // the sales are split into pages, the oldest sales first
+    let first_page: Page<SaleJson, SearchCursor> = market
+        .view(
+            &worker,
+            "get_sales",
+            serde_json::json!({ "cursor": null, "limit": 3 })
+                .to_string()
+                .into_bytes(),
+        )
+        .await?
+        .json()?;
+    assert_eq!(first_page.items.len(), 3);
+    assert!(first_page.next_cursor.is_some());
+    let last_page: Page<SaleJson, SearchCursor> = market
+        .view(
+            &worker,
+            "get_sales",
+            serde_json::json!({ "cursor": first_page.next_cursor, "limit": 3 })
+                .to_string()
+                .into_bytes(),
+        )
+        .await?
+        .json()?;
+    assert_eq!(last_page.items.len(), 2);
+    assert_eq!(last_page.next_cursor, None);
+    assert!(last_page
+        .items
+        .iter()
+        .all(|sale| !first_page.items.contains(sale)));
+    // an empty page would return the same cursor forever
+    let empty_page = market
+        .view(
+            &worker,
+            "get_sales",
+            serde_json::json!({ "cursor": null, "limit": 0 })
+                .to_string()
+                .into_bytes(),
+        )
+        .await;
+    assert!(empty_page.is_err());
+
     let supply_by_owner: U64 = market
         .view(
             &worker,
@@ -109,14 +154,15 @@ async fn sale_views() -> anyhow::Result<()> {
             "get_sales_by_owner_id",
             serde_json::json!({
                 "account_id": user2.id(),
-                "from_index": "0",
+                "cursor": null,
                 "limit": 10,
             })
             .to_string()
             .into_bytes(),
         )
         .await?
-        .json()?;
+        .json::<Page<SaleJson, String>>()?
+        .items;
     assert_eq!(sales_user2.len(), tokens_series2.len());
     assert!(tokens_series2.contains(&sales_user2[1].token_id));
 
@@ -143,14 +189,15 @@ async fn sale_views() -> anyhow::Result<()> {
             "get_sales_by_nft_contract_id",
             serde_json::json!({
                 "nft_contract_id": nft.id(),
-                "from_index": "0",
+                "cursor": null,
                 "limit": 10,
             })
             .to_string()
             .into_bytes(),
         )
         .await?
-        .json()?;
+        .json::<Page<SaleJson, String>>()?
+        .items;
     assert_eq!(
         sales_nft_contract_id.len(),
         tokens_series1.len() + tokens_series2.len()
@@ -178,14 +225,15 @@ async fn sale_views() -> anyhow::Result<()> {
             "get_sales_by_nft_token_type",
             serde_json::json!({
                 "token_type": series2,
-                "from_index": "0",
+                "cursor": null,
                 "limit": 10,
             })
             .to_string()
             .into_bytes(),
         )
         .await?
-        .json()?;
+        .json::<Page<SaleJson, String>>()?
+        .items;
     assert_eq!(sales_nft_token_type.len(), tokens_series2.len());
     assert!(tokens_series2.contains(&sales_nft_token_type[1].token_id));
 
@@ -271,7 +319,8 @@ async fn sale_views() -> anyhow::Result<()> {
             serde_json::json!({}).to_string().into_bytes(),
         )
         .await?
-        .json()?;
+        .json::<Page<SaleJson, SearchCursor>>()?
+        .items;
     assert_eq!(sales.len(), tokens_series1.len() + tokens_series2.len());
     assert!(
         tokens_series1.contains(&sales[1].token_id) || tokens_series2.contains(&sales[1].token_id)
@@ -297,14 +346,15 @@ async fn sale_views() -> anyhow::Result<()> {
             "get_sales_by_owner_id",
             serde_json::json!({
                 "account_id": user2.id(),
-                "from_index": "0",
+                "cursor": null,
                 "limit": 10,
             })
             .to_string()
             .into_bytes(),
         )
         .await?
-        .json()?;
+        .json::<Page<SaleJson, String>>()?
+        .items;
     assert_eq!(sales_user2.len(), tokens_series2.len());
     assert!(tokens_series2.contains(&sales_user2[0].token_id));
 
@@ -331,14 +381,15 @@ async fn sale_views() -> anyhow::Result<()> {
             "get_sales_by_nft_contract_id",
             serde_json::json!({
                 "nft_contract_id": nft.id(),
-                "from_index": "0",
+                "cursor": null,
                 "limit": 10,
             })
             .to_string()
             .into_bytes(),
         )
         .await?
-        .json()?;
+        .json::<Page<SaleJson, String>>()?
+        .items;
     assert_eq!(
         sales_nft_contract_id.len(),
         tokens_series1.len() + tokens_series2.len()
@@ -366,15 +417,397 @@ async fn sale_views() -> anyhow::Result<()> {
             "get_sales_by_nft_token_type",
             serde_json::json!({
                 "token_type": series2,
-                "from_index": "0",
+                "cursor": null,
                 "limit": 10,
             })
             .to_string()
             .into_bytes(),
         )
         .await?
-        .json()?;
+        .json::<Page<SaleJson, String>>()?
+        .items;
     assert_eq!(sales_nft_token_type.len(), tokens_series2.len());
     assert!(tokens_series2.contains(&sales_nft_token_type[0].token_id));
+
+    // removing a sale of the previous page doesn't make the next page skip a sale
+    let first_page: Page<SaleJson, SearchCursor> = market
+        .view(
+            &worker,
+            "get_sales",
+            serde_json::json!({ "cursor": null, "limit": 2 })
+                .to_string()
+                .into_bytes(),
+        )
+        .await?
+        .json()?;
+    let first_tokens: Vec<String> = first_page
+        .items
+        .iter()
+        .map(|sale| sale.token_id.clone())
+        .collect();
+    assert_eq!(first_tokens, tokens_series1);
+    let outcome = user1
+        .call(&worker, market.id().clone(), "remove_sale")
+        .args_json(serde_json::json!({
+            "nft_contract_id": nft.id(),
+            "token_id": tokens_series1[0],
+        }))?
+        .deposit(1)
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+    let next_page: Page<SaleJson, SearchCursor> = market
+        .view(
+            &worker,
+            "get_sales",
+            serde_json::json!({ "cursor": first_page.next_cursor, "limit": 2 })
+                .to_string()
+                .into_bytes(),
+        )
+        .await?
+        .json()?;
+    let next_tokens: Vec<String> = next_page
+        .items
+        .iter()
+        .map(|sale| sale.token_id.clone())
+        .collect();
+    assert_eq!(next_tokens, tokens_series2);
+    assert_eq!(next_page.next_cursor, None);
+    Ok(())
+}
+
+/*
+    - Filters the sales by the contract, currency and price range
+    - Sorts by price in both directions and by creation time
+    - Splits the result into pages with `next_cursor`
+    - A relisted sale is found once at its new price, the search works after it is removed
+*/
+#[tokio::test]
+async fn search_sales() -> anyhow::Result<()> {
+    let worker = workspaces::sandbox();
+    let owner = worker.root_account();
+    let nft = init_nft(&worker, owner.id()).await?;
+    let market = init_market(&worker, worker.root_account().id(), vec![nft.id()]).await?;
+    let user1 = create_subaccount(&worker, &owner, "user1").await?;
+
+    let series = create_series(&worker, nft.id().clone(), &user1, owner.id().clone()).await?;
+    deposit(&worker, market.id().clone(), &user1).await;
+    let prices = [300u128, 100, 400, 200];
+    let mut tokens = vec![];
+    for price in prices {
+        let token = mint_token(&worker, nft.id().clone(), &user1, user1.id(), &series).await?;
+        let sale_conditions = HashMap::from([("near".parse().unwrap(), price.into())]);
+        nft_approve(
+            &worker,
+            nft.id().clone(),
+            market.id().clone(),
+            &user1,
+            token.clone(),
+            sale_conditions,
+            series.clone(),
+        )
+        .await;
+        tokens.push(token);
+    }
+
+    let search = |sort: &str, cursor: Option<SearchCursor>, limit: u64| {
+        serde_json::json!({
+            "filter": {
+                "nft_contract_id": nft.id(),
+                "ft_token_id": "near",
+                "price_max": "300",
+            },
+            "sort": sort,
+            "cursor": cursor,
+            "limit": limit,
+        })
+        .to_string()
+        .into_bytes()
+    };
+
+    let page: Page<SaleJson, SearchCursor> = market
+        .view(&worker, "search_sales", search("PriceAsc", None, 2))
+        .await?
+        .json()?;
+    let found: Vec<String> = page.items.iter().map(|sale| sale.token_id.clone()).collect();
+    assert_eq!(found, vec![tokens[1].clone(), tokens[3].clone()]);
+    assert!(page.next_cursor.is_some());
+    let page: Page<SaleJson, SearchCursor> = market
+        .view(&worker, "search_sales", search("PriceAsc", page.next_cursor, 2))
+        .await?
+        .json()?;
+    let found: Vec<String> = page.items.iter().map(|sale| sale.token_id.clone()).collect();
+    assert_eq!(found, vec![tokens[0].clone()]);
+    assert!(page.next_cursor.is_none());
+
+    let page: Page<SaleJson, SearchCursor> = market
+        .view(&worker, "search_sales", search("PriceDesc", None, 10))
+        .await?
+        .json()?;
+    let found: Vec<String> = page.items.iter().map(|sale| sale.token_id.clone()).collect();
+    assert_eq!(
+        found,
+        vec![tokens[0].clone(), tokens[3].clone(), tokens[1].clone()]
+    );
+
+    // without the price filter all sales are found, the newest first
+    let page: Page<SaleJson, SearchCursor> = market
+        .view(
+            &worker,
+            "search_sales",
+            serde_json::json!({ "filter": {} }).to_string().into_bytes(),
+        )
+        .await?
+        .json()?;
+    let found: Vec<String> = page.items.iter().map(|sale| sale.token_id.clone()).collect();
+    let newest_first: Vec<String> = tokens.iter().rev().cloned().collect();
+    assert_eq!(found, newest_first);
+
+    // listing the token again replaces the sale in the search indices
+    nft_approve(
+        &worker,
+        nft.id().clone(),
+        market.id().clone(),
+        &user1,
+        tokens[1].clone(),
+        HashMap::from([("near".parse().unwrap(), 250.into())]),
+        series.clone(),
+    )
+    .await;
+    let page: Page<SaleJson, SearchCursor> = market
+        .view(&worker, "search_sales", search("PriceAsc", None, 10))
+        .await?
+        .json()?;
+    let found: Vec<String> = page.items.iter().map(|sale| sale.token_id.clone()).collect();
+    assert_eq!(
+        found,
+        vec![tokens[3].clone(), tokens[1].clone(), tokens[0].clone()]
+    );
+    let outcome = user1
+        .call(&worker, market.id().clone(), "remove_sale")
+        .args_json(serde_json::json!({
+            "nft_contract_id": nft.id(),
+            "token_id": tokens[1],
+        }))?
+        .deposit(1)
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+    let page: Page<SaleJson, SearchCursor> = market
+        .view(&worker, "search_sales", search("PriceAsc", None, 10))
+        .await?
+        .json()?;
+    let found: Vec<String> = page.items.iter().map(|sale| sale.token_id.clone()).collect();
+    assert_eq!(found, vec![tokens[3].clone(), tokens[0].clone()]);
+    Ok(())
+}
+
+/*
+    - Counts the active sales and finds the floor price of the contract and of the series
+    - A relisted sale is counted once, private sales are skipped for the floor price
+    - Records the volume, the number of sales, the last and the highest price of purchases
+*/
+#[tokio::test]
+async fn collection_stats() -> anyhow::Result<()> {
+    let worker = workspaces::sandbox();
+    let owner = worker.root_account();
+    let nft = init_nft(&worker, owner.id()).await?;
+    let market = init_market(&worker, worker.root_account().id(), vec![nft.id()]).await?;
+    let user1 = create_subaccount(&worker, &owner, "user1").await?;
+    let user2 = create_subaccount(&worker, &owner, "user2").await?;
+
+    let series = create_series(&worker, nft.id().clone(), &user1, owner.id().clone()).await?;
+    deposit(&worker, market.id().clone(), &user1).await;
+    let mut tokens = vec![];
+    for price in [10000u128, 20000] {
+        let token = mint_token(&worker, nft.id().clone(), &user1, user1.id(), &series).await?;
+        let sale_conditions = HashMap::from([("near".parse().unwrap(), price.into())]);
+        nft_approve(
+            &worker,
+            nft.id().clone(),
+            market.id().clone(),
+            &user1,
+            token.clone(),
+            sale_conditions,
+            series.clone(),
+        )
+        .await;
+        tokens.push(token);
+    }
+
+    let near: AccountId = "near".parse().unwrap();
+    let stats: CollectionStats = market
+        .view(
+            &worker,
+            "get_collection_stats",
+            serde_json::json!({ "nft_contract_id": nft.id(), "series_id": series })
+                .to_string()
+                .into_bytes(),
+        )
+        .await?
+        .json()?;
+    assert_eq!(stats.listed, U64(2));
+    assert_eq!(stats.floor_price.get(&near), Some(&U128(10000)));
+    assert!(stats.trades.is_empty());
+
+    // the cheapest token is sold, the floor goes up
+    let sale_conditions = HashMap::from([(near.clone(), U128(10000))]);
+    let price = price_with_fees(&worker, &market, sale_conditions).await?;
+    buy(
+        &worker,
+        nft.id().clone(),
+        market.id().clone(),
+        &user2,
+        tokens[0].clone(),
+        price,
+    )
+    .await;
+
+    let stats: CollectionStats = market
+        .view(
+            &worker,
+            "get_collection_stats",
+            serde_json::json!({ "nft_contract_id": nft.id(), "series_id": null })
+                .to_string()
+                .into_bytes(),
+        )
+        .await?
+        .json()?;
+    assert_eq!(stats.listed, U64(1));
+    assert_eq!(stats.floor_price.get(&near), Some(&U128(20000)));
+    let trades = stats.trades.get(&near).unwrap();
+    assert_eq!(trades.sales_count, U64(1));
+    assert_eq!(trades.volume, price);
+    assert_eq!(trades.last_price, price);
+    assert_eq!(trades.highest_price, price);
+
+    // a relisted token is counted once, a private sale doesn't set the floor
+    nft_approve(
+        &worker,
+        nft.id().clone(),
+        market.id().clone(),
+        &user1,
+        tokens[1].clone(),
+        HashMap::from([(near.clone(), U128(20000))]),
+        series.clone(),
+    )
+    .await;
+    let private_token = mint_token(&worker, nft.id().clone(), &user1, user1.id(), &series).await?;
+    let outcome = user1
+        .call(&worker, nft.id().clone(), "nft_approve")
+        .args_json(serde_json::json!({
+            "token_id": private_token,
+            "account_id": market.id(),
+            "msg": serde_json::json!(ArgsKind::Sale(SaleArgs {
+                sale_conditions: HashMap::from([(near.clone(), U128(5000))]),
+                token_type: Some(series.clone()),
+                start: None,
+                end: None,
+                origins: None,
+                allowed_buyers: Some(vec![user2.id().as_ref().parse().unwrap()]),
+                reserved_buyer: None,
+            }))
+            .to_string()
+        }))?
+        .deposit(parse_near!("1 N"))
+        .gas(parse_gas!("200 Tgas") as u64)
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+    let stats: CollectionStats = market
+        .view(
+            &worker,
+            "get_collection_stats",
+            serde_json::json!({ "nft_contract_id": nft.id(), "series_id": null })
+                .to_string()
+                .into_bytes(),
+        )
+        .await?
+        .json()?;
+    assert_eq!(stats.listed, U64(2));
+    assert_eq!(stats.floor_price.get(&near), Some(&U128(20000)));
+    Ok(())
+}
+
+/*
+    - Records a trade after the purchase with the price and the fees
+    - The trade is found by the token, by the buyer and by the seller
+*/
+#[tokio::test]
+async fn trade_history() -> anyhow::Result<()> {
+    let worker = workspaces::sandbox();
+    let owner = worker.root_account();
+    let nft = init_nft(&worker, owner.id()).await?;
+    let market = init_market(&worker, worker.root_account().id(), vec![nft.id()]).await?;
+    let user1 = create_subaccount(&worker, &owner, "user1").await?;
+    let user2 = create_subaccount(&worker, &owner, "user2").await?;
+
+    let series = create_series(&worker, nft.id().clone(), &user1, owner.id().clone()).await?;
+    let token = mint_token(&worker, nft.id().clone(), &user1, user1.id(), &series).await?;
+    deposit(&worker, market.id().clone(), &user1).await;
+    let sale_conditions = HashMap::from([("near".parse().unwrap(), U128(10000))]);
+    nft_approve(
+        &worker,
+        nft.id().clone(),
+        market.id().clone(),
+        &user1,
+        token.clone(),
+        sale_conditions.clone(),
+        series.clone(),
+    )
+    .await;
+    let price = price_with_fees(&worker, &market, sale_conditions).await?;
+    buy(
+        &worker,
+        nft.id().clone(),
+        market.id().clone(),
+        &user2,
+        token.clone(),
+        price,
+    )
+    .await;
+
+    let by_token: Page<Trade> = market
+        .view(
+            &worker,
+            "get_trades_by_token",
+            serde_json::json!({ "nft_contract_id": nft.id(), "token_id": token })
+                .to_string()
+                .into_bytes(),
+        )
+        .await?
+        .json()?;
+    assert_eq!(by_token.items.len(), 1);
+    let trade = &by_token.items[0];
+    assert_eq!(trade.seller_id.as_str(), user1.id().as_ref());
+    assert_eq!(trade.buyer_id.as_str(), user2.id().as_ref());
+    assert_eq!(trade.price, price);
+    // the protocol fee is paid by both sides
+    assert_eq!(trade.fees, U128(600));
+
+    for (method, account) in [("get_trades_by_buyer", &user2), ("get_trades_by_seller", &user1)] {
+        let trades: Page<Trade> = market
+            .view(
+                &worker,
+                method,
+                serde_json::json!({ "account_id": account.id() })
+                    .to_string()
+                    .into_bytes(),
+            )
+            .await?
+            .json()?;
+        assert_eq!(&trades.items, &by_token.items);
+    }
+    let other: Page<Trade> = market
+        .view(
+            &worker,
+            "get_trades_by_buyer",
+            serde_json::json!({ "account_id": user1.id() })
+                .to_string()
+                .into_bytes(),
+        )
+        .await?
+        .json()?;
+    assert!(other.items.is_empty());
     Ok(())
 }
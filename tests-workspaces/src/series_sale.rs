@@ -0,0 +1,533 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use near_units::{parse_gas, parse_near};
+use nft_bid_market::{
+    ActivePhase, Page, RaffleJson, RaffleStatus, SeriesSaleJson, Trade, WalletAllocation,
+    RAFFLE_MINT_BATCH, STORAGE_FOR_MINT,
+};
+use nft_contract::common::{U128, U64};
+
+use crate::utils::{
+    check_outcome_fail, check_outcome_success, create_series, create_subaccount, deposit,
+    init_market, init_nft,
+};
+
+/*
+    - Only the series owner can approve the market to sell the series
+    - The series sale requires the storage deposit
+    - The series owner can't buy the series
+    - The deposit should cover the price with fees and `STORAGE_FOR_MINT`
+    - The market mints the token to the buyer and pays the series owner
+    - The series sale is removed when all the copies are sold
+*/
+#[tokio::test]
+async fn series_sale_positive() -> anyhow::Result<()> {
+    let worker = workspaces::sandbox();
+    let owner = worker.root_account();
+    let nft = init_nft(&worker, owner.id()).await?;
+    let market = init_market(&worker, worker.root_account().id(), vec![nft.id()]).await?;
+    let user1 = create_subaccount(&worker, &owner, "user1").await?;
+    let user2 = create_subaccount(&worker, &owner, "user2").await?;
+    let series = create_series(&worker, nft.id().clone(), &user1, owner.id().clone()).await?;
+    let approve_args = serde_json::json!({
+        "token_series_id": series,
+        "sale_conditions": { "near": "1000" },
+        "copies": 2,
+        "approved_market_id": market.id(),
+    });
+
+    let outcome = user2
+        .call(&worker, nft.id().clone(), "nft_series_market_approve")
+        .args_json(approve_args.clone())?
+        .deposit(parse_near!("0.01 N"))
+        .gas(parse_gas!("100 Tgas") as u64)
+        .transact()
+        .await?;
+    check_outcome_fail(outcome.status, "Not token owner").await;
+    let outcome = user1
+        .call(&worker, nft.id().clone(), "nft_series_market_approve")
+        .args_json(approve_args.clone())?
+        .deposit(parse_near!("0.01 N"))
+        .gas(parse_gas!("100 Tgas") as u64)
+        .transact()
+        .await?;
+    check_outcome_fail(outcome.status, "Insufficient storage paid").await;
+    deposit(&worker, market.id().clone(), &user1).await;
+    let outcome = user1
+        .call(&worker, nft.id().clone(), "nft_series_market_approve")
+        .args_json(approve_args)?
+        .deposit(parse_near!("0.01 N"))
+        .gas(parse_gas!("100 Tgas") as u64)
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+
+    let series_args = serde_json::json!({ "nft_contract_id": nft.id(), "series_id": series })
+        .to_string()
+        .into_bytes();
+    let series_sale: Option<SeriesSaleJson> = market
+        .view(&worker, "get_series_sale", series_args.clone())
+        .await?
+        .json()?;
+    let series_sale = series_sale.expect("The series sale should be created");
+    assert_eq!(series_sale.owner_id.as_str(), user1.id().as_ref());
+    assert_eq!(series_sale.copies, U64(2));
+    assert_eq!(series_sale.sold, U64(0));
+
+    let price = 1030 + STORAGE_FOR_MINT;
+    let buy_args = serde_json::json!({ "nft_contract_id": nft.id(), "series_id": series });
+    let outcome = user1
+        .call(&worker, market.id().clone(), "buy_series")
+        .args_json(buy_args.clone())?
+        .deposit(price)
+        .gas(parse_gas!("200 Tgas") as u64)
+        .transact()
+        .await?;
+    check_outcome_fail(outcome.status, "Cannot buy your own sale.").await;
+    let outcome = user2
+        .call(&worker, market.id().clone(), "buy_series")
+        .args_json(buy_args.clone())?
+        .deposit(price - 1)
+        .gas(parse_gas!("200 Tgas") as u64)
+        .transact()
+        .await?;
+    check_outcome_fail(outcome.status, "Attached deposit must be at least").await;
+    let outcome = user2
+        .call(&worker, market.id().clone(), "buy_series")
+        .args_json(buy_args.clone())?
+        .deposit(price)
+        .gas(parse_gas!("200 Tgas") as u64)
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+
+    let token_id = format!("{}:1", series);
+    let token: serde_json::Value = nft
+        .view(
+            &worker,
+            "nft_token",
+            serde_json::json!({ "token_id": token_id })
+                .to_string()
+                .into_bytes(),
+        )
+        .await?
+        .json()?;
+    assert_eq!(token["owner_id"].as_str(), Some(user2.id().as_ref()));
+    let series_sale: Option<SeriesSaleJson> = market
+        .view(&worker, "get_series_sale", series_args.clone())
+        .await?
+        .json()?;
+    let series_sale = series_sale.unwrap();
+    assert_eq!(series_sale.copies, U64(1));
+    assert_eq!(series_sale.minting, U64(0));
+    assert_eq!(series_sale.sold, U64(1));
+
+    let trades: Page<Trade> = market
+        .view(
+            &worker,
+            "get_trades_by_token",
+            serde_json::json!({ "nft_contract_id": nft.id(), "token_id": token_id })
+                .to_string()
+                .into_bytes(),
+        )
+        .await?
+        .json()?;
+    let trade = &trades.items[0];
+    assert_eq!(trade.seller_id.as_str(), user1.id().as_ref());
+    assert_eq!(trade.price, U128(1030));
+    assert_eq!(trade.fees, U128(60));
+
+    let outcome = user2
+        .call(&worker, market.id().clone(), "buy_series")
+        .args_json(buy_args.clone())?
+        .deposit(price)
+        .gas(parse_gas!("200 Tgas") as u64)
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+    let series_sales: Page<SeriesSaleJson, String> = market
+        .view(
+            &worker,
+            "get_series_sales",
+            serde_json::json!({}).to_string().into_bytes(),
+        )
+        .await?
+        .json()?;
+    assert!(series_sales.items.is_empty());
+    let outcome = user2
+        .call(&worker, market.id().clone(), "buy_series")
+        .args_json(buy_args)?
+        .deposit(price)
+        .gas(parse_gas!("200 Tgas") as u64)
+        .transact()
+        .await?;
+    check_outcome_fail(outcome.status, "No series sale").await;
+    Ok(())
+}
+
+/*
+    - Only the series owner can set the phases
+    - Only the allowlisted accounts can buy during the presale, at the price of the phase
+    - The wallet can't buy more than the limit of the phase
+    - The phase ids should be unique, reordering the phases keeps the purchases of the wallets
+    - The next phase starts when the presale ends, with its own price and limit
+*/
+#[tokio::test]
+async fn series_sale_phases() -> anyhow::Result<()> {
+    let worker = workspaces::sandbox();
+    let owner = worker.root_account();
+    let nft = init_nft(&worker, owner.id()).await?;
+    let market = init_market(&worker, worker.root_account().id(), vec![nft.id()]).await?;
+    let user1 = create_subaccount(&worker, &owner, "user1").await?;
+    let user2 = create_subaccount(&worker, &owner, "user2").await?;
+    let user3 = create_subaccount(&worker, &owner, "user3").await?;
+    let series = create_series(&worker, nft.id().clone(), &user1, owner.id().clone()).await?;
+    deposit(&worker, market.id().clone(), &user1).await;
+    let outcome = user1
+        .call(&worker, nft.id().clone(), "nft_series_market_approve")
+        .args_json(serde_json::json!({
+            "token_series_id": series,
+            "sale_conditions": { "near": "1000" },
+            "copies": 5,
+            "approved_market_id": market.id(),
+        }))?
+        .deposit(parse_near!("0.01 N"))
+        .gas(parse_gas!("100 Tgas") as u64)
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+
+    let since_the_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards");
+    let waiting_time = Duration::from_secs(15);
+    let presale_end = (since_the_epoch + waiting_time).as_nanos() as u64;
+    let presale = serde_json::json!({
+        "id": 1,
+        "end": presale_end.to_string(),
+        "price": "500",
+        "allowlist": [user2.id()],
+        "wallet_limit": 1,
+    });
+    let public_sale = serde_json::json!({
+        "id": 2,
+        "start": presale_end.to_string(),
+        "wallet_limit": 2,
+    });
+    let phases_args = serde_json::json!({
+        "nft_contract_id": nft.id(),
+        "series_id": series,
+        "phases": [presale.clone(), public_sale.clone()],
+    });
+    let outcome = user2
+        .call(&worker, market.id().clone(), "set_series_phases")
+        .args_json(phases_args.clone())?
+        .deposit(1)
+        .transact()
+        .await?;
+    check_outcome_fail(outcome.status, "Only the series owner can set the phases").await;
+    let outcome = user1
+        .call(&worker, market.id().clone(), "set_series_phases")
+        .args_json(phases_args)?
+        .deposit(1)
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+
+    let series_args = serde_json::json!({ "nft_contract_id": nft.id(), "series_id": series })
+        .to_string()
+        .into_bytes();
+    let phase: Option<ActivePhase> = market
+        .view(&worker, "get_series_phase", series_args.clone())
+        .await?
+        .json()?;
+    assert_eq!(phase.expect("The presale should be active").index, 0);
+    let allocation: WalletAllocation = market
+        .view(
+            &worker,
+            "get_series_allocation",
+            serde_json::json!({
+                "nft_contract_id": nft.id(),
+                "series_id": series,
+                "account_id": user3.id(),
+            })
+            .to_string()
+            .into_bytes(),
+        )
+        .await?
+        .json()?;
+    assert!(!allocation.allowed);
+    assert_eq!(allocation.remaining, U64(0));
+
+    let buy_args = serde_json::json!({ "nft_contract_id": nft.id(), "series_id": series });
+    let outcome = user3
+        .call(&worker, market.id().clone(), "buy_series")
+        .args_json(buy_args.clone())?
+        .deposit(1030 + STORAGE_FOR_MINT)
+        .gas(parse_gas!("200 Tgas") as u64)
+        .transact()
+        .await?;
+    check_outcome_fail(
+        outcome.status,
+        "The buyer is not on the allowlist of the phase",
+    )
+    .await;
+    let outcome = user2
+        .call(&worker, market.id().clone(), "buy_series")
+        .args_json(buy_args.clone())?
+        .deposit(515 + STORAGE_FOR_MINT)
+        .gas(parse_gas!("200 Tgas") as u64)
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+    let outcome = user2
+        .call(&worker, market.id().clone(), "buy_series")
+        .args_json(buy_args.clone())?
+        .deposit(515 + STORAGE_FOR_MINT)
+        .gas(parse_gas!("200 Tgas") as u64)
+        .transact()
+        .await?;
+    check_outcome_fail(outcome.status, "The wallet limit of the phase is reached").await;
+
+    let mut duplicate = public_sale.clone();
+    duplicate["id"] = serde_json::json!(1);
+    let outcome = user1
+        .call(&worker, market.id().clone(), "set_series_phases")
+        .args_json(serde_json::json!({
+            "nft_contract_id": nft.id(),
+            "series_id": series,
+            "phases": [duplicate, presale.clone()],
+        }))?
+        .deposit(1)
+        .transact()
+        .await?;
+    check_outcome_fail(outcome.status, "The phase ids should be unique").await;
+    let outcome = user1
+        .call(&worker, market.id().clone(), "set_series_phases")
+        .args_json(serde_json::json!({
+            "nft_contract_id": nft.id(),
+            "series_id": series,
+            "phases": [public_sale, presale],
+        }))?
+        .deposit(1)
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+    let outcome = user2
+        .call(&worker, market.id().clone(), "buy_series")
+        .args_json(buy_args.clone())?
+        .deposit(515 + STORAGE_FOR_MINT)
+        .gas(parse_gas!("200 Tgas") as u64)
+        .transact()
+        .await?;
+    check_outcome_fail(outcome.status, "The wallet limit of the phase is reached").await;
+
+    tokio::time::sleep(waiting_time).await;
+    let phase: Option<ActivePhase> = market
+        .view(&worker, "get_series_phase", series_args)
+        .await?
+        .json()?;
+    let phase = phase.expect("The public sale should be active");
+    assert_eq!(phase.index, 0);
+    assert_eq!(phase.phase.id, 2);
+    let outcome = user3
+        .call(&worker, market.id().clone(), "buy_series")
+        .args_json(buy_args.clone())?
+        .deposit(515 + STORAGE_FOR_MINT)
+        .gas(parse_gas!("200 Tgas") as u64)
+        .transact()
+        .await?;
+    check_outcome_fail(outcome.status, "Attached deposit must be at least").await;
+    let outcome = user3
+        .call(&worker, market.id().clone(), "buy_series")
+        .args_json(buy_args)?
+        .deposit(1030 + STORAGE_FOR_MINT)
+        .gas(parse_gas!("200 Tgas") as u64)
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+    let allocation: WalletAllocation = market
+        .view(
+            &worker,
+            "get_series_allocation",
+            serde_json::json!({
+                "nft_contract_id": nft.id(),
+                "series_id": series,
+                "account_id": user3.id(),
+            })
+            .to_string()
+            .into_bytes(),
+        )
+        .await?
+        .json()?;
+    assert_eq!(allocation.phase, Some(2));
+    assert_eq!(allocation.bought, U64(1));
+    assert_eq!(allocation.remaining, U64(1));
+    Ok(())
+}
+
+/*
+    - Only the series owner can start a raffle with the copies of the series sale
+    - Each account can enter the raffle once, escrowing the price with fees and `STORAGE_FOR_MINT`
+    - The winners can't be drawn before the entry window ends
+    - Anyone can draw, the winners get the minted tokens in batches of `RAFFLE_MINT_BATCH`
+    - The series sale is kept until the raffled copies are minted, even with no copies left
+    - The finished raffle is removed
+*/
+#[tokio::test]
+async fn series_raffle() -> anyhow::Result<()> {
+    let worker = workspaces::sandbox();
+    let owner = worker.root_account();
+    let nft = init_nft(&worker, owner.id()).await?;
+    let market = init_market(&worker, worker.root_account().id(), vec![nft.id()]).await?;
+    let user1 = create_subaccount(&worker, &owner, "user1").await?;
+    let user2 = create_subaccount(&worker, &owner, "user2").await?;
+    let user3 = create_subaccount(&worker, &owner, "user3").await?;
+    let user4 = create_subaccount(&worker, &owner, "user4").await?;
+    let user5 = create_subaccount(&worker, &owner, "user5").await?;
+    let series = create_series(&worker, nft.id().clone(), &user1, owner.id().clone()).await?;
+    deposit(&worker, market.id().clone(), &user1).await;
+    let outcome = user1
+        .call(&worker, nft.id().clone(), "nft_series_market_approve")
+        .args_json(serde_json::json!({
+            "token_series_id": series,
+            "sale_conditions": { "near": "1000" },
+            "copies": 3,
+            "approved_market_id": market.id(),
+        }))?
+        .deposit(parse_near!("0.01 N"))
+        .gas(parse_gas!("100 Tgas") as u64)
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+
+    let since_the_epoch = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards");
+    let waiting_time = Duration::from_secs(15);
+    let raffle_args = serde_json::json!({
+        "nft_contract_id": nft.id(),
+        "series_id": series,
+        "price": "1000",
+        "copies": 3,
+        "end": (since_the_epoch + waiting_time).as_nanos().to_string(),
+    });
+    let outcome = user2
+        .call(&worker, market.id().clone(), "start_raffle")
+        .args_json(raffle_args.clone())?
+        .deposit(1)
+        .transact()
+        .await?;
+    check_outcome_fail(outcome.status, "Only the series owner can start a raffle").await;
+    let raffle_id: U64 = user1
+        .call(&worker, market.id().clone(), "start_raffle")
+        .args_json(raffle_args)?
+        .deposit(1)
+        .transact()
+        .await?
+        .json()?;
+
+    let entry = 1030 + STORAGE_FOR_MINT;
+    let raffle_id_args = serde_json::json!({ "raffle_id": raffle_id });
+    for user in [&user2, &user3, &user4, &user5] {
+        let outcome = user
+            .call(&worker, market.id().clone(), "enter_raffle")
+            .args_json(raffle_id_args.clone())?
+            .deposit(entry)
+            .transact()
+            .await?;
+        check_outcome_success(outcome.status).await;
+    }
+    let outcome = user2
+        .call(&worker, market.id().clone(), "enter_raffle")
+        .args_json(raffle_id_args.clone())?
+        .deposit(entry)
+        .transact()
+        .await?;
+    check_outcome_fail(outcome.status, "Already entered").await;
+    let outcome = user4
+        .call(&worker, market.id().clone(), "draw")
+        .args_json(raffle_id_args.clone())?
+        .gas(parse_gas!("300 Tgas") as u64)
+        .transact()
+        .await?;
+    check_outcome_fail(outcome.status, "The raffle is not over yet").await;
+
+    tokio::time::sleep(waiting_time).await;
+    let remaining: U64 = user4
+        .call(&worker, market.id().clone(), "draw")
+        .args_json(raffle_id_args.clone())?
+        .gas(parse_gas!("300 Tgas") as u64)
+        .transact()
+        .await?
+        .json()?;
+    assert_eq!(remaining, U64(3 - RAFFLE_MINT_BATCH as u64));
+    let raffle: Option<RaffleJson> = market
+        .view(
+            &worker,
+            "get_raffle",
+            raffle_id_args.to_string().into_bytes(),
+        )
+        .await?
+        .json()?;
+    let raffle = raffle.unwrap();
+    assert_eq!(raffle.entries, U64(4));
+    assert_eq!(raffle.status, RaffleStatus::Minting);
+    let winners: Vec<String> = market
+        .view(
+            &worker,
+            "get_raffle_winners",
+            raffle_id_args.to_string().into_bytes(),
+        )
+        .await?
+        .json()?;
+    assert_eq!(winners.len(), 3);
+    let series_args = serde_json::json!({ "nft_contract_id": nft.id(), "series_id": series })
+        .to_string()
+        .into_bytes();
+    let series_sale: Option<SeriesSaleJson> = market
+        .view(&worker, "get_series_sale", series_args.clone())
+        .await?
+        .json()?;
+    let series_sale = series_sale.expect("The series sale is removed before the raffle is minted");
+    assert_eq!(series_sale.copies, U64(0));
+    assert_eq!(series_sale.raffled, U64(3 - RAFFLE_MINT_BATCH as u64));
+
+    let remaining: U64 = user4
+        .call(&worker, market.id().clone(), "draw")
+        .args_json(raffle_id_args.clone())?
+        .gas(parse_gas!("300 Tgas") as u64)
+        .transact()
+        .await?
+        .json()?;
+    assert_eq!(remaining, U64(0));
+    let raffle: Option<RaffleJson> = market
+        .view(
+            &worker,
+            "get_raffle",
+            raffle_id_args.to_string().into_bytes(),
+        )
+        .await?
+        .json()?;
+    assert!(raffle.is_none());
+    let series_sale: Option<SeriesSaleJson> = market
+        .view(&worker, "get_series_sale", series_args)
+        .await?
+        .json()?;
+    assert!(series_sale.is_none());
+    for user in [&user2, &user3, &user4, &user5] {
+        let supply: U128 = nft
+            .view(
+                &worker,
+                "nft_supply_for_owner",
+                serde_json::json!({ "account_id": user.id() })
+                    .to_string()
+                    .into_bytes(),
+            )
+            .await?
+            .json()?;
+        let won = winners.iter().any(|winner| winner == user.id().as_ref());
+        assert_eq!(supply, U128(won as u128));
+    }
+    Ok(())
+}
@@ -0,0 +1,956 @@
+use std::collections::HashMap;
+
+use near_units::{parse_gas, parse_near};
+use nft_bid_market::{
+    ArgsKind, OriginJson, Page, PayoutMode, PurchaseQuote, Royalty, SaleArgs, SaleJson, Settlement,
+    Trade, MAX_PAYOUT_LEN_LIMIT, SETTLEMENT_BATCH_SIZE,
+};
+use workspaces::AccountId;
+use nft_contract::common::{U128, U64};
+
+use crate::utils::{
+    buy, check_outcome_fail, check_outcome_success, create_subaccount, deposit, init_market,
+    init_nft_failing, init_nft_reference, offer, price_with_fees,
+};
+
+/*
+    - Only the market owner can set the payout mode
+    - In the standard mode the market pays the fees itself and passes the rest as `balance`
+    - The royalty of the NFT contract is calculated from the price without fees
+    - The origin fee is added to the earnings of the origin
+*/
+#[tokio::test]
+async fn standard_payout() -> anyhow::Result<()> {
+    let worker = workspaces::sandbox();
+    let owner = worker.root_account();
+    let user1 = create_subaccount(&worker, &owner, "user1").await?;
+    let user2 = create_subaccount(&worker, &owner, "user2").await?;
+    let artist = create_subaccount(&worker, &owner, "artist").await?;
+    let origin = create_subaccount(&worker, &owner, "origin").await?;
+    let nft = init_nft_reference(&worker, owner.id(), HashMap::from([(artist.id(), 1000)])).await?;
+    let market = init_market(&worker, worker.root_account().id(), vec![nft.id()]).await?;
+    owner
+        .call(&worker, market.id().clone(), "register_origin")
+        .args_json(serde_json::json!({ "origin_id": origin.id(), "max_fee": 100 }))?
+        .transact()
+        .await?;
+
+    let outcome = user1
+        .call(&worker, market.id().clone(), "set_payout_mode")
+        .args_json(serde_json::json!({
+            "nft_contract_id": nft.id(),
+            "payout_mode": PayoutMode::Standard,
+        }))?
+        .transact()
+        .await?;
+    check_outcome_fail(
+        outcome.status,
+        "Only the market owner can set the payout mode",
+    )
+    .await;
+    let outcome = owner
+        .call(&worker, market.id().clone(), "set_payout_mode")
+        .args_json(serde_json::json!({
+            "nft_contract_id": nft.id(),
+            "payout_mode": PayoutMode::Standard,
+        }))?
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+    let payout_mode: PayoutMode = market
+        .view(
+            &worker,
+            "get_payout_mode",
+            serde_json::json!({ "nft_contract_id": nft.id() })
+                .to_string()
+                .into_bytes(),
+        )
+        .await?
+        .json()?;
+    assert_eq!(payout_mode, PayoutMode::Standard);
+
+    let token_id = "0".to_string();
+    owner
+        .call(&worker, nft.id().clone(), "nft_mint")
+        .args_json(serde_json::json!({
+            "token_id": token_id,
+            "receiver_id": user1.id(),
+            "token_metadata": { "title": "some title" },
+        }))?
+        .deposit(parse_near!("0.01 N"))
+        .transact()
+        .await?;
+    deposit(&worker, market.id().clone(), &user1).await;
+    let sale_conditions = HashMap::from([("near".parse().unwrap(), U128(10000))]);
+    let outcome = user1
+        .call(&worker, nft.id().clone(), "nft_approve")
+        .args_json(serde_json::json!({
+            "token_id": token_id,
+            "account_id": market.id(),
+            "msg": serde_json::json!(ArgsKind::Sale(SaleArgs {
+                sale_conditions: sale_conditions.clone(),
+                token_type: None,
+                start: None,
+                end: None,
+                origins: Some(HashMap::from([(origin.id().as_ref().parse().unwrap(), 100)])),
+                allowed_buyers: None,
+                reserved_buyer: None,
+            })).to_string()
+        }))?
+        .deposit(parse_near!("1 N"))
+        .gas(parse_gas!("200 Tgas") as u64)
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+
+    let price = price_with_fees(&worker, &market, sale_conditions).await?;
+    buy(
+        &worker,
+        nft.id().clone(),
+        market.id().clone(),
+        &user2,
+        token_id.clone(),
+        price,
+    )
+    .await;
+
+    let token: serde_json::Value = nft
+        .view(
+            &worker,
+            "nft_token",
+            serde_json::json!({ "token_id": token_id })
+                .to_string()
+                .into_bytes(),
+        )
+        .await?
+        .json()?;
+    assert_eq!(token["owner_id"].as_str(), Some(user2.id().as_ref()));
+
+    let trades: Page<Trade> = market
+        .view(
+            &worker,
+            "get_trades_by_token",
+            serde_json::json!({ "nft_contract_id": nft.id(), "token_id": token_id })
+                .to_string()
+                .into_bytes(),
+        )
+        .await?
+        .json()?;
+    let trade = &trades.items[0];
+    assert_eq!(trade.price, U128(10300));
+    // protocol fee of both sides and the seller origin
+    assert_eq!(trade.fees, U128(700));
+    // 10% of the price without fees
+    assert_eq!(trade.royalties, U128(960));
+
+    let origin_stats: Option<OriginJson> = market
+        .view(
+            &worker,
+            "get_origin",
+            serde_json::json!({ "origin_id": origin.id() })
+                .to_string()
+                .into_bytes(),
+        )
+        .await?
+        .json()?;
+    let origin_stats = origin_stats.expect("The origin should be registered");
+    assert_eq!(
+        origin_stats.earnings,
+        HashMap::from([("near".parse().unwrap(), U128(100))])
+    );
+    assert_eq!(origin_stats.trades, U64(1));
+    Ok(())
+}
+
+/*
+    - Only the market owner, the NFT contract or the verified creator can set the royalty
+    - Only the market owner can verify the creator
+    - The royalty can't exceed the cap
+    - In the transfer mode the token is transferred with `nft_transfer`
+    - The market pays the fees, the royalty from its registry and the rest to the seller
+*/
+#[tokio::test]
+async fn transfer_payout() -> anyhow::Result<()> {
+    let worker = workspaces::sandbox();
+    let owner = worker.root_account();
+    let user1 = create_subaccount(&worker, &owner, "user1").await?;
+    let user2 = create_subaccount(&worker, &owner, "user2").await?;
+    let artist = create_subaccount(&worker, &owner, "artist").await?;
+    let origin = create_subaccount(&worker, &owner, "origin").await?;
+    let nft = init_nft_reference(&worker, owner.id(), HashMap::new()).await?;
+    let market = init_market(&worker, worker.root_account().id(), vec![nft.id()]).await?;
+    owner
+        .call(&worker, market.id().clone(), "register_origin")
+        .args_json(serde_json::json!({ "origin_id": origin.id(), "max_fee": 100 }))?
+        .transact()
+        .await?;
+
+    let outcome = artist
+        .call(&worker, market.id().clone(), "set_collection_royalty")
+        .args_json(serde_json::json!({
+            "nft_contract_id": nft.id(),
+            "royalty": { artist.id().as_ref(): 500 },
+        }))?
+        .transact()
+        .await?;
+    check_outcome_fail(
+        outcome.status,
+        "Only the market owner, the NFT contract or the verified creator can set the royalty",
+    )
+    .await;
+    let outcome = artist
+        .call(&worker, market.id().clone(), "set_collection_creator")
+        .args_json(serde_json::json!({
+            "nft_contract_id": nft.id(),
+            "creator_id": artist.id(),
+        }))?
+        .transact()
+        .await?;
+    check_outcome_fail(outcome.status, "Only the market owner can verify the creator").await;
+    let outcome = owner
+        .call(&worker, market.id().clone(), "set_collection_creator")
+        .args_json(serde_json::json!({
+            "nft_contract_id": nft.id(),
+            "creator_id": artist.id(),
+        }))?
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+    let outcome = artist
+        .call(&worker, market.id().clone(), "set_collection_royalty")
+        .args_json(serde_json::json!({
+            "nft_contract_id": nft.id(),
+            "royalty": { artist.id().as_ref(): 5001 },
+        }))?
+        .transact()
+        .await?;
+    check_outcome_fail(outcome.status, "maximum royalty cap exceeded 5000").await;
+    let outcome = artist
+        .call(&worker, market.id().clone(), "set_collection_royalty")
+        .args_json(serde_json::json!({
+            "nft_contract_id": nft.id(),
+            "royalty": { artist.id().as_ref(): 500 },
+        }))?
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+    let royalty: Royalty = market
+        .view(
+            &worker,
+            "get_collection_royalty",
+            serde_json::json!({ "nft_contract_id": nft.id() })
+                .to_string()
+                .into_bytes(),
+        )
+        .await?
+        .json()?;
+    assert_eq!(
+        royalty,
+        HashMap::from([(artist.id().as_ref().parse().unwrap(), 500)])
+    );
+
+    let outcome = owner
+        .call(&worker, market.id().clone(), "set_payout_mode")
+        .args_json(serde_json::json!({
+            "nft_contract_id": nft.id(),
+            "payout_mode": PayoutMode::Transfer,
+        }))?
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+
+    let token_id = "0".to_string();
+    owner
+        .call(&worker, nft.id().clone(), "nft_mint")
+        .args_json(serde_json::json!({
+            "token_id": token_id,
+            "receiver_id": user1.id(),
+            "token_metadata": { "title": "some title" },
+        }))?
+        .deposit(parse_near!("0.01 N"))
+        .transact()
+        .await?;
+    deposit(&worker, market.id().clone(), &user1).await;
+    let sale_conditions = HashMap::from([("near".parse().unwrap(), U128(10000))]);
+    let outcome = user1
+        .call(&worker, nft.id().clone(), "nft_approve")
+        .args_json(serde_json::json!({
+            "token_id": token_id,
+            "account_id": market.id(),
+            "msg": serde_json::json!(ArgsKind::Sale(SaleArgs {
+                sale_conditions: sale_conditions.clone(),
+                token_type: None,
+                start: None,
+                end: None,
+                origins: Some(HashMap::from([(origin.id().as_ref().parse().unwrap(), 100)])),
+                allowed_buyers: None,
+                reserved_buyer: None,
+            })).to_string()
+        }))?
+        .deposit(parse_near!("1 N"))
+        .gas(parse_gas!("200 Tgas") as u64)
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+
+    let price = price_with_fees(&worker, &market, sale_conditions).await?;
+    buy(
+        &worker,
+        nft.id().clone(),
+        market.id().clone(),
+        &user2,
+        token_id.clone(),
+        price,
+    )
+    .await;
+
+    let token: serde_json::Value = nft
+        .view(
+            &worker,
+            "nft_token",
+            serde_json::json!({ "token_id": token_id })
+                .to_string()
+                .into_bytes(),
+        )
+        .await?
+        .json()?;
+    assert_eq!(token["owner_id"].as_str(), Some(user2.id().as_ref()));
+
+    let trades: Page<Trade> = market
+        .view(
+            &worker,
+            "get_trades_by_token",
+            serde_json::json!({ "nft_contract_id": nft.id(), "token_id": token_id })
+                .to_string()
+                .into_bytes(),
+        )
+        .await?
+        .json()?;
+    let trade = &trades.items[0];
+    assert_eq!(trade.price, U128(10300));
+    assert_eq!(trade.fees, U128(700));
+    // 5% of the price without fees from the market-side royalty
+    assert_eq!(trade.royalties, U128(480));
+    Ok(())
+}
+
+/*
+    - Only the market owner can set the payout length
+    - Payouts longer than the batch are settled partially, the rest is kept as a settlement
+    - Anyone can do the remaining transfers with `settle`
+*/
+#[tokio::test]
+async fn staged_settlement() -> anyhow::Result<()> {
+    let worker = workspaces::sandbox();
+    let owner = worker.root_account();
+    let user1 = create_subaccount(&worker, &owner, "user1").await?;
+    let user2 = create_subaccount(&worker, &owner, "user2").await?;
+    let mut royalty = HashMap::new();
+    let mut artists = vec![];
+    for i in 0..12 {
+        artists.push(create_subaccount(&worker, &owner, &format!("artist{}", i)).await?);
+    }
+    for artist in artists.iter() {
+        royalty.insert(artist.id(), 100);
+    }
+    let nft = init_nft_reference(&worker, owner.id(), royalty).await?;
+    let market = init_market(&worker, worker.root_account().id(), vec![nft.id()]).await?;
+
+    let outcome = user1
+        .call(&worker, market.id().clone(), "set_max_payout_len")
+        .args_json(serde_json::json!({ "max_payout_len": 20 }))?
+        .transact()
+        .await?;
+    check_outcome_fail(
+        outcome.status,
+        "Only the market owner can set the payout length",
+    )
+    .await;
+    let outcome = owner
+        .call(&worker, market.id().clone(), "set_max_payout_len")
+        .args_json(serde_json::json!({ "max_payout_len": 20 }))?
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+    let outcome = owner
+        .call(&worker, market.id().clone(), "set_payout_mode")
+        .args_json(serde_json::json!({
+            "nft_contract_id": nft.id(),
+            "payout_mode": PayoutMode::Standard,
+        }))?
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+
+    let token_id = "0".to_string();
+    owner
+        .call(&worker, nft.id().clone(), "nft_mint")
+        .args_json(serde_json::json!({
+            "token_id": token_id,
+            "receiver_id": user1.id(),
+            "token_metadata": { "title": "some title" },
+        }))?
+        .deposit(parse_near!("0.01 N"))
+        .transact()
+        .await?;
+    deposit(&worker, market.id().clone(), &user1).await;
+    let sale_conditions = HashMap::from([("near".parse().unwrap(), U128(10000))]);
+    let outcome = user1
+        .call(&worker, nft.id().clone(), "nft_approve")
+        .args_json(serde_json::json!({
+            "token_id": token_id,
+            "account_id": market.id(),
+            "msg": serde_json::json!(ArgsKind::Sale(SaleArgs {
+                sale_conditions: sale_conditions.clone(),
+                token_type: None,
+                start: None,
+                end: None,
+                origins: None,
+                allowed_buyers: None,
+                reserved_buyer: None,
+            })).to_string()
+        }))?
+        .deposit(parse_near!("1 N"))
+        .gas(parse_gas!("200 Tgas") as u64)
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+
+    let price = price_with_fees(&worker, &market, sale_conditions).await?;
+    buy(
+        &worker,
+        nft.id().clone(),
+        market.id().clone(),
+        &user2,
+        token_id.clone(),
+        price,
+    )
+    .await;
+
+    let token: serde_json::Value = nft
+        .view(
+            &worker,
+            "nft_token",
+            serde_json::json!({ "token_id": token_id })
+                .to_string()
+                .into_bytes(),
+        )
+        .await?
+        .json()?;
+    assert_eq!(token["owner_id"].as_str(), Some(user2.id().as_ref()));
+
+    // 12 artists and the seller
+    let settlements: Page<Settlement> = market
+        .view(&worker, "get_settlements", b"{}".to_vec())
+        .await?
+        .json()?;
+    assert_eq!(settlements.items.len(), 1);
+    let settlement = &settlements.items[0];
+    assert_eq!(settlement.transfers.len(), 13 - SETTLEMENT_BATCH_SIZE);
+
+    let mut remaining = settlement.transfers.len();
+    while remaining > 0 {
+        let outcome = user1
+            .call(&worker, market.id().clone(), "settle")
+            .args_json(serde_json::json!({ "settlement_id": settlement.id }))?
+            .gas(parse_gas!("100 Tgas") as u64)
+            .transact()
+            .await?;
+        let left: U64 = outcome.json()?;
+        assert_eq!(left.0 as usize, remaining.saturating_sub(SETTLEMENT_BATCH_SIZE));
+        remaining = left.0 as usize;
+    }
+    let settlement: Option<Settlement> = market
+        .view(
+            &worker,
+            "get_settlement",
+            serde_json::json!({ "settlement_id": U64(0) })
+                .to_string()
+                .into_bytes(),
+        )
+        .await?
+        .json()?;
+    assert!(settlement.is_none());
+    Ok(())
+}
+
+/*
+    - Panics if the payout length exceeds `MAX_PAYOUT_LEN_LIMIT`
+    - A payout at `MAX_PAYOUT_LEN_LIMIT` fits the gas of the purchase callback
+    - All its transfers are done in batches with `settle`
+*/
+#[tokio::test]
+async fn long_payout_settlement() -> anyhow::Result<()> {
+    let worker = workspaces::sandbox();
+    let owner = worker.root_account();
+    let user1 = create_subaccount(&worker, &owner, "user1").await?;
+    let user2 = create_subaccount(&worker, &owner, "user2").await?;
+    // the royalty receivers don't need accounts, their failed transfers become claimable
+    let artists: Vec<AccountId> = (0..MAX_PAYOUT_LEN_LIMIT - 1)
+        .map(|i| format!("artist{}.{}", i, owner.id()).parse())
+        .collect::<Result<_, _>>()?;
+    let royalty = artists.iter().map(|artist| (artist, 50)).collect();
+    let nft = init_nft_reference(&worker, owner.id(), royalty).await?;
+    let market = init_market(&worker, worker.root_account().id(), vec![nft.id()]).await?;
+
+    let outcome = owner
+        .call(&worker, market.id().clone(), "set_max_payout_len")
+        .args_json(serde_json::json!({ "max_payout_len": MAX_PAYOUT_LEN_LIMIT + 1 }))?
+        .transact()
+        .await?;
+    check_outcome_fail(outcome.status, "The payout length should be from 1 to").await;
+    let outcome = owner
+        .call(&worker, market.id().clone(), "set_max_payout_len")
+        .args_json(serde_json::json!({ "max_payout_len": MAX_PAYOUT_LEN_LIMIT }))?
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+    let outcome = owner
+        .call(&worker, market.id().clone(), "set_payout_mode")
+        .args_json(serde_json::json!({
+            "nft_contract_id": nft.id(),
+            "payout_mode": PayoutMode::Standard,
+        }))?
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+
+    let token_id = "0".to_string();
+    owner
+        .call(&worker, nft.id().clone(), "nft_mint")
+        .args_json(serde_json::json!({
+            "token_id": token_id,
+            "receiver_id": user1.id(),
+            "token_metadata": { "title": "some title" },
+        }))?
+        .deposit(parse_near!("0.01 N"))
+        .transact()
+        .await?;
+    deposit(&worker, market.id().clone(), &user1).await;
+    let sale_conditions = HashMap::from([("near".parse().unwrap(), U128(100000))]);
+    let outcome = user1
+        .call(&worker, nft.id().clone(), "nft_approve")
+        .args_json(serde_json::json!({
+            "token_id": token_id,
+            "account_id": market.id(),
+            "msg": serde_json::json!(ArgsKind::Sale(SaleArgs {
+                sale_conditions: sale_conditions.clone(),
+                token_type: None,
+                start: None,
+                end: None,
+                origins: None,
+                allowed_buyers: None,
+                reserved_buyer: None,
+            })).to_string()
+        }))?
+        .deposit(parse_near!("1 N"))
+        .gas(parse_gas!("200 Tgas") as u64)
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+
+    let price = price_with_fees(&worker, &market, sale_conditions).await?;
+    buy(
+        &worker,
+        nft.id().clone(),
+        market.id().clone(),
+        &user2,
+        token_id.clone(),
+        price,
+    )
+    .await;
+
+    let token: serde_json::Value = nft
+        .view(
+            &worker,
+            "nft_token",
+            serde_json::json!({ "token_id": token_id })
+                .to_string()
+                .into_bytes(),
+        )
+        .await?
+        .json()?;
+    assert_eq!(token["owner_id"].as_str(), Some(user2.id().as_ref()));
+
+    // the artists and the seller
+    let settlements: Page<Settlement> = market
+        .view(&worker, "get_settlements", b"{}".to_vec())
+        .await?
+        .json()?;
+    assert_eq!(settlements.items.len(), 1);
+    let settlement = &settlements.items[0];
+    assert_eq!(
+        settlement.transfers.len(),
+        MAX_PAYOUT_LEN_LIMIT as usize - SETTLEMENT_BATCH_SIZE
+    );
+
+    let mut remaining = settlement.transfers.len();
+    while remaining > 0 {
+        let outcome = user1
+            .call(&worker, market.id().clone(), "settle")
+            .args_json(serde_json::json!({ "settlement_id": settlement.id }))?
+            .gas(parse_gas!("100 Tgas") as u64)
+            .transact()
+            .await?;
+        let left: U64 = outcome.json()?;
+        assert_eq!(left.0 as usize, remaining.saturating_sub(SETTLEMENT_BATCH_SIZE));
+        remaining = left.0 as usize;
+    }
+    Ok(())
+}
+
+/*
+    - A failed transfer is credited to the claimable balance of the receiver
+    - Can't withdraw more than the claimable balance
+*/
+#[tokio::test]
+async fn claimable_balance() -> anyhow::Result<()> {
+    let worker = workspaces::sandbox();
+    let owner = worker.root_account();
+    let user1 = create_subaccount(&worker, &owner, "user1").await?;
+    let user2 = create_subaccount(&worker, &owner, "user2").await?;
+    // the account doesn't exist, so NEAR can't be transferred to it
+    let ghost: AccountId = format!("ghost.{}", owner.id()).parse()?;
+    let nft = init_nft_reference(&worker, owner.id(), HashMap::from([(&ghost, 1000)])).await?;
+    let market = init_market(&worker, worker.root_account().id(), vec![nft.id()]).await?;
+    let outcome = owner
+        .call(&worker, market.id().clone(), "set_payout_mode")
+        .args_json(serde_json::json!({
+            "nft_contract_id": nft.id(),
+            "payout_mode": PayoutMode::Standard,
+        }))?
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+
+    let token_id = "0".to_string();
+    owner
+        .call(&worker, nft.id().clone(), "nft_mint")
+        .args_json(serde_json::json!({
+            "token_id": token_id,
+            "receiver_id": user1.id(),
+            "token_metadata": { "title": "some title" },
+        }))?
+        .deposit(parse_near!("0.01 N"))
+        .transact()
+        .await?;
+    deposit(&worker, market.id().clone(), &user1).await;
+    let sale_conditions = HashMap::from([("near".parse().unwrap(), U128(10000))]);
+    let outcome = user1
+        .call(&worker, nft.id().clone(), "nft_approve")
+        .args_json(serde_json::json!({
+            "token_id": token_id,
+            "account_id": market.id(),
+            "msg": serde_json::json!(ArgsKind::Sale(SaleArgs {
+                sale_conditions: sale_conditions.clone(),
+                token_type: None,
+                start: None,
+                end: None,
+                origins: None,
+                allowed_buyers: None,
+                reserved_buyer: None,
+            })).to_string()
+        }))?
+        .deposit(parse_near!("1 N"))
+        .gas(parse_gas!("200 Tgas") as u64)
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+
+    let price = price_with_fees(&worker, &market, sale_conditions).await?;
+    buy(
+        &worker,
+        nft.id().clone(),
+        market.id().clone(),
+        &user2,
+        token_id.clone(),
+        price,
+    )
+    .await;
+
+    // 10% of the price without the protocol fees
+    let claimable: HashMap<String, U128> = market
+        .view(
+            &worker,
+            "get_claimable",
+            serde_json::json!({ "account_id": ghost })
+                .to_string()
+                .into_bytes(),
+        )
+        .await?
+        .json()?;
+    assert_eq!(claimable, HashMap::from([("near".to_string(), U128(970))]));
+
+    let outcome = user1
+        .call(&worker, market.id().clone(), "withdraw")
+        .args_json(serde_json::json!({
+            "ft_token_id": "near",
+            "amount": U128(1),
+        }))?
+        .deposit(1)
+        .transact()
+        .await?;
+    check_outcome_fail(outcome.status, "Not enough claimable balance").await;
+    Ok(())
+}
+
+/*
+    - The sale is locked while the NFT contract is called
+    - If the transfer fails, the sale is unlocked and keeps its bids
+    - The trade is not recorded
+*/
+#[tokio::test]
+async fn failed_purchase_restores_sale() -> anyhow::Result<()> {
+    let worker = workspaces::sandbox();
+    let owner = worker.root_account();
+    let user1 = create_subaccount(&worker, &owner, "user1").await?;
+    let user2 = create_subaccount(&worker, &owner, "user2").await?;
+    let user3 = create_subaccount(&worker, &owner, "user3").await?;
+    let nft = init_nft_failing(&worker, owner.id()).await?;
+    let market = init_market(&worker, worker.root_account().id(), vec![nft.id()]).await?;
+
+    let token_id = "0".to_string();
+    owner
+        .call(&worker, nft.id().clone(), "nft_mint")
+        .args_json(serde_json::json!({
+            "token_id": token_id,
+            "receiver_id": user1.id(),
+            "token_metadata": { "title": "some title" },
+        }))?
+        .deposit(parse_near!("0.01 N"))
+        .transact()
+        .await?;
+    deposit(&worker, market.id().clone(), &user1).await;
+    let sale_conditions = HashMap::from([("near".parse().unwrap(), U128(10000))]);
+    let outcome = user1
+        .call(&worker, nft.id().clone(), "nft_approve")
+        .args_json(serde_json::json!({
+            "token_id": token_id,
+            "account_id": market.id(),
+            "msg": serde_json::json!(ArgsKind::Sale(SaleArgs {
+                sale_conditions: sale_conditions.clone(),
+                token_type: None,
+                start: None,
+                end: None,
+                origins: None,
+                allowed_buyers: None,
+                reserved_buyer: None,
+            })).to_string()
+        }))?
+        .deposit(parse_near!("1 N"))
+        .gas(parse_gas!("200 Tgas") as u64)
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+
+    // a bid which should survive the failed purchase
+    offer(
+        &worker,
+        nft.id().clone(),
+        market.id().clone(),
+        &user3,
+        token_id.clone(),
+        U128(5000),
+    )
+    .await;
+    let price = price_with_fees(&worker, &market, sale_conditions).await?;
+    buy(
+        &worker,
+        nft.id().clone(),
+        market.id().clone(),
+        &user2,
+        token_id.clone(),
+        price,
+    )
+    .await;
+
+    let token: serde_json::Value = nft
+        .view(
+            &worker,
+            "nft_token",
+            serde_json::json!({ "token_id": token_id })
+                .to_string()
+                .into_bytes(),
+        )
+        .await?
+        .json()?;
+    assert_eq!(token["owner_id"].as_str(), Some(user1.id().as_ref()));
+
+    let sale: Option<SaleJson> = market
+        .view(
+            &worker,
+            "get_sale",
+            serde_json::json!({ "nft_contract_id": nft.id(), "token_id": token_id })
+                .to_string()
+                .into_bytes(),
+        )
+        .await?
+        .json()?;
+    let sale = sale.expect("The sale should be restored");
+    assert!(!sale.locked);
+    let bids: Vec<_> = sale.bids.values().flatten().collect();
+    assert_eq!(bids.len(), 1);
+    assert_eq!(bids[0].owner_id.as_str(), user3.id().as_ref());
+
+    let trades: Page<Trade> = market
+        .view(
+            &worker,
+            "get_trades_by_token",
+            serde_json::json!({ "nft_contract_id": nft.id(), "token_id": token_id })
+                .to_string()
+                .into_bytes(),
+        )
+        .await?
+        .json()?;
+    assert!(trades.items.is_empty());
+    Ok(())
+}
+
+/*
+    - The royalty of the token is unknown before `cache_royalty`
+    - `cache_royalty` stores the royalty of `nft_payout` without the owner share
+    - The quote matches the fees and the royalty paid by the purchase
+*/
+#[tokio::test]
+async fn purchase_quote() -> anyhow::Result<()> {
+    let worker = workspaces::sandbox();
+    let owner = worker.root_account();
+    let user1 = create_subaccount(&worker, &owner, "user1").await?;
+    let user2 = create_subaccount(&worker, &owner, "user2").await?;
+    let artist = create_subaccount(&worker, &owner, "artist").await?;
+    let origin = create_subaccount(&worker, &owner, "origin").await?;
+    let nft = init_nft_reference(&worker, owner.id(), HashMap::from([(artist.id(), 1000)])).await?;
+    let market = init_market(&worker, worker.root_account().id(), vec![nft.id()]).await?;
+    owner
+        .call(&worker, market.id().clone(), "register_origin")
+        .args_json(serde_json::json!({ "origin_id": origin.id(), "max_fee": 100 }))?
+        .transact()
+        .await?;
+    owner
+        .call(&worker, market.id().clone(), "set_payout_mode")
+        .args_json(serde_json::json!({
+            "nft_contract_id": nft.id(),
+            "payout_mode": PayoutMode::Standard,
+        }))?
+        .transact()
+        .await?;
+
+    let token_id = "0".to_string();
+    owner
+        .call(&worker, nft.id().clone(), "nft_mint")
+        .args_json(serde_json::json!({
+            "token_id": token_id,
+            "receiver_id": user1.id(),
+            "token_metadata": { "title": "some title" },
+        }))?
+        .deposit(parse_near!("0.01 N"))
+        .transact()
+        .await?;
+    deposit(&worker, market.id().clone(), &user1).await;
+    let sale_conditions = HashMap::from([("near".parse().unwrap(), U128(10000))]);
+    let outcome = user1
+        .call(&worker, nft.id().clone(), "nft_approve")
+        .args_json(serde_json::json!({
+            "token_id": token_id,
+            "account_id": market.id(),
+            "msg": serde_json::json!(ArgsKind::Sale(SaleArgs {
+                sale_conditions: sale_conditions.clone(),
+                token_type: None,
+                start: None,
+                end: None,
+                origins: Some(HashMap::from([(origin.id().as_ref().parse().unwrap(), 100)])),
+                allowed_buyers: None,
+                reserved_buyer: None,
+            })).to_string()
+        }))?
+        .deposit(parse_near!("1 N"))
+        .gas(parse_gas!("200 Tgas") as u64)
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+
+    let quote_args = serde_json::json!({
+        "nft_contract_id": nft.id(),
+        "token_id": token_id,
+        "ft_token_id": "near",
+    })
+    .to_string()
+    .into_bytes();
+    let quote: PurchaseQuote = market
+        .view(&worker, "get_purchase_quote", quote_args.clone())
+        .await?
+        .json()?;
+    assert!(!quote.royalty_cached);
+    assert!(quote.royalty.is_empty());
+
+    let outcome = user2
+        .call(&worker, market.id().clone(), "cache_royalty")
+        .args_json(serde_json::json!({
+            "nft_contract_id": nft.id(),
+            "token_id": token_id,
+        }))?
+        .gas(parse_gas!("100 Tgas") as u64)
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+    let royalty: Option<Royalty> = market
+        .view(
+            &worker,
+            "get_cached_royalty",
+            serde_json::json!({ "nft_contract_id": nft.id(), "token_id": token_id })
+                .to_string()
+                .into_bytes(),
+        )
+        .await?
+        .json()?;
+    assert_eq!(
+        royalty,
+        Some(HashMap::from([(artist.id().as_ref().parse().unwrap(), 1000)]))
+    );
+
+    let quote: PurchaseQuote = market
+        .view(&worker, "get_purchase_quote", quote_args)
+        .await?
+        .json()?;
+    assert!(quote.royalty_cached);
+    assert_eq!(quote.total, U128(10300));
+    assert_eq!(quote.protocol_fee, U128(600));
+    assert_eq!(
+        quote.origins,
+        HashMap::from([(origin.id().as_ref().parse().unwrap(), U128(100))])
+    );
+    assert_eq!(
+        quote.royalty,
+        HashMap::from([(artist.id().as_ref().parse().unwrap(), U128(960))])
+    );
+    assert_eq!(quote.seller, U128(8640));
+
+    let price = price_with_fees(&worker, &market, sale_conditions).await?;
+    assert_eq!(price, quote.total);
+    buy(
+        &worker,
+        nft.id().clone(),
+        market.id().clone(),
+        &user2,
+        token_id.clone(),
+        price,
+    )
+    .await;
+    let trades: Page<Trade> = market
+        .view(
+            &worker,
+            "get_trades_by_token",
+            serde_json::json!({ "nft_contract_id": nft.id(), "token_id": token_id })
+                .to_string()
+                .into_bytes(),
+        )
+        .await?
+        .json()?;
+    let trade = &trades.items[0];
+    assert_eq!(
+        trade.fees.0,
+        quote.protocol_fee.0 + quote.origins.values().map(|amount| amount.0).sum::<u128>()
+    );
+    assert_eq!(trade.royalties, U128(960));
+    Ok(())
+}
@@ -0,0 +1,145 @@
+use near_units::{parse_gas, parse_near};
+use nft_bid_market::{ArgsKind, SwapArgs};
+use nft_contract::common::U64;
+
+use crate::utils::{
+    check_outcome_success, create_series, create_subaccount, deposit, init_market, init_nft,
+    mint_token, nft_token,
+};
+
+/*
+    - The proposer approves the offered tokens and escrows NEAR
+    - The owner of the wanted token accepts the swap by approving it with `ArgsKind::Swap`
+    - The tokens are exchanged and the swap is removed
+    - If a token of the proposer can't be transferred, the tokens are returned and the swap is removed
+*/
+#[tokio::test]
+async fn swap_positive() -> anyhow::Result<()> {
+    let worker = workspaces::sandbox();
+    let owner = worker.root_account();
+    let nft = init_nft(&worker, owner.id()).await?;
+    let market = init_market(&worker, worker.root_account().id(), vec![nft.id()]).await?;
+    let user1 = create_subaccount(&worker, &owner, "user1").await?;
+    let user2 = create_subaccount(&worker, &owner, "user2").await?;
+    let series = create_series(&worker, nft.id().clone(), &user1, owner.id().clone()).await?;
+    let token_a = mint_token(&worker, nft.id().clone(), &user1, user1.id(), &series).await?;
+    let token_b = mint_token(&worker, nft.id().clone(), &user1, user2.id(), &series).await?;
+    deposit(&worker, market.id().clone(), &user1).await;
+
+    let outcome = user1
+        .call(&worker, nft.id().clone(), "nft_approve")
+        .args_json(serde_json::json!({
+            "token_id": token_a,
+            "account_id": market.id(),
+        }))?
+        .deposit(parse_near!("0.01 N"))
+        .gas(parse_gas!("200 Tgas") as u64)
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+    let approval_id = *nft_token(&worker, &nft, &token_a)
+        .await?
+        .approved_account_ids
+        .unwrap()
+        .get(&market.id().as_ref().parse().unwrap())
+        .unwrap();
+    let swap_id: U64 = user1
+        .call(&worker, market.id().clone(), "propose_swap")
+        .args_json(serde_json::json!({
+            "offered": [{
+                "nft_contract_id": nft.id(),
+                "token_id": token_a,
+                "approval_id": approval_id,
+            }],
+            "wanted_nft_contract_id": nft.id(),
+            "wanted_token_id": token_b,
+        }))?
+        .deposit(parse_near!("2 N"))
+        .transact()
+        .await?
+        .json()?;
+    let swaps: Vec<serde_json::Value> = market
+        .view(
+            &worker,
+            "get_swaps_for_token",
+            serde_json::json!({ "nft_contract_id": nft.id(), "token_id": token_b })
+                .to_string()
+                .into_bytes(),
+        )
+        .await?
+        .json()?;
+    assert_eq!(swaps.len(), 1);
+
+    let outcome = user2
+        .call(&worker, nft.id().clone(), "nft_approve")
+        .args_json(serde_json::json!({
+            "token_id": token_b,
+            "account_id": market.id(),
+            "msg": serde_json::json!(ArgsKind::Swap(SwapArgs { swap_id })).to_string(),
+        }))?
+        .deposit(parse_near!("0.01 N"))
+        .gas(parse_gas!("300 Tgas") as u64)
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+    let token = nft_token(&worker, &nft, &token_a).await?;
+    assert_eq!(token.owner_id.as_str(), user2.id().as_ref());
+    let token = nft_token(&worker, &nft, &token_b).await?;
+    assert_eq!(token.owner_id.as_str(), user1.id().as_ref());
+    let swap: Option<serde_json::Value> = market
+        .view(
+            &worker,
+            "get_swap",
+            serde_json::json!({ "swap_id": swap_id })
+                .to_string()
+                .into_bytes(),
+        )
+        .await?
+        .json()?;
+    assert!(swap.is_none());
+
+    // the approval of the offered token is not valid, the swap is rolled back
+    let swap_id: U64 = user1
+        .call(&worker, market.id().clone(), "propose_swap")
+        .args_json(serde_json::json!({
+            "offered": [{
+                "nft_contract_id": nft.id(),
+                "token_id": token_b,
+                "approval_id": 999,
+            }],
+            "wanted_nft_contract_id": nft.id(),
+            "wanted_token_id": token_a,
+        }))?
+        .deposit(parse_near!("1 N"))
+        .transact()
+        .await?
+        .json()?;
+    let outcome = user2
+        .call(&worker, nft.id().clone(), "nft_approve")
+        .args_json(serde_json::json!({
+            "token_id": token_a,
+            "account_id": market.id(),
+            "msg": serde_json::json!(ArgsKind::Swap(SwapArgs { swap_id })).to_string(),
+        }))?
+        .deposit(parse_near!("0.01 N"))
+        .gas(parse_gas!("300 Tgas") as u64)
+        .transact()
+        .await?;
+    check_outcome_success(outcome.status).await;
+    let token = nft_token(&worker, &nft, &token_a).await?;
+    assert_eq!(token.owner_id.as_str(), user2.id().as_ref());
+    let token = nft_token(&worker, &nft, &token_b).await?;
+    assert_eq!(token.owner_id.as_str(), user1.id().as_ref());
+    let swap: Option<serde_json::Value> = market
+        .view(
+            &worker,
+            "get_swap",
+            serde_json::json!({ "swap_id": swap_id })
+                .to_string()
+                .into_bytes(),
+        )
+        .await?
+        .json()?;
+    assert!(swap.is_none());
+    Ok(())
+}
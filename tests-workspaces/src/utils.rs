@@ -14,6 +14,8 @@ use near_primitives::views::FinalExecutionStatus;
 
 const NFT_WASM_FILEPATH: &str = "../res/nft_contract.wasm";
 const MARKET_WASM_FILEPATH: &str = "../res/nft_bid_market.wasm";
+const NFT_REFERENCE_WASM_FILEPATH: &str = "../res/nft_reference.wasm";
+const NFT_FAILING_WASM_FILEPATH: &str = "../res/nft_failing.wasm";
 
 pub async fn init_nft(
     worker: &workspaces::Worker<impl DevNetwork>,
@@ -36,6 +38,50 @@ pub async fn init_nft(
     Ok(contract)
 }
 
+// A plain NEP-171 contract with standard NEP-199 payouts and the same royalty for every token
+pub async fn init_nft_reference(
+    worker: &workspaces::Worker<impl DevNetwork>,
+    root_id: &workspaces::AccountId,
+    royalty: HashMap<&workspaces::AccountId, u32>,
+) -> anyhow::Result<workspaces::Contract> {
+    let wasm = std::fs::read(NFT_REFERENCE_WASM_FILEPATH)?;
+    let contract = worker.dev_deploy(wasm).await?;
+    let outcome = contract
+        .call(worker, "new")
+        .args_json(serde_json::json!({
+            "owner_id": root_id,
+            "royalty": royalty,
+        }))?
+        .gas(parse_gas!("150 Tgas") as u64)
+        .transact()
+        .await?;
+    match outcome.status {
+        near_primitives::views::FinalExecutionStatus::SuccessValue(_) => (),
+        _ => panic!(),
+    };
+    Ok(contract)
+}
+
+// A NEP-171 contract which fails every `nft_transfer_payout`
+pub async fn init_nft_failing(
+    worker: &workspaces::Worker<impl DevNetwork>,
+    root_id: &workspaces::AccountId,
+) -> anyhow::Result<workspaces::Contract> {
+    let wasm = std::fs::read(NFT_FAILING_WASM_FILEPATH)?;
+    let contract = worker.dev_deploy(wasm).await?;
+    let outcome = contract
+        .call(worker, "new")
+        .args_json(serde_json::json!({ "owner_id": root_id }))?
+        .gas(parse_gas!("150 Tgas") as u64)
+        .transact()
+        .await?;
+    match outcome.status {
+        near_primitives::views::FinalExecutionStatus::SuccessValue(_) => (),
+        _ => panic!(),
+    };
+    Ok(contract)
+}
+
 pub async fn init_market(
     worker: &workspaces::Worker<impl DevNetwork>,
     root_id: &workspaces::AccountId,
@@ -173,6 +219,8 @@ pub async fn nft_approve(
                 start: None,
                 end: None,
                 origins: None,
+                allowed_buyers: None,
+                reserved_buyer: None,
             })).to_string()
         }))
         .unwrap()
@@ -225,6 +273,27 @@ pub async fn offer(
         .unwrap();
 }
 
+pub async fn buy(
+    worker: &Worker<impl DevNetwork>,
+    nft: workspaces::AccountId,
+    market: workspaces::AccountId,
+    user: &Account,
+    token: String,
+    price: U128,
+) {
+    user.call(worker, market.clone(), "buy")
+        .args_json(serde_json::json!({
+            "nft_contract_id": nft,
+            "token_id": token,
+        }))
+        .unwrap()
+        .deposit(price.into())
+        .gas(parse_gas!("300 Tgas") as u64)
+        .transact()
+        .await
+        .unwrap();
+}
+
 pub async fn create_series_raw(
     worker: &Worker<impl DevNetwork>,
     nft: workspaces::AccountId,
@@ -347,3 +416,21 @@ pub async fn nft_transfer_payout_helper(
         .json()
         .unwrap()
 }
+
+pub async fn nft_token(
+    worker: &Worker<impl DevNetwork>,
+    nft: &Contract,
+    token_id: &str,
+) -> anyhow::Result<Token> {
+    let token: Option<Token> = nft
+        .view(
+            worker,
+            "nft_token",
+            serde_json::json!({ "token_id": token_id })
+                .to_string()
+                .into_bytes(),
+        )
+        .await?
+        .json()?;
+    Ok(token.expect("The token should exist"))
+}